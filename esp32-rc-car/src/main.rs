@@ -5,7 +5,11 @@ use esp_idf_hal::ledc::{config::TimerConfig, LedcDriver, LedcTimerDriver, Resolu
 use esp_idf_hal::peripherals::Peripherals;
 use esp_idf_hal::prelude::*;
 use esp_idf_svc::log::EspLogger;
-use esp_idf_svc::{eventloop::EspSystemEventLoop, nvs::EspDefaultNvsPartition, wifi::EspWifi};
+use esp_idf_svc::{
+    eventloop::EspSystemEventLoop,
+    nvs::{EspDefaultNvs, EspDefaultNvsPartition},
+    wifi::EspWifi,
+};
 use esp_idf_sys::*;
 
 mod esp32_car;
@@ -55,8 +59,8 @@ fn main() -> anyhow::Result<()> {
     // wifi
     //
 
-    let mut wifi_driver =
-        EspWifi::new(peripherals.modem, sys_loop, Some(nvs)).context("failed EspWifi::new()")?;
+    let mut wifi_driver = EspWifi::new(peripherals.modem, sys_loop, Some(nvs.clone()))
+        .context("failed EspWifi::new()")?;
 
     wifi_driver
         .set_configuration(&Configuration::AccessPoint(AccessPointConfiguration {
@@ -103,22 +107,61 @@ fn main() -> anyhow::Result<()> {
     let (incoming_input_message_sender, incoming_input_message_receiver) =
         std::sync::mpsc::channel();
 
+    // Server converts UDP datagrams to InputMessages (and TelemetryMessages to UDP datagrams)
+    let mut server = rc_messaging::transport::Server::new(
+        format!("{}:{}", ip_info.ip, 13337)
+            .parse()
+            .context("failed rc_messaging::transport::Server::new()")?,
+        incoming_input_message_sender,
+    )?;
+
+    // what each ControlMessage channel means to us (laid out like an InputMessage)
+    server.set_vehicle_descriptor(rc_messaging::serialization::VehicleDescriptor {
+        kind: rc_messaging::serialization::VehicleKind::Car,
+        axes: vec!["throttle".to_string(), "steering".to_string()],
+        buttons: vec![
+            "throttle_limit_up".to_string(),
+            "throttle_limit_down".to_string(),
+            "steering_trim_left".to_string(),
+            "steering_trim_right".to_string(),
+        ],
+    });
+
+    let outgoing_telemetry_message_sender = server.get_outgoing_telemetry_message_sender();
+
+    // switches between the drive modes below
+    let (incoming_drive_mode_message_sender, incoming_drive_mode_message_receiver) =
+        std::sync::mpsc::channel();
+    server.set_incoming_drive_mode_message_sender(incoming_drive_mode_message_sender);
+
     // run a thread to handle Server
     std::thread::Builder::new()
         .stack_size(16384)
         .spawn(move || -> anyhow::Result<()> {
-            // Server converts UDP datagrams to InputMessages
-            let server = rc_messaging::transport::Server::new(
-                format!("{}:{}", ip_info.ip, 13337)
-                    .parse()
-                    .context("failed rc_messaging::transport::Server::new()")?,
-                incoming_input_message_sender,
-            )?;
-
             server.run()?;
             Ok(())
         })?;
 
+    // let controllers on the LAN find us
+    let announcer = rc_messaging::discovery::Announcer::new(
+        rc_messaging::serialization::BeaconMessage {
+            name: "esp32-rc-car".to_string(),
+            kind: rc_messaging::serialization::VehicleKind::Car,
+            protocol_version: rc_messaging::envelope::PROTOCOL_VERSION,
+            control_port: 13337,
+        },
+        rc_messaging::discovery::get_broadcast_address(),
+    )?;
+
+    // run a thread to handle Announcer
+    std::thread::Builder::new()
+        .stack_size(8192)
+        .spawn(move || -> anyhow::Result<()> {
+            announcer.run()?;
+
+            Ok(())
+        })?;
+
     let throttle_freq_hz: u32 = PWM_FREQ_HZ;
     let throttle_max_duty: u32 = throttle_driver.get_max_duty();
     let steering_freq_hz: u32 = PWM_FREQ_HZ;
@@ -150,6 +193,60 @@ fn main() -> anyhow::Result<()> {
                 0.10,
                 0.0,
             );
+            vehicle.set_outgoing_telemetry_message_sender(outgoing_telemetry_message_sender);
+
+            // the starting throttle limits and steering offset only stand until some are saved
+            // NVS has no floats, so they're kept in a blob (which is one write to save them all)
+            vehicle.set_settings_store(Box::new(rc_vehicle::settings::BlobSettingsStore::new(
+                EspDefaultNvs::new(nvs, "rc-vehicle", true)?,
+                "settings",
+                |nvs, key, buf| Ok(nvs.get_raw(key, buf)?.map(|blob| blob.len())),
+                |nvs, key, blob| {
+                    nvs.set_raw(key, blob)?;
+                    Ok(())
+                },
+            )));
+
+            // ride out a short dropout rather than lurching to a stop, then coast
+            vehicle.set_failsafe_policy(rc_vehicle::failsafe::FailsafePolicy::new(
+                rc_vehicle::failsafe::DEFAULT_TIMEOUT,
+                vec![
+                    rc_vehicle::failsafe::FailsafeStage {
+                        after: std::time::Duration::ZERO,
+                        action: rc_vehicle::failsafe::FailsafeAction::Hold,
+                    },
+                    rc_vehicle::failsafe::FailsafeStage {
+                        after: std::time::Duration::from_millis(300),
+                        action: rc_vehicle::failsafe::FailsafeAction::Send(
+                            rc_vehicle::failsafe::get_safe_input_message(),
+                        ),
+                    },
+                ],
+            ));
+
+            // softer around the middle of the steering, and no wheelspin from a stab at the throttle
+            let mut input_shaper = rc_vehicle::shaping::InputShaper::new();
+            input_shaper.set_pipeline(
+                rc_vehicle::shaping::Axis::Steering,
+                rc_vehicle::shaping::Pipeline::new(vec![Box::new(rc_vehicle::shaping::Expo(0.3))]),
+            );
+            input_shaper.set_pipeline(
+                rc_vehicle::shaping::Axis::Throttle,
+                rc_vehicle::shaping::Pipeline::new(vec![Box::new(rc_vehicle::shaping::Slew::new(
+                    4.0,
+                ))]),
+            );
+            vehicle.set_input_shaper(input_shaper);
+
+            // the ESC brakes on the first reverse after going forward, and reverse is twitchy
+            vehicle.set_reverse_policy(rc_vehicle::vehicle::ReversePolicy {
+                speed_cap: 0.5,
+                brake_delay: Some(std::time::Duration::from_millis(250)),
+            });
+
+            // starts in beginner, so whoever picks up the controller has to ask for more
+            vehicle.set_drive_modes(rc_vehicle::drive_mode::get_default_drive_modes());
+            vehicle.set_incoming_drive_mode_message_receiver(incoming_drive_mode_message_receiver);
 
             vehicle.run()?;
 
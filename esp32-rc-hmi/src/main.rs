@@ -24,6 +24,11 @@ fn main() -> anyhow::Result<()> {
 
     let mut led = PinDriver::output(peripherals.pins.gpio2)?;
 
+    // the dev board's BOOT button (pulled up, so low when pressed) is the handbrake, which arms the
+    // vehicle when held with both pots centred
+    let mut arm_button = PinDriver::input(peripherals.pins.gpio0)?;
+    arm_button.set_pull(Pull::Up)?;
+
     let mut adc = AdcDriver::new(peripherals.adc1, &Config::new().calibration(true))?;
     let mut steering_adc_pin: AdcChannelDriver<{ attenuation::DB_11 }, _> =
         AdcChannelDriver::new(peripherals.pins.gpio36)?;
@@ -70,14 +75,40 @@ fn main() -> anyhow::Result<()> {
     // vehicle control
     //
 
+    // listen for vehicles announcing themselves, falling back to the car's AP address
+    let discovered_vehicles = rc_messaging::discovery::discover(
+        format!("{}:{}", "0.0.0.0", rc_messaging::discovery::DISCOVERY_PORT).parse()?,
+        std::time::Duration::from_secs(3),
+        None,
+    )?;
+    println!("discovered_vehicles={:?}", discovered_vehicles);
+
+    let send_address: std::net::SocketAddr = match discovered_vehicles
+        .into_iter()
+        .find(|discovered_vehicle| discovered_vehicle.is_compatible())
+    {
+        Some(discovered_vehicle) => discovered_vehicle.control_address,
+        None => format!("{}:{}", "192.168.71.1", 13337).parse()?,
+    };
+    println!("send_address={:?}", send_address);
+
     // Client converts InputMessages to UDP datagrams
-    let client =
-        rc_messaging::transport::Client::new(format!("{}:{}", "192.168.71.1", 13337).parse()?)?;
+    let mut client = rc_messaging::transport::Client::new(send_address)?;
+
+    // the tightest encoding for the airtime; the vehicle replies in kind
+    client.set_codec(rc_messaging::codec::Codec::Postcard);
 
     let outgoing_input_message_sender: std::sync::mpsc::Sender<
         rc_messaging::serialization::InputMessage,
     > = client.get_outgoing_input_message_sender();
 
+    let latency_stats_reader = client.get_latency_stats_reader();
+
+    // vehicles start disarmed and telemetry says whether they still are
+    let (incoming_telemetry_message_sender, incoming_telemetry_message_receiver) =
+        std::sync::mpsc::channel::<rc_messaging::serialization::TelemetryMessage>();
+    client.set_incoming_telemetry_message_sender(incoming_telemetry_message_sender);
+
     // run a thread to handle Client
     std::thread::Builder::new()
         .stack_size(32768)
@@ -92,7 +123,37 @@ fn main() -> anyhow::Result<()> {
     std::thread::Builder::new()
         .stack_size(32768)
         .spawn(move || -> anyhow::Result<()> {
+            let mut armed: Option<bool> = None;
+
+            // calibration for these particular pots, applied after the rough translate and scale
+            let mut input_shaper = rc_vehicle::shaping::InputShaper::new();
+            input_shaper.set_pipeline(
+                rc_vehicle::shaping::Axis::Throttle,
+                rc_vehicle::shaping::Pipeline::new(vec![
+                    Box::new(rc_vehicle::shaping::Invert),
+                    Box::new(rc_vehicle::shaping::Trim(0.0075)),
+                    Box::new(rc_vehicle::shaping::Deadzone(0.01)),
+                ]),
+            );
+            input_shaper.set_pipeline(
+                rc_vehicle::shaping::Axis::Steering,
+                rc_vehicle::shaping::Pipeline::new(vec![
+                    Box::new(rc_vehicle::shaping::Trim(-0.129)),
+                    Box::new(rc_vehicle::shaping::Deadzone(0.01)),
+                ]),
+            );
+
             loop {
+                if let Some(telemetry_message) =
+                    incoming_telemetry_message_receiver.try_iter().last()
+                {
+                    // only ever shown, never acted on (telemetry can be lost or late)
+                    if armed != Some(telemetry_message.armed) {
+                        armed = Some(telemetry_message.armed);
+                        println!("vehicle armed={:?}", telemetry_message.armed);
+                    }
+                }
+
                 // 142 (forward) -> 1650 (neutral) -> 2580 (reverse)
                 let raw_throttle = adc.read(&mut throttle_adc_pin)?;
 
@@ -100,33 +161,27 @@ fn main() -> anyhow::Result<()> {
                 let raw_steering = adc.read(&mut steering_adc_pin)?;
 
                 let mut throttle: f32 = raw_throttle.into();
-                throttle = -(((throttle - 142.0) / (3134.0 - 142.0)) * 2.0 - 1.0); // rough translate and scale
-                throttle += 0.0075; // adjust out remaining error
-                if (-0.01..=0.01).contains(&throttle) {
-                    // apply deadzone
-                    throttle = 0.0;
-                }
+                throttle = ((throttle - 142.0) / (3134.0 - 142.0)) * 2.0 - 1.0; // rough translate and scale
 
                 let mut steering: f32 = raw_steering.into();
                 steering = ((steering - 142.0) / (3134.0 - 142.0)) * 2.0 - 1.0; // rough translate and scale
-                steering -= 0.129; // adjust out remaining error
-                if (-0.01..=0.01).contains(&steering) {
-                    // apply deadzone
-                    steering = 0.0;
-                }
 
-                let input_message = rc_messaging::serialization::InputMessage {
+                let mut input_message = rc_messaging::serialization::InputMessage {
                     throttle,
                     steering,
                     throttle_left: 0.0,
                     throttle_right: 0.0,
+                    steering_left: 0.0,
+                    steering_right: 0.0,
                     mode_up: false,
                     mode_down: false,
                     mode_left: false,
                     mode_right: false,
-                    handbrake: false,
+                    handbrake: arm_button.is_low(),
                 };
 
+                input_shaper.shape(&mut input_message, std::time::Instant::now());
+
                 println!(
                     "throttle: {:?}, steering: {:?}, {:?}",
                     raw_throttle, raw_steering, input_message
@@ -138,12 +193,21 @@ fn main() -> anyhow::Result<()> {
             }
         })?;
 
-    // main loop just blinks the led
+    // main loop just blinks the led; fast if the link is laggy or lossy
     loop {
+        let latency_stats = latency_stats_reader();
+
+        let blink_interval = if latency_stats.is_degraded() {
+            println!("warning: link degraded; latency_stats={:?}", latency_stats);
+            std::time::Duration::from_millis(50)
+        } else {
+            std::time::Duration::from_millis(200)
+        };
+
         led.set_high()?;
-        std::thread::sleep(std::time::Duration::from_millis(200));
+        std::thread::sleep(blink_interval);
 
         led.set_low()?;
-        std::thread::sleep(std::time::Duration::from_millis(200));
+        std::thread::sleep(blink_interval);
     }
 }
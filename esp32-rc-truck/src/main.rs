@@ -5,7 +5,11 @@ use esp_idf_hal::ledc::{config::TimerConfig, LedcDriver, LedcTimerDriver, Resolu
 use esp_idf_hal::peripherals::Peripherals;
 use esp_idf_hal::prelude::*;
 use esp_idf_svc::log::EspLogger;
-use esp_idf_svc::{eventloop::EspSystemEventLoop, nvs::EspDefaultNvsPartition, wifi::EspWifi};
+use esp_idf_svc::{
+    eventloop::EspSystemEventLoop,
+    nvs::{EspDefaultNvs, EspDefaultNvsPartition},
+    wifi::EspWifi,
+};
 use esp_idf_sys::*;
 
 mod esp32_truck;
@@ -63,7 +67,7 @@ fn main() -> anyhow::Result<()> {
     // wifi
     //
 
-    let mut wifi_driver = EspWifi::new(peripherals.modem, sys_loop, Some(nvs))?;
+    let mut wifi_driver = EspWifi::new(peripherals.modem, sys_loop, Some(nvs.clone()))?;
 
     wifi_driver.set_configuration(&Configuration::AccessPoint(AccessPointConfiguration {
         ssid: "esp32-rc-truck".into(),
@@ -94,21 +98,60 @@ fn main() -> anyhow::Result<()> {
     let (incoming_input_message_sender, incoming_input_message_receiver) =
         std::sync::mpsc::channel();
 
+    // Server converts UDP datagrams to InputMessages (and TelemetryMessages to UDP datagrams)
+    let mut server = rc_messaging::transport::Server::new(
+        format!("{}:{}", ip_info.ip.to_string(), 13337).parse()?,
+        incoming_input_message_sender,
+    )?;
+
+    // what each ControlMessage channel means to us (laid out like an InputMessage)
+    server.set_vehicle_descriptor(rc_messaging::serialization::VehicleDescriptor {
+        kind: rc_messaging::serialization::VehicleKind::Truck,
+        axes: vec![
+            "throttle".to_string(),
+            "steering".to_string(),
+            "".to_string(),
+            "tray".to_string(),
+        ],
+        buttons: vec![
+            "throttle_limit_up".to_string(),
+            "throttle_limit_down".to_string(),
+            "steering_trim_left".to_string(),
+            "steering_trim_right".to_string(),
+        ],
+    });
+
+    let outgoing_telemetry_message_sender = server.get_outgoing_telemetry_message_sender();
+
     // run a thread to handle Server
     std::thread::Builder::new()
         .stack_size(16384)
         .spawn(move || -> anyhow::Result<()> {
-            // Server converts UDP datagrams to InputMessages
-            let server = rc_messaging::transport::Server::new(
-                format!("{}:{}", ip_info.ip.to_string(), 13337).parse()?,
-                incoming_input_message_sender,
-            )?;
-
             server.run()?;
 
             Ok(())
         })?;
 
+    // let controllers on the LAN find us
+    let announcer = rc_messaging::discovery::Announcer::new(
+        rc_messaging::serialization::BeaconMessage {
+            name: "esp32-rc-truck".to_string(),
+            kind: rc_messaging::serialization::VehicleKind::Truck,
+            protocol_version: rc_messaging::envelope::PROTOCOL_VERSION,
+            control_port: 13337,
+        },
+        rc_messaging::discovery::get_broadcast_address(),
+    )?;
+
+    // run a thread to handle Announcer
+    std::thread::Builder::new()
+        .stack_size(8192)
+        .spawn(move || -> anyhow::Result<()> {
+            announcer.run()?;
+
+            Ok(())
+        })?;
+
     let throttle_max_duty: u32 = throttle_driver.get_max_duty();
     let steering_max_duty: u32 = steering_driver.get_max_duty();
     let tray_max_duty: u32 = tray_driver.get_max_duty();
@@ -142,6 +185,19 @@ fn main() -> anyhow::Result<()> {
                 0.20,
                 0.0,
             );
+            vehicle.set_outgoing_telemetry_message_sender(outgoing_telemetry_message_sender);
+
+            // the starting throttle limits and steering offset only stand until some are saved
+            // NVS has no floats, so they're kept in a blob (which is one write to save them all)
+            vehicle.set_settings_store(Box::new(rc_vehicle::settings::BlobSettingsStore::new(
+                EspDefaultNvs::new(nvs, "rc-vehicle", true)?,
+                "settings",
+                |nvs, key, buf| Ok(nvs.get_raw(key, buf)?.map(|blob| blob.len())),
+                |nvs, key, blob| {
+                    nvs.set_raw(key, blob)?;
+                    Ok(())
+                },
+            )));
 
             vehicle.run()?;
 
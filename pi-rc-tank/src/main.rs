@@ -17,19 +17,110 @@ fn main() -> anyhow::Result<()> {
     let (incoming_input_message_sender, incoming_input_message_receiver) =
         std::sync::mpsc::channel();
 
+    // a stick per track unless a single stick has been asked for (e.g. MIXER=arcade, or
+    // MIXER=arcade-skid to never run the inside track backwards)
+    let mixer = match std::env::var("MIXER").as_deref() {
+        Ok("arcade") => Some(rc_vehicle::mixing::ArcadeToTank::default()),
+        Ok("arcade-skid") => Some(rc_vehicle::mixing::ArcadeToTank {
+            pivot_turn: rc_vehicle::mixing::PivotTurn::Skid,
+            ..Default::default()
+        }),
+        Ok(mixer) => anyhow::bail!("unknown MIXER={:?}", mixer),
+        Err(_) => None,
+    };
+
+    // UDP unless something else has been asked for (e.g. TRANSPORT=tcp or
+    // TRANSPORT=serial:/dev/ttyUSB0:115200)
+    let transport = rc_messaging::transport::get_server_transport(
+        &std::env::var("TRANSPORT").unwrap_or("udp".to_string()),
+        format!("{}:{}", "0.0.0.0", 13337).parse()?,
+    )?;
+
+    // drive from a recorded session rather than (as well as) the network, optionally sped up
+    // (e.g. REPLAY=session.bin REPLAY_SPEED=2.0)
+    if let Ok(path) = std::env::var("REPLAY") {
+        let mut player = rc_messaging::recording::Player::open(path)?;
+        if let Ok(speed) = std::env::var("REPLAY_SPEED") {
+            player.set_speed(speed.parse()?);
+        }
+
+        let incoming_input_message_sender = incoming_input_message_sender.clone();
+        std::thread::spawn(move || -> anyhow::Result<()> {
+            player.run(&incoming_input_message_sender)?;
+
+            Ok(())
+        });
+    }
+
+    // Server converts datagrams to InputMessages (and TelemetryMessages to datagrams)
+    let mut server = rc_messaging::transport::Server::new_with_transport(
+        transport,
+        incoming_input_message_sender,
+    );
+
+    // keep a copy of the session for replaying later (e.g. RECORD=session.bin)
+    if let Ok(path) = std::env::var("RECORD") {
+        server.set_recorder(rc_messaging::recording::Recorder::create(path)?);
+    }
+
+    // only accept signed datagrams if a key has been configured
+    if let Ok(pre_shared_key) = std::env::var("PRE_SHARED_KEY") {
+        server.set_pre_shared_key(rc_messaging::auth::PreSharedKey::new(
+            pre_shared_key.as_bytes(),
+        ));
+    }
+
+    // what each ControlMessage channel means to us (laid out like an InputMessage)
+    server.set_vehicle_descriptor(rc_messaging::serialization::VehicleDescriptor {
+        kind: rc_messaging::serialization::VehicleKind::Tank,
+        axes: match mixer {
+            Some(_) => vec!["throttle".to_string(), "steering".to_string()],
+            None => vec![
+                "".to_string(),
+                "".to_string(),
+                "throttle_left".to_string(),
+                "throttle_right".to_string(),
+            ],
+        },
+        buttons: vec![
+            "throttle_limit_up".to_string(),
+            "throttle_limit_down".to_string(),
+        ],
+    });
+
+    let outgoing_telemetry_message_sender = server.get_outgoing_telemetry_message_sender();
+
     // run a thread to handle Server
     std::thread::spawn(move || -> anyhow::Result<()> {
-        // Server converts UDP datagrams to InputMessages
-        let server = rc_messaging::transport::Server::new(
-            format!("{}:{}", "0.0.0.0", 13337).parse()?,
-            incoming_input_message_sender,
-        )?;
-
         server.run()?;
 
         Ok(())
     });
 
+    // let controllers on the LAN find us
+    let mut announcer = rc_messaging::discovery::Announcer::new(
+        rc_messaging::serialization::BeaconMessage {
+            name: std::env::var("VEHICLE_NAME").unwrap_or("pi-rc-tank".to_string()),
+            kind: rc_messaging::serialization::VehicleKind::Tank,
+            protocol_version: rc_messaging::envelope::PROTOCOL_VERSION,
+            control_port: 13337,
+        },
+        rc_messaging::discovery::get_broadcast_address(),
+    )?;
+
+    if let Ok(pre_shared_key) = std::env::var("PRE_SHARED_KEY") {
+        announcer.set_pre_shared_key(rc_messaging::auth::PreSharedKey::new(
+            pre_shared_key.as_bytes(),
+        ));
+    }
+
+    // run a thread to handle Announcer
+    std::thread::spawn(move || -> anyhow::Result<()> {
+        announcer.run()?;
+
+        Ok(())
+    });
+
     // run a thread to handle PiTank -> StringCar -> Vehicle
     std::thread::spawn(move || -> anyhow::Result<()> {
         // PiTank converts set_throttles calls to serial writes
@@ -46,6 +137,49 @@ fn main() -> anyhow::Result<()> {
             0.20,
             0.0,
         );
+        vehicle.set_outgoing_telemetry_message_sender(outgoing_telemetry_message_sender);
+
+        // the starting throttle limits and steering offset only stand until some are saved (e.g.
+        // SETTINGS=/var/lib/pi-rc-tank.settings)
+        vehicle.set_settings_store(Box::new(rc_vehicle::settings::FileSettingsStore::new(
+            std::env::var("SETTINGS").unwrap_or("pi-rc-tank.settings".to_string()),
+        )));
+
+        // the tracks don't turn at all for the first bit of throttle anyway (and the same goes for
+        // the single stick, if it's mixed)
+        let mut input_shaper = rc_vehicle::shaping::InputShaper::new();
+        for axis in [
+            rc_vehicle::shaping::Axis::Throttle,
+            rc_vehicle::shaping::Axis::Steering,
+            rc_vehicle::shaping::Axis::ThrottleLeft,
+            rc_vehicle::shaping::Axis::ThrottleRight,
+        ] {
+            input_shaper.set_pipeline(
+                axis,
+                rc_vehicle::shaping::Pipeline::new(vec![Box::new(rc_vehicle::shaping::Deadzone(
+                    0.05,
+                ))]),
+            );
+        }
+        vehicle.set_input_shaper(input_shaper);
+
+        if let Some(mixer) = mixer {
+            vehicle.set_mixer(Box::new(mixer));
+        }
+
+        // there's no steering to trim, so only the speed limit
+        vehicle.set_bindings(rc_vehicle::bindings::Bindings::new(vec![
+            rc_vehicle::bindings::Binding {
+                button: rc_vehicle::bindings::Button::ModeUp,
+                trigger: rc_vehicle::bindings::Trigger::Press,
+                action: rc_vehicle::bindings::Action::ChangeSpeedLimit(0.10),
+            },
+            rc_vehicle::bindings::Binding {
+                button: rc_vehicle::bindings::Button::ModeDown,
+                trigger: rc_vehicle::bindings::Trigger::Press,
+                action: rc_vehicle::bindings::Action::ChangeSpeedLimit(-0.10),
+            },
+        ]));
 
         vehicle.run()?;
 
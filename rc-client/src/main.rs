@@ -1,6 +1,6 @@
 use std::env;
 use std::net::{SocketAddr, UdpSocket};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use bevy::app::App;
 use bevy::core::CorePlugin;
@@ -19,7 +19,14 @@ use bevy::time::TimePlugin;
 use bevy::winit::WinitPlugin;
 use iyes_loopless::prelude::AppLooplessFixedTimestepExt;
 
-use rc_messaging::serialization::{serialize, InputMessage};
+use rc_messaging::address::Address;
+use rc_messaging::auth::PreSharedKey;
+use rc_messaging::codec::Codec;
+use rc_messaging::discovery::{discover, DISCOVERY_PORT};
+use rc_messaging::envelope::{decode, encode_into, get_timestamp_us, Envelope, Message};
+use rc_messaging::latency::{LatencyTracker, PING_INTERVAL};
+use rc_messaging::serialization::{DriveModeMessage, InputMessage, PingMessage};
+use rc_messaging::session::ClientSession;
 
 pub const TITLE: &str = "car-client";
 pub const BOUNDS: Vec2 = Vec2::new(640.0, 400.0);
@@ -28,18 +35,101 @@ pub const LOCAL_TIME_STEP_NAME: &str = "local_time_step";
 pub const NETWORK_TIME_STEP: f64 = 1.0 / 20.0;
 pub const NETWORK_TIME_STEP_NAME: &str = "network_time_step";
 
+pub const DISCOVERY_DURATION: Duration = Duration::from_secs(2);
+
+// HOST and PORT win if they're set, otherwise we listen for vehicles announcing themselves and
+// take the one named VEHICLE_NAME (or the first one we found)
 pub fn get_socket_addr_from_env() -> SocketAddr {
-    let host = env::var("HOST").unwrap();
-    let port = env::var("PORT").unwrap();
-    let socket_addr: SocketAddr = format!("{}:{}", host, port).parse().unwrap();
+    if let (Ok(host), Ok(port)) = (env::var("HOST"), env::var("PORT")) {
+        return format!("{}:{}", host, port).parse().unwrap();
+    }
+
+    let discovered_vehicles = discover(
+        format!("{}:{}", "0.0.0.0", DISCOVERY_PORT).parse().unwrap(),
+        DISCOVERY_DURATION,
+        get_pre_shared_key_from_env(),
+    )
+    .unwrap();
+
+    for discovered_vehicle in discovered_vehicles.iter() {
+        println!(
+            "discovered_vehicle={:?} is_compatible={:?}",
+            discovered_vehicle,
+            discovered_vehicle.is_compatible()
+        );
+    }
+
+    let vehicle_name = env::var("VEHICLE_NAME").ok();
 
-    socket_addr
+    let discovered_vehicle = discovered_vehicles
+        .into_iter()
+        .filter(|discovered_vehicle| discovered_vehicle.is_compatible())
+        .find(|discovered_vehicle| {
+            vehicle_name
+                .as_ref()
+                .is_none_or(|vehicle_name| discovered_vehicle.name == *vehicle_name)
+        })
+        .expect("no vehicles discovered; set HOST and PORT to connect directly");
+
+    discovered_vehicle.control_address
+}
+
+pub fn get_pre_shared_key_from_env() -> Option<PreSharedKey> {
+    env::var("PRE_SHARED_KEY")
+        .ok()
+        .map(|pre_shared_key| PreSharedKey::new(pre_shared_key.as_bytes()))
+}
+
+// e.g. CODEC=json to make the traffic readable in Wireshark; the vehicle replies in kind
+pub fn get_codec_from_env() -> Codec {
+    env::var("CODEC")
+        .map(|codec| codec.parse().unwrap())
+        .unwrap_or_default()
 }
 
 #[derive(Resource, Debug)]
 struct InputState {
     pub last_input_message: Option<InputMessage>,
     pub is_handled: bool,
+    pub sequence: u64,
+    // only if PRE_SHARED_KEY is set; nothing but Requests go out until the vehicle has answered
+    pub session: Option<ClientSession>,
+    pub codec: Codec,
+    // North moves the vehicle on to its next drive mode, once per press
+    pub is_drive_mode_pressed: bool,
+    pub drive_mode_message: Option<DriveModeMessage>,
+}
+
+#[derive(Resource, Debug)]
+struct LinkState {
+    pub latency_tracker: LatencyTracker,
+    pub last_pinged_at: Option<Instant>,
+    pub is_degraded: bool,
+    pub is_armed: Option<bool>,
+    pub drive_mode: Option<String>,
+}
+
+fn send(input_state: &mut InputState, socket: &UdpSocket, message: &Message) {
+    let mut buf = [0; 1024];
+
+    let sequence = input_state.sequence;
+    input_state.sequence += 1;
+
+    let n = match &mut input_state.session {
+        Some(session) => session
+            .encode_into(sequence, message, input_state.codec, &mut buf)
+            .unwrap(),
+        None => encode_into(
+            sequence,
+            get_timestamp_us(),
+            message,
+            input_state.codec,
+            &mut buf,
+        )
+        .unwrap(),
+    };
+
+    let _ = socket.send(&buf[0..n]);
 }
 
 fn handle_input(
@@ -82,6 +172,7 @@ fn handle_input(
         let mut mode_down: bool = false;
         let mut mode_left: bool = false;
         let mut mode_right: bool = false;
+        let mut drive_mode: bool = false;
 
         for button_input in button_inputs.get_pressed() {
             if button_input.button_type == GamepadButtonType::South {
@@ -94,9 +185,16 @@ fn handle_input(
                 mode_left = true;
             } else if button_input.button_type == GamepadButtonType::DPadRight {
                 mode_right = true;
+            } else if button_input.button_type == GamepadButtonType::North {
+                drive_mode = true;
             }
         }
 
+        if drive_mode && !input_state.is_drive_mode_pressed {
+            input_state.drive_mode_message = Some(DriveModeMessage::Cycle);
+        }
+        input_state.is_drive_mode_pressed = drive_mode;
+
         let input_message = InputMessage {
             throttle: throttle - brake,
             steering: steering_left,
@@ -117,7 +215,7 @@ fn handle_input(
     }
 }
 
-fn handle_network(input_state: Res<InputState>, socket: NonSend<UdpSocket>) {
+fn handle_network(mut input_state: ResMut<InputState>, socket: NonSend<UdpSocket>) {
     if input_state.last_input_message.is_none() {
         return;
     }
@@ -129,9 +227,124 @@ fn handle_network(input_state: Res<InputState>, socket: NonSend<UdpSocket>) {
     let input_message = input_state.last_input_message.clone().unwrap();
     println!("input_message={:?}", input_message);
 
-    let input_message_data = serialize(input_message.clone()).unwrap();
+    send(&mut input_state, &socket, &Message::Input(input_message));
+
+    if let Some(drive_mode_message) = input_state.drive_mode_message.take() {
+        println!("drive_mode_message={:?}", drive_mode_message);
 
-    let _ = socket.send(input_message_data.to_vec().as_slice());
+        send(
+            &mut input_state,
+            &socket,
+            &Message::DriveMode(drive_mode_message),
+        );
+    }
+}
+
+fn handle_ping(
+    mut input_state: ResMut<InputState>,
+    mut link_state: ResMut<LinkState>,
+    socket: NonSend<UdpSocket>,
+) {
+    let now = Instant::now();
+
+    if link_state
+        .last_pinged_at
+        .is_some_and(|last_pinged_at| now - last_pinged_at < PING_INTERVAL)
+    {
+        return;
+    }
+    link_state.last_pinged_at = Some(now);
+
+    let id = link_state.latency_tracker.ping(now);
+
+    send(
+        &mut input_state,
+        &socket,
+        &Message::Ping(PingMessage::Ping(id)),
+    );
+
+    // let the driver know when the link gets laggy or lossy (and when it recovers)
+    let latency_stats = link_state.latency_tracker.get_stats(now);
+    if latency_stats.is_degraded() != link_state.is_degraded {
+        link_state.is_degraded = latency_stats.is_degraded();
+
+        if link_state.is_degraded {
+            log::warn!("link degraded; latency_stats={:?}", latency_stats);
+        } else {
+            log::info!("link recovered; latency_stats={:?}", latency_stats);
+        }
+    }
+}
+
+fn handle_telemetry(
+    mut input_state: ResMut<InputState>,
+    mut link_state: ResMut<LinkState>,
+    socket: NonSend<UdpSocket>,
+) {
+    let mut buf = [0; 1024];
+
+    let address: Address = socket.peer_addr().unwrap().into();
+
+    // the socket is non-blocking, so this drains whatever has arrived since last time
+    while let Ok(n) = socket.recv(&mut buf) {
+        // the session takes care of its own messages (e.g. joining again after the vehicle restarts)
+        let envelope = match &mut input_state.session {
+            Some(session) => session.receive(&address, &buf[0..n]),
+            None => decode(&buf[0..n]).ok(),
+        };
+
+        match envelope {
+            Some(Envelope {
+                message: Message::Telemetry(telemetry_message),
+                ..
+            }) => {
+                println!("telemetry_message={:?}", telemetry_message);
+
+                // vehicles start disarmed, so let the driver know how to get going
+                if link_state.is_armed != Some(telemetry_message.armed) {
+                    link_state.is_armed = Some(telemetry_message.armed);
+
+                    if telemetry_message.armed {
+                        log::info!("vehicle armed");
+                    } else {
+                        log::warn!("vehicle disarmed; hold South with the sticks centred to arm");
+                    }
+                }
+
+                if telemetry_message.drive_mode.is_some()
+                    && link_state.drive_mode != telemetry_message.drive_mode
+                {
+                    link_state.drive_mode = telemetry_message.drive_mode.clone();
+
+                    log::info!(
+                        "vehicle in drive_mode={:?}; press North for the next one",
+                        telemetry_message.drive_mode.unwrap()
+                    );
+                }
+            }
+            // e.g. somebody else is already driving
+            Some(Envelope {
+                message: Message::Lease(lease_message),
+                ..
+            }) => {
+                println!("lease_message={:?}", lease_message);
+            }
+            // what the vehicle makes of each channel, sent when we're granted the lease
+            Some(Envelope {
+                message: Message::Descriptor(vehicle_descriptor),
+                ..
+            }) => {
+                println!("vehicle_descriptor={:?}", vehicle_descriptor);
+            }
+            Some(Envelope {
+                message: Message::Ping(PingMessage::Pong(id)),
+                ..
+            }) => {
+                link_state.latency_tracker.pong(id, Instant::now());
+            }
+            _ => {}
+        }
+    }
 }
 
 fn main() {
@@ -166,6 +379,19 @@ fn main() {
     app.insert_resource(InputState {
         last_input_message: None,
         is_handled: false,
+        sequence: get_timestamp_us(),
+        session: get_pre_shared_key_from_env().map(ClientSession::new),
+        codec: get_codec_from_env(),
+        is_drive_mode_pressed: false,
+        drive_mode_message: None,
+    });
+
+    app.insert_resource(LinkState {
+        latency_tracker: LatencyTracker::new(),
+        last_pinged_at: None,
+        is_degraded: false,
+        is_armed: None,
+        drive_mode: None,
     });
 
     app.add_fixed_timestep_system(LOCAL_TIME_STEP_NAME, 0, handle_input);
@@ -176,6 +402,10 @@ fn main() {
         handle_network.after(handle_input),
     );
 
+    app.add_fixed_timestep_system(NETWORK_TIME_STEP_NAME, 0, handle_ping.after(handle_network));
+
+    app.add_fixed_timestep_system(NETWORK_TIME_STEP_NAME, 0, handle_telemetry);
+
     let remote_addr = get_socket_addr_from_env();
 
     let local_addr: SocketAddr = if remote_addr.is_ipv4() {
@@ -188,6 +418,7 @@ fn main() {
 
     let socket = UdpSocket::bind(local_addr).unwrap();
     socket.connect(remote_addr).unwrap();
+    socket.set_nonblocking(true).unwrap();
 
     app.insert_non_send_resource(socket);
 
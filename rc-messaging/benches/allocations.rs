@@ -0,0 +1,163 @@
+// counts the heap allocations (and times) per packet of the old Vec-based encode / decode path
+// against the slice-based one the transport uses, for each codec; run with `cargo bench`
+//
+// the slice-based path must not allocate at all, so this fails if it ever starts to
+
+struct CountingAllocator;
+
+static ALLOCATIONS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        std::alloc::System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+        std::alloc::System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: std::alloc::Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        std::alloc::System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const PACKETS: usize = 100_000;
+
+fn get_message() -> rc_messaging::envelope::Message {
+    rc_messaging::envelope::Message::Input(rc_messaging::serialization::InputMessage {
+        throttle: 0.69,
+        steering: -0.69,
+        throttle_left: 0.0,
+        throttle_right: 0.0,
+        steering_left: 0.0,
+        steering_right: 0.0,
+        mode_up: false,
+        mode_down: false,
+        mode_left: false,
+        mode_right: false,
+        handbrake: false,
+    })
+}
+
+// returns (allocations, nanoseconds) per packet
+fn measure(mut f: impl FnMut(u64)) -> (f64, f64) {
+    // warm up (e.g. anything lazily initialised)
+    f(0);
+
+    let allocations = ALLOCATIONS.load(std::sync::atomic::Ordering::Relaxed);
+    let started_at = std::time::Instant::now();
+
+    for sequence in 0..PACKETS as u64 {
+        f(sequence);
+    }
+
+    let elapsed = started_at.elapsed();
+    let allocations = ALLOCATIONS.load(std::sync::atomic::Ordering::Relaxed) - allocations;
+
+    (
+        allocations as f64 / PACKETS as f64,
+        elapsed.as_nanos() as f64 / PACKETS as f64,
+    )
+}
+
+fn main() -> anyhow::Result<()> {
+    let pre_shared_key = rc_messaging::auth::PreSharedKey::new(b"car123!@#");
+    let message = get_message();
+
+    println!(
+        "{:<12} {:<24} {:>12} {:>12}",
+        "codec", "path", "allocs/pkt", "ns/pkt"
+    );
+
+    let mut regressions = vec![];
+
+    for codec in [
+        rc_messaging::codec::Codec::MessagePack,
+        rc_messaging::codec::Codec::Postcard,
+        rc_messaging::codec::Codec::Cbor,
+        rc_messaging::codec::Codec::Json,
+    ] {
+        let mut datagram = rc_messaging::envelope::encode_with_codec(0, 0, &message, codec)?;
+        rc_messaging::auth::sign(&pre_shared_key, &mut datagram);
+
+        let mut buf = vec![0; 1024];
+
+        let results = [
+            (
+                "encode (Vec)",
+                false,
+                measure(|sequence| {
+                    let mut buf =
+                        rc_messaging::envelope::encode_with_codec(sequence, 0, &message, codec)
+                            .unwrap();
+                    rc_messaging::auth::sign(&pre_shared_key, &mut buf);
+                    std::hint::black_box(buf);
+                }),
+            ),
+            (
+                "encode_into (slice)",
+                true,
+                measure(|sequence| {
+                    let n = rc_messaging::envelope::encode_into(
+                        sequence,
+                        0,
+                        &message,
+                        codec,
+                        &mut buf[..1024 - rc_messaging::auth::TAG_SIZE],
+                    )
+                    .unwrap();
+                    std::hint::black_box(rc_messaging::auth::sign_in_place(
+                        &pre_shared_key,
+                        &mut buf,
+                        n,
+                    ));
+                }),
+            ),
+            (
+                "decode (copied to Vec)",
+                false,
+                measure(|_| {
+                    let buf = datagram.to_vec();
+                    rc_messaging::auth::verify(&pre_shared_key, &buf).unwrap();
+                    std::hint::black_box(rc_messaging::envelope::decode(&buf).unwrap());
+                }),
+            ),
+            (
+                "decode (slice)",
+                true,
+                measure(|_| {
+                    rc_messaging::auth::verify(&pre_shared_key, &datagram).unwrap();
+                    std::hint::black_box(rc_messaging::envelope::decode(&datagram).unwrap());
+                }),
+            ),
+        ];
+
+        for (path, must_not_allocate, (allocations, nanoseconds)) in results {
+            println!(
+                "{:<12} {:<24} {:>12.2} {:>12.0}",
+                format!("{:?}", codec),
+                path,
+                allocations,
+                nanoseconds
+            );
+
+            if must_not_allocate && allocations > 0.0 {
+                regressions.push(format!("{:?} {}", codec, path));
+            }
+        }
+    }
+
+    if !regressions.is_empty() {
+        return Err(anyhow::anyhow!(
+            "expected no allocations per packet for regressions={:?}",
+            regressions
+        ));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,32 @@
+// where a datagram came from / is going to; it's part of the core (rather than crate::transport)
+// because a LeaseMessage can carry one
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum Address {
+    Socket(core::net::SocketAddr),
+    // a point-to-point link (serial port, loopback etc) that has no network address
+    Link(alloc::string::String),
+}
+
+impl Address {
+    pub fn get_socket_address(&self) -> Option<core::net::SocketAddr> {
+        match self {
+            Address::Socket(address) => Some(*address),
+            Address::Link(_) => None,
+        }
+    }
+}
+
+impl From<core::net::SocketAddr> for Address {
+    fn from(address: core::net::SocketAddr) -> Self {
+        Address::Socket(address)
+    }
+}
+
+impl core::fmt::Display for Address {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Address::Socket(address) => write!(f, "{}", address),
+            Address::Link(name) => write!(f, "{}", name),
+        }
+    }
+}
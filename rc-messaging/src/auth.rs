@@ -0,0 +1,217 @@
+use alloc::vec::Vec;
+use hmac::Mac;
+
+type HmacSha256 = hmac::Hmac<sha2::Sha256>;
+
+pub const TAG_SIZE: usize = 32;
+
+#[derive(Clone)]
+pub struct PreSharedKey {
+    key: Vec<u8>,
+}
+
+impl PreSharedKey {
+    pub fn new(key: &[u8]) -> Self {
+        Self { key: key.to_vec() }
+    }
+
+    // what a Client signs with once a Server has told it the nonce of the session it's in (see
+    // crate::serialization::SessionMessage)
+    pub fn get_session_key(&self, nonce: u64) -> Self {
+        let mut mac = self.get_mac();
+        mac.update(b"session");
+        mac.update(&nonce.to_be_bytes());
+
+        Self {
+            key: mac.finalize().into_bytes().to_vec(),
+        }
+    }
+
+    fn get_mac(&self) -> HmacSha256 {
+        // HMAC accepts keys of any length
+        HmacSha256::new_from_slice(&self.key).unwrap()
+    }
+}
+
+impl core::fmt::Debug for PreSharedKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("PreSharedKey").finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    Unsigned,
+    BadSignature,
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::Unsigned => write!(f, "datagram is not signed"),
+            Error::BadSignature => write!(f, "datagram has a bad signature"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+// sign a datagram produced by crate::envelope::encode; the signature covers the whole header
+// (including the sequence number) and the payload
+pub fn sign(pre_shared_key: &PreSharedKey, buf: &mut Vec<u8>) {
+    let n = buf.len();
+    buf.resize(n + TAG_SIZE, 0);
+
+    sign_in_place(pre_shared_key, buf, n);
+}
+
+// the same as sign but for the n byte datagram at the start of buf (e.g. from
+// crate::envelope::encode_into), which must have room for the tag after it; returns the length of
+// the signed datagram
+pub fn sign_in_place(pre_shared_key: &PreSharedKey, buf: &mut [u8], n: usize) -> usize {
+    assert!(n >= crate::envelope::HEADER_SIZE);
+    assert!(buf.len() >= n + TAG_SIZE);
+    assert_eq!(buf[2], crate::envelope::PROTOCOL_VERSION);
+
+    buf[crate::envelope::FLAGS_OFFSET] |= crate::envelope::FLAG_SIGNED;
+
+    let mut mac = pre_shared_key.get_mac();
+    mac.update(&buf[..n]);
+    let tag = mac.finalize().into_bytes();
+
+    buf[n..n + TAG_SIZE].copy_from_slice(&tag);
+
+    n + TAG_SIZE
+}
+
+pub fn verify(pre_shared_key: &PreSharedKey, buf: &[u8]) -> Result<(), Error> {
+    let is_signed = buf.len() >= crate::envelope::HEADER_SIZE + TAG_SIZE
+        && buf[0..2] == crate::envelope::MAGIC
+        && buf[2] == crate::envelope::PROTOCOL_VERSION
+        && buf[crate::envelope::FLAGS_OFFSET] & crate::envelope::FLAG_SIGNED != 0;
+
+    if !is_signed {
+        return Err(Error::Unsigned);
+    }
+
+    let (signed_buf, tag) = buf.split_at(buf.len() - TAG_SIZE);
+
+    let mut mac = pre_shared_key.get_mac();
+    mac.update(signed_buf);
+    mac.verify_slice(tag).map_err(|_| Error::BadSignature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_buf() -> anyhow::Result<Vec<u8>> {
+        let input_message = crate::serialization::InputMessage {
+            throttle: 0.69,
+            steering: 0.69,
+            throttle_left: 0.69,
+            throttle_right: 0.69,
+            steering_left: 0.69,
+            steering_right: 0.69,
+            mode_up: true,
+            mode_down: true,
+            mode_left: true,
+            mode_right: true,
+            handbrake: true,
+        };
+
+        Ok(crate::envelope::encode(
+            1337,
+            69,
+            &crate::envelope::Message::Input(input_message),
+        )?)
+    }
+
+    #[test]
+    fn sign_and_verify() -> anyhow::Result<()> {
+        let pre_shared_key = PreSharedKey::new(b"car123!@#");
+
+        let unsigned_buf = get_buf()?;
+        let mut buf = unsigned_buf.clone();
+        sign(&pre_shared_key, &mut buf);
+        assert_eq!(buf.len(), unsigned_buf.len() + TAG_SIZE);
+
+        verify(&pre_shared_key, &buf)?;
+
+        let envelope = crate::envelope::decode(&buf)?;
+        assert!(envelope.signed);
+        assert_eq!(envelope, {
+            let mut envelope = crate::envelope::decode(&unsigned_buf)?;
+            envelope.signed = true;
+            envelope
+        });
+
+        Ok(())
+    }
+
+    #[test]
+    fn sign_in_place() -> anyhow::Result<()> {
+        let pre_shared_key = PreSharedKey::new(b"car123!@#");
+
+        let mut signed_buf = get_buf()?;
+        sign(&pre_shared_key, &mut signed_buf);
+
+        let unsigned_buf = get_buf()?;
+        let mut buf = [0; 256];
+        buf[0..unsigned_buf.len()].copy_from_slice(&unsigned_buf);
+
+        let n = super::sign_in_place(&pre_shared_key, &mut buf, unsigned_buf.len());
+        assert_eq!(&buf[0..n], signed_buf.as_slice());
+        assert_eq!(verify(&pre_shared_key, &buf[0..n]), Ok(()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn session_key() -> anyhow::Result<()> {
+        let pre_shared_key = PreSharedKey::new(b"car123!@#");
+
+        let mut buf = get_buf()?;
+        sign(&pre_shared_key.get_session_key(1), &mut buf);
+        verify(&pre_shared_key.get_session_key(1), &buf)?;
+
+        // signed for one session is no good for any other (or for none)
+        assert_eq!(
+            verify(&pre_shared_key.get_session_key(2), &buf),
+            Err(Error::BadSignature)
+        );
+        assert_eq!(verify(&pre_shared_key, &buf), Err(Error::BadSignature));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_unsigned() -> anyhow::Result<()> {
+        let pre_shared_key = PreSharedKey::new(b"car123!@#");
+
+        let buf = get_buf()?;
+        assert_eq!(verify(&pre_shared_key, &buf), Err(Error::Unsigned));
+
+        let buf = crate::serialization::serialize(0.69)?;
+        assert_eq!(verify(&pre_shared_key, &buf), Err(Error::Unsigned));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_bad_signature() -> anyhow::Result<()> {
+        let pre_shared_key = PreSharedKey::new(b"car123!@#");
+
+        let mut buf = get_buf()?;
+        sign(&PreSharedKey::new(b"truck123!@#"), &mut buf);
+        assert_eq!(verify(&pre_shared_key, &buf), Err(Error::BadSignature));
+
+        // tampering with the sequence number must invalidate the signature
+        let mut buf = get_buf()?;
+        sign(&pre_shared_key, &mut buf);
+        buf[crate::envelope::FLAGS_OFFSET + 8] ^= 0x01;
+        assert_eq!(verify(&pre_shared_key, &buf), Err(Error::BadSignature));
+
+        Ok(())
+    }
+}
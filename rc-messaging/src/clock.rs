@@ -0,0 +1,161 @@
+// where the time comes from, so that timeouts (leases, failsafes and so on) can be tested on
+// simulated time rather than by sleeping and hoping the machine isn't too busy
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> std::time::Instant;
+
+    fn sleep(&self, duration: std::time::Duration);
+
+    // how long waiting for something (e.g. on a channel) for up to the given timeout should really
+    // block for; anything left over is slept once the wait is over
+    fn get_blocking_timeout(&self, timeout: std::time::Duration) -> std::time::Duration {
+        timeout
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> std::time::Instant {
+        std::time::Instant::now()
+    }
+
+    fn sleep(&self, duration: std::time::Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+pub fn get_system_clock() -> std::sync::Arc<dyn Clock> {
+    std::sync::Arc::new(SystemClock)
+}
+
+// only moves when it's advanced (or slept on), and never blocks; clones share the same time
+#[derive(Debug, Clone)]
+pub struct SimulatedClock {
+    now: std::sync::Arc<std::sync::Mutex<std::time::Instant>>,
+}
+
+impl Default for SimulatedClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimulatedClock {
+    pub fn new() -> Self {
+        Self {
+            now: std::sync::Arc::new(std::sync::Mutex::new(std::time::Instant::now())),
+        }
+    }
+
+    pub fn advance(&self, duration: std::time::Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now(&self) -> std::time::Instant {
+        *self.now.lock().unwrap()
+    }
+
+    fn sleep(&self, duration: std::time::Duration) {
+        self.advance(duration);
+    }
+
+    // whatever has been sent by now is all there is
+    fn get_blocking_timeout(&self, _timeout: std::time::Duration) -> std::time::Duration {
+        std::time::Duration::ZERO
+    }
+}
+
+// std::sync::mpsc::Receiver::recv_timeout, but on the given Clock's time
+pub fn recv_timeout<T>(
+    clock: &dyn Clock,
+    receiver: &std::sync::mpsc::Receiver<T>,
+    timeout: std::time::Duration,
+) -> Result<T, std::sync::mpsc::RecvTimeoutError> {
+    let deadline = clock.now() + timeout;
+
+    let blocking_timeout = clock.get_blocking_timeout(timeout);
+
+    let result = if blocking_timeout.is_zero() {
+        receiver.try_recv().map_err(|err| match err {
+            std::sync::mpsc::TryRecvError::Empty => std::sync::mpsc::RecvTimeoutError::Timeout,
+            std::sync::mpsc::TryRecvError::Disconnected => {
+                std::sync::mpsc::RecvTimeoutError::Disconnected
+            }
+        })
+    } else {
+        receiver.recv_timeout(blocking_timeout)
+    };
+
+    if let Err(std::sync::mpsc::RecvTimeoutError::Timeout) = result {
+        clock.sleep(deadline.saturating_duration_since(clock.now()));
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> std::time::Duration {
+        std::time::Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn simulated_clock() {
+        let clock = SimulatedClock::new();
+        let started_at = clock.now();
+
+        let shared_clock = clock.clone();
+        shared_clock.advance(ms(100));
+        clock.sleep(ms(50));
+
+        assert_eq!(clock.now() - started_at, ms(150));
+        assert_eq!(shared_clock.now(), clock.now());
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+
+        // nothing there, so straight to the deadline without really waiting
+        let really_started_at = std::time::Instant::now();
+        assert_eq!(
+            recv_timeout(&clock, &receiver, std::time::Duration::from_secs(60)),
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout)
+        );
+        assert!(really_started_at.elapsed() < std::time::Duration::from_secs(1));
+        assert_eq!(
+            clock.now() - started_at,
+            ms(150) + std::time::Duration::from_secs(60)
+        );
+
+        // something there, so no time passes
+        sender.send(1).unwrap();
+        assert_eq!(recv_timeout(&clock, &receiver, ms(100)), Ok(1));
+        assert_eq!(
+            clock.now() - started_at,
+            ms(150) + std::time::Duration::from_secs(60)
+        );
+
+        drop(sender);
+        assert_eq!(
+            recv_timeout(&clock, &receiver, ms(100)),
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn system_clock() {
+        let clock = SystemClock;
+        let (_sender, receiver) = std::sync::mpsc::channel::<()>();
+
+        let started_at = clock.now();
+        assert_eq!(
+            recv_timeout(&clock, &receiver, ms(50)),
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout)
+        );
+        assert!(clock.now() - started_at >= ms(50));
+    }
+}
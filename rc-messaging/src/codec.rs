@@ -0,0 +1,302 @@
+use alloc::vec::Vec;
+
+// how an envelope's payload is serialized; the Codec goes in the envelope's flags so a receiver can
+// decode anything it's sent, and a Server answers each link in whatever Codec it last heard on it
+//
+// MessagePack is the default (and the only Codec older receivers understand), postcard is the most
+// compact (for constrained links) and JSON is the easiest to read (for debugging on the bench)
+//
+// MessagePack needs the std feature, so without it (e.g. bare-metal) postcard is the default
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[repr(u8)]
+pub enum Codec {
+    MessagePack = 0,
+    Postcard = 1,
+    Cbor = 2,
+    Json = 3,
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        if cfg!(feature = "std") {
+            Codec::MessagePack
+        } else {
+            Codec::Postcard
+        }
+    }
+}
+
+impl TryFrom<u8> for Codec {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Codec::MessagePack),
+            1 => Ok(Codec::Postcard),
+            2 => Ok(Codec::Cbor),
+            3 => Ok(Codec::Json),
+            _ => Err(value),
+        }
+    }
+}
+
+// for binaries that let the Codec be chosen at runtime (e.g. CODEC=json)
+impl core::str::FromStr for Codec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "msgpack" | "messagepack" => Ok(Codec::MessagePack),
+            "postcard" => Ok(Codec::Postcard),
+            "cbor" => Ok(Codec::Cbor),
+            "json" => Ok(Codec::Json),
+            _ => Err(anyhow::anyhow!("unsupported codec={:?}", s)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    #[cfg(feature = "std")]
+    MessagePackEncode(rmp_serde::encode::Error),
+    #[cfg(feature = "std")]
+    MessagePackDecode(rmp_serde::decode::Error),
+    Unsupported(Codec),
+    BufferTooSmall,
+    Postcard(postcard::Error),
+    CborEncode(ciborium::ser::Error<<Vec<u8> as ciborium_io::Write>::Error>),
+    CborDecode(ciborium::de::Error<<&'static [u8] as ciborium_io::Read>::Error>),
+    Json(serde_json::Error),
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            #[cfg(feature = "std")]
+            Error::MessagePackEncode(err) => write!(f, "{}", err),
+            #[cfg(feature = "std")]
+            Error::MessagePackDecode(err) => write!(f, "{}", err),
+            Error::Unsupported(codec) => write!(f, "unsupported in this build; codec={:?}", codec),
+            Error::BufferTooSmall => write!(f, "buffer too small"),
+            Error::Postcard(err) => write!(f, "{}", err),
+            Error::CborEncode(err) => write!(f, "{}", err),
+            Error::CborDecode(err) => write!(f, "{}", err),
+            Error::Json(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+impl Codec {
+    // appends the encoded value to buf
+    pub fn encode<T>(&self, value: &T, buf: &mut Vec<u8>) -> Result<(), Error>
+    where
+        T: serde::ser::Serialize,
+    {
+        match self {
+            #[cfg(feature = "std")]
+            Codec::MessagePack => {
+                rmp_serde::encode::write(buf, value).map_err(Error::MessagePackEncode)
+            }
+            #[cfg(not(feature = "std"))]
+            Codec::MessagePack => Err(Error::Unsupported(*self)),
+            Codec::Postcard => postcard::to_extend(value, core::mem::take(buf))
+                .map(|extended| *buf = extended)
+                .map_err(Error::Postcard),
+            Codec::Cbor => ciborium::ser::into_writer(value, buf).map_err(Error::CborEncode),
+            // serde_json can only write into a Vec of its own without std
+            Codec::Json => serde_json::to_vec(value)
+                .map(|encoded| buf.extend_from_slice(&encoded))
+                .map_err(Error::Json),
+        }
+    }
+
+    // writes the encoded value to the start of buf without allocating and returns its length (CBOR
+    // and JSON fall back to encoding into a Vec without std)
+    pub fn encode_into<T>(&self, value: &T, buf: &mut [u8]) -> Result<usize, Error>
+    where
+        T: serde::ser::Serialize,
+    {
+        match self {
+            Codec::Postcard => postcard::to_slice(value, buf)
+                .map(|used| used.len())
+                .map_err(|err| match err {
+                    postcard::Error::SerializeBufferFull => Error::BufferTooSmall,
+                    err => Error::Postcard(err),
+                }),
+            #[cfg(feature = "std")]
+            Codec::MessagePack | Codec::Cbor | Codec::Json => self.write_into(value, buf),
+            #[cfg(not(feature = "std"))]
+            Codec::MessagePack => Err(Error::Unsupported(*self)),
+            #[cfg(not(feature = "std"))]
+            Codec::Cbor | Codec::Json => {
+                let mut encoded = Vec::new();
+                self.encode(value, &mut encoded)?;
+
+                buf.get_mut(..encoded.len())
+                    .ok_or(Error::BufferTooSmall)?
+                    .copy_from_slice(&encoded);
+
+                Ok(encoded.len())
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn write_into<T>(&self, value: &T, buf: &mut [u8]) -> Result<usize, Error>
+    where
+        T: serde::ser::Serialize,
+    {
+        let available = buf.len();
+        let mut writer = &mut buf[..];
+
+        match self {
+            Codec::MessagePack => {
+                rmp_serde::encode::write(&mut writer, value).map_err(Error::MessagePackEncode)
+            }
+            Codec::Postcard => postcard::to_io(value, &mut writer)
+                .map(|_| ())
+                .map_err(Error::Postcard),
+            Codec::Cbor => {
+                ciborium::ser::into_writer(value, &mut writer).map_err(Error::CborEncode)
+            }
+            Codec::Json => serde_json::to_writer(&mut writer, value).map_err(Error::Json),
+        }?;
+
+        Ok(available - writer.len())
+    }
+
+    pub fn decode<T>(&self, buf: &[u8]) -> Result<T, Error>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        match self {
+            #[cfg(feature = "std")]
+            Codec::MessagePack => rmp_serde::from_slice(buf).map_err(Error::MessagePackDecode),
+            #[cfg(not(feature = "std"))]
+            Codec::MessagePack => Err(Error::Unsupported(*self)),
+            Codec::Postcard => postcard::from_bytes(buf).map_err(Error::Postcard),
+            Codec::Cbor => ciborium::de::from_reader(buf).map_err(Error::CborDecode),
+            Codec::Json => serde_json::from_slice(buf).map_err(Error::Json),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODECS: [Codec; 4] = [
+        Codec::MessagePack,
+        Codec::Postcard,
+        Codec::Cbor,
+        Codec::Json,
+    ];
+
+    fn get_telemetry_message() -> crate::serialization::TelemetryMessage {
+        crate::serialization::TelemetryMessage {
+            throttle: 0.69,
+            steering: -0.69,
+            throttle_left: 0.69,
+            throttle_right: 0.69,
+            throttle_min: -0.69,
+            throttle_max: 0.69,
+            steering_offset: 0.01,
+            failsafe: true,
+            armed: true,
+            battery_voltage: None,
+            drive_mode: Some("beginner".into()),
+            link_stats: Some(crate::sequencing::LinkStats {
+                accepted: 1337,
+                last_sequence: Some(u64::MAX),
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn encode_and_decode() -> anyhow::Result<()> {
+        for codec in CODECS {
+            assert_eq!(Codec::try_from(codec as u8), Ok(codec));
+
+            let mut buf = vec![0xff];
+            codec.encode(&get_telemetry_message(), &mut buf)?;
+
+            // appended rather than overwritten
+            assert_eq!(buf[0], 0xff);
+
+            let telemetry_message: crate::serialization::TelemetryMessage =
+                codec.decode(&buf[1..])?;
+            assert_eq!(telemetry_message, get_telemetry_message());
+
+            let lease_message = crate::serialization::LeaseMessage::HandOver(
+                crate::address::Address::Socket("127.0.0.1:13337".parse()?),
+            );
+
+            let mut buf = vec![];
+            codec.encode(&lease_message, &mut buf)?;
+            assert_eq!(
+                codec.decode::<crate::serialization::LeaseMessage>(&buf)?,
+                lease_message
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn encode_into() -> anyhow::Result<()> {
+        for codec in CODECS {
+            let mut encoded = vec![];
+            codec.encode(&get_telemetry_message(), &mut encoded)?;
+
+            let mut buf = [0; 1024];
+            let n = codec.encode_into(&get_telemetry_message(), &mut buf)?;
+            assert_eq!(&buf[0..n], encoded.as_slice());
+
+            assert!(codec
+                .encode_into(&get_telemetry_message(), &mut buf[0..n - 1])
+                .is_err());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn postcard_is_smallest() -> anyhow::Result<()> {
+        let sizes = CODECS
+            .iter()
+            .map(|codec| {
+                let mut buf = vec![];
+                codec.encode(&get_telemetry_message(), &mut buf)?;
+                Ok((*codec, buf.len()))
+            })
+            .collect::<Result<Vec<(Codec, usize)>, Error>>()?;
+
+        let (smallest, _) = sizes.iter().min_by_key(|(_, n)| *n).unwrap();
+        assert_eq!(*smallest, Codec::Postcard);
+
+        Ok(())
+    }
+
+    #[test]
+    fn bad_payload() {
+        for codec in CODECS {
+            assert!(codec
+                .decode::<crate::serialization::TelemetryMessage>(&[0xff, 0xff])
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn from_str() -> anyhow::Result<()> {
+        assert_eq!("msgpack".parse::<Codec>()?, Codec::MessagePack);
+        assert_eq!("postcard".parse::<Codec>()?, Codec::Postcard);
+        assert_eq!("cbor".parse::<Codec>()?, Codec::Cbor);
+        assert_eq!("json".parse::<Codec>()?, Codec::Json);
+        assert!("xml".parse::<Codec>().is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,233 @@
+// log at most one malformed datagram per source address per LOG_INTERVAL
+const LOG_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+// don't let a flood of spoofed source addresses eat all our memory
+const MAX_ADDRESSES: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Malformation {
+    Oversize,
+    TooShort,
+    BadMagic,
+    UnsupportedVersion,
+    UnsupportedFlags,
+    UnknownMessageType,
+    BadPayload,
+}
+
+impl From<&crate::envelope::Error> for Malformation {
+    fn from(err: &crate::envelope::Error) -> Self {
+        match err {
+            crate::envelope::Error::TooShort(_) => Malformation::TooShort,
+            crate::envelope::Error::BadMagic(_) => Malformation::BadMagic,
+            crate::envelope::Error::UnsupportedVersion(_) => Malformation::UnsupportedVersion,
+            // the codec is carried in the flags
+            crate::envelope::Error::UnsupportedFlags(_)
+            | crate::envelope::Error::UnknownCodec(_) => Malformation::UnsupportedFlags,
+            crate::envelope::Error::UnknownMessageType(_) => Malformation::UnknownMessageType,
+            crate::envelope::Error::Encode(_) | crate::envelope::Error::Decode(_) => {
+                Malformation::BadPayload
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MalformedStats {
+    pub oversize: u64,
+    pub too_short: u64,
+    pub bad_magic: u64,
+    pub unsupported_version: u64,
+    pub unsupported_flags: u64,
+    pub unknown_message_type: u64,
+    pub bad_payload: u64,
+    pub suppressed_logs: u64,
+}
+
+impl MalformedStats {
+    pub fn get_total(&self) -> u64 {
+        self.oversize
+            + self.too_short
+            + self.bad_magic
+            + self.unsupported_version
+            + self.unsupported_flags
+            + self.unknown_message_type
+            + self.bad_payload
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    stats: MalformedStats,
+    last_seen_at: std::time::Instant,
+    last_logged_at: Option<std::time::Instant>,
+}
+
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    entries: std::collections::HashMap<crate::transport::Address, Entry>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_malformed_stats(
+        &self,
+    ) -> std::collections::HashMap<crate::transport::Address, MalformedStats> {
+        self.entries
+            .iter()
+            .map(|(address, entry)| (address.clone(), entry.stats.clone()))
+            .collect()
+    }
+
+    // returns true if this one was logged (rather than suppressed); now is from whatever clock the
+    // Server is timed against
+    pub fn record(
+        &mut self,
+        address: &crate::transport::Address,
+        malformation: Malformation,
+        reason: &dyn std::fmt::Display,
+        now: std::time::Instant,
+    ) -> bool {
+        if !self.entries.contains_key(address) && self.entries.len() >= MAX_ADDRESSES {
+            let oldest_address = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_seen_at)
+                .map(|(address, _)| address.clone())
+                .unwrap();
+
+            self.entries.remove(&oldest_address);
+        }
+
+        let entry = self
+            .entries
+            .entry(address.clone())
+            .or_insert_with(|| Entry {
+                stats: MalformedStats::default(),
+                last_seen_at: now,
+                last_logged_at: None,
+            });
+
+        entry.last_seen_at = now;
+
+        let stats = &mut entry.stats;
+        match malformation {
+            Malformation::Oversize => stats.oversize += 1,
+            Malformation::TooShort => stats.too_short += 1,
+            Malformation::BadMagic => stats.bad_magic += 1,
+            Malformation::UnsupportedVersion => stats.unsupported_version += 1,
+            Malformation::UnsupportedFlags => stats.unsupported_flags += 1,
+            Malformation::UnknownMessageType => stats.unknown_message_type += 1,
+            Malformation::BadPayload => stats.bad_payload += 1,
+        }
+
+        let should_log = entry.last_logged_at.is_none_or(|last_logged_at| {
+            now.saturating_duration_since(last_logged_at) >= LOG_INTERVAL
+        });
+
+        if !should_log {
+            stats.suppressed_logs += 1;
+            return false;
+        }
+
+        println!(
+            "dropping malformed datagram from address={} because reason={}; suppressed_logs={:?}, total={:?}",
+            address,
+            reason,
+            stats.suppressed_logs,
+            stats.get_total()
+        );
+
+        entry.last_logged_at = Some(now);
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::clock::Clock;
+
+    #[test]
+    fn rate_limited_per_address() -> anyhow::Result<()> {
+        let mut diagnostics = Diagnostics::new();
+        let now = crate::clock::SimulatedClock::new().now();
+
+        let address_1 = crate::transport::Address::Socket("127.0.0.1:1".parse()?);
+        let address_2 = crate::transport::Address::Link("/dev/ttyUSB0".to_string());
+
+        assert!(diagnostics.record(&address_1, Malformation::TooShort, &"too short", now));
+        assert!(!diagnostics.record(&address_1, Malformation::BadMagic, &"bad magic", now));
+        assert!(!diagnostics.record(&address_1, Malformation::BadMagic, &"bad magic", now));
+        assert!(diagnostics.record(&address_2, Malformation::Oversize, &"oversize", now));
+
+        let malformed_stats = diagnostics.get_malformed_stats();
+
+        let malformed_stats_1 = malformed_stats.get(&address_1).unwrap();
+        assert_eq!(malformed_stats_1.too_short, 1);
+        assert_eq!(malformed_stats_1.bad_magic, 2);
+        assert_eq!(malformed_stats_1.suppressed_logs, 2);
+        assert_eq!(malformed_stats_1.get_total(), 3);
+
+        let malformed_stats_2 = malformed_stats.get(&address_2).unwrap();
+        assert_eq!(malformed_stats_2.oversize, 1);
+        assert_eq!(malformed_stats_2.suppressed_logs, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rate_limited_over_time() -> anyhow::Result<()> {
+        let mut diagnostics = Diagnostics::new();
+        let clock = crate::clock::SimulatedClock::new();
+
+        let address = crate::transport::Address::Socket("127.0.0.1:1".parse()?);
+        let mut record = || diagnostics.record(&address, Malformation::TooShort, &"", clock.now());
+
+        // however quickly they really arrive, it's once per LOG_INTERVAL on the Server's clock
+        assert!(record());
+        clock.advance(LOG_INTERVAL - std::time::Duration::from_millis(1));
+        assert!(!record());
+        clock.advance(std::time::Duration::from_millis(1));
+        assert!(record());
+        assert!(!record());
+        clock.advance(LOG_INTERVAL * 10);
+        assert!(record());
+
+        let malformed_stats = diagnostics.get_malformed_stats();
+        let malformed_stats = malformed_stats.get(&address).unwrap();
+        assert_eq!(malformed_stats.too_short, 5);
+        assert_eq!(malformed_stats.suppressed_logs, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn bounded_number_of_addresses() -> anyhow::Result<()> {
+        let mut diagnostics = Diagnostics::new();
+        let clock = crate::clock::SimulatedClock::new();
+
+        for port in 0..(MAX_ADDRESSES as u16 * 2) {
+            clock.advance(std::time::Duration::from_millis(1));
+            diagnostics.record(
+                &std::net::SocketAddr::new("127.0.0.1".parse()?, port).into(),
+                Malformation::BadPayload,
+                &"bad payload",
+                clock.now(),
+            );
+        }
+
+        let malformed_stats = diagnostics.get_malformed_stats();
+        assert_eq!(malformed_stats.len(), MAX_ADDRESSES);
+        assert!(malformed_stats.contains_key(
+            &std::net::SocketAddr::new("127.0.0.1".parse()?, MAX_ADDRESSES as u16 * 2 - 1).into()
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,449 @@
+// vehicles broadcast a BeaconMessage every ANNOUNCE_INTERVAL to DISCOVERY_PORT; controllers listen
+// there and keep a list of whichever vehicles they've heard from recently
+pub const DISCOVERY_PORT: u16 = 13338;
+pub const DEFAULT_LIVE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
+
+const ANNOUNCE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+// how often the closed flag is checked while waiting
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
+
+const BUF_SIZE: usize = 1024;
+
+pub fn get_broadcast_address() -> std::net::SocketAddr {
+    std::net::SocketAddr::from(([255, 255, 255, 255], DISCOVERY_PORT))
+}
+
+pub struct Announcer {
+    socket: std::net::UdpSocket,
+    announce_address: std::net::SocketAddr,
+    beacon_message: crate::serialization::BeaconMessage,
+    closed: std::sync::Arc<std::sync::Mutex<bool>>,
+    pre_shared_key: Option<crate::auth::PreSharedKey>,
+}
+
+impl Announcer {
+    // announce_address is usually get_broadcast_address()
+    pub fn new(
+        beacon_message: crate::serialization::BeaconMessage,
+        announce_address: std::net::SocketAddr,
+    ) -> Result<Self, anyhow::Error> {
+        let socket = std::net::UdpSocket::bind("0.0.0.0:0")?;
+        socket.set_broadcast(true)?;
+
+        Ok(Self {
+            socket,
+            announce_address,
+            beacon_message,
+            closed: std::sync::Arc::new(std::sync::Mutex::new(false)),
+            pre_shared_key: None,
+        })
+    }
+
+    // once set, every beacon is signed with this key
+    pub fn set_pre_shared_key(&mut self, pre_shared_key: crate::auth::PreSharedKey) {
+        self.pre_shared_key = Some(pre_shared_key);
+    }
+
+    pub fn get_closer(&self) -> impl Fn() {
+        let closed = std::sync::Arc::clone(&self.closed);
+        move || {
+            let mut closed = closed.lock().unwrap();
+            *closed = true;
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        *self.closed.lock().unwrap()
+    }
+
+    pub fn run(&self) -> anyhow::Result<()> {
+        let mut sequence: u64 = crate::envelope::get_timestamp_us();
+
+        let mut buf = vec![0; BUF_SIZE];
+
+        let mut last_announced_at: Option<std::time::Instant> = None;
+
+        loop {
+            if self.is_closed() {
+                break;
+            }
+
+            if last_announced_at
+                .is_some_and(|last_announced_at| last_announced_at.elapsed() < ANNOUNCE_INTERVAL)
+            {
+                std::thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+
+            last_announced_at = Some(std::time::Instant::now());
+
+            let n = crate::transport::encode_into(
+                sequence,
+                &crate::envelope::Message::Beacon(self.beacon_message.clone()),
+                crate::codec::Codec::default(),
+                self.pre_shared_key.as_ref(),
+                &mut buf,
+            )?;
+            sequence += 1;
+
+            // e.g. the network isn't up yet; not worth stopping for
+            if let Err(err) = self.socket.send_to(&buf[0..n], self.announce_address) {
+                println!("send_to() failed because err={:?}", err);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscoveredVehicle {
+    pub name: String,
+    pub kind: crate::serialization::VehicleKind,
+    pub protocol_version: u8,
+    pub control_address: std::net::SocketAddr,
+    pub last_seen_at: std::time::Instant,
+}
+
+impl DiscoveredVehicle {
+    pub fn is_compatible(&self) -> bool {
+        self.protocol_version == crate::envelope::PROTOCOL_VERSION
+    }
+}
+
+type DiscoveredVehicles = std::collections::HashMap<std::net::SocketAddr, DiscoveredVehicle>;
+
+pub struct Discoverer {
+    socket: std::net::UdpSocket,
+    bind_address: std::net::SocketAddr,
+    live_timeout: std::time::Duration,
+    discovered_vehicles: std::sync::Arc<std::sync::Mutex<DiscoveredVehicles>>,
+    closed: std::sync::Arc<std::sync::Mutex<bool>>,
+    pre_shared_key: Option<crate::auth::PreSharedKey>,
+}
+
+impl Discoverer {
+    // bind_address is usually 0.0.0.0:DISCOVERY_PORT
+    pub fn new(bind_address: std::net::SocketAddr) -> Result<Self, anyhow::Error> {
+        let socket = std::net::UdpSocket::bind(bind_address)?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+
+        Ok(Self {
+            bind_address: socket.local_addr()?,
+            socket,
+            live_timeout: DEFAULT_LIVE_TIMEOUT,
+            discovered_vehicles: std::sync::Arc::new(std::sync::Mutex::new(
+                std::collections::HashMap::new(),
+            )),
+            closed: std::sync::Arc::new(std::sync::Mutex::new(false)),
+            pre_shared_key: None,
+        })
+    }
+
+    // how long since its last beacon before a vehicle is no longer listed
+    pub fn set_live_timeout(&mut self, live_timeout: std::time::Duration) {
+        self.live_timeout = live_timeout;
+    }
+
+    // once set, only beacons signed with this key are believed
+    pub fn set_pre_shared_key(&mut self, pre_shared_key: crate::auth::PreSharedKey) {
+        self.pre_shared_key = Some(pre_shared_key);
+    }
+
+    pub fn get_closer(&self) -> impl Fn() {
+        let closed = std::sync::Arc::clone(&self.closed);
+        move || {
+            let mut closed = closed.lock().unwrap();
+            *closed = true;
+        }
+    }
+
+    pub fn get_bind_address(&self) -> std::net::SocketAddr {
+        self.bind_address
+    }
+
+    // live vehicles only, ordered by name
+    pub fn get_discovered_vehicles_reader(&self) -> impl Fn() -> Vec<DiscoveredVehicle> {
+        let discovered_vehicles = std::sync::Arc::clone(&self.discovered_vehicles);
+        let live_timeout = self.live_timeout;
+        move || {
+            let discovered_vehicles = discovered_vehicles.lock().unwrap();
+
+            let mut live_vehicles: Vec<DiscoveredVehicle> = discovered_vehicles
+                .values()
+                .filter(|discovered_vehicle| {
+                    discovered_vehicle.last_seen_at.elapsed() <= live_timeout
+                })
+                .cloned()
+                .collect();
+
+            live_vehicles.sort_by(|a, b| {
+                a.name
+                    .cmp(&b.name)
+                    .then(a.control_address.cmp(&b.control_address))
+            });
+
+            live_vehicles
+        }
+    }
+
+    fn handle(&self, buf: &[u8], address: std::net::SocketAddr) {
+        if let Some(pre_shared_key) = &self.pre_shared_key {
+            if let Err(err) = crate::auth::verify(pre_shared_key, buf) {
+                println!(
+                    "dropping beacon from address={:?} because err={}",
+                    address, err
+                );
+                return;
+            }
+        }
+
+        let beacon_message = match crate::envelope::decode(buf) {
+            Ok(crate::envelope::Envelope {
+                message: crate::envelope::Message::Beacon(beacon_message),
+                ..
+            }) => beacon_message,
+            Ok(envelope) => {
+                println!(
+                    "ignoring unexpected message_type={:?} from address={:?}",
+                    envelope.message.get_message_type(),
+                    address
+                );
+                return;
+            }
+            Err(err) => {
+                println!(
+                    "dropping beacon from address={:?} because err={}",
+                    address, err
+                );
+                return;
+            }
+        };
+
+        let control_address = std::net::SocketAddr::new(address.ip(), beacon_message.control_port);
+
+        let mut discovered_vehicles = self.discovered_vehicles.lock().unwrap();
+
+        // forget about anything that's been gone a while, so this can't grow forever
+        let live_timeout = self.live_timeout;
+        discovered_vehicles.retain(|_, discovered_vehicle| {
+            discovered_vehicle.last_seen_at.elapsed() <= live_timeout
+        });
+
+        discovered_vehicles.insert(
+            control_address,
+            DiscoveredVehicle {
+                name: beacon_message.name,
+                kind: beacon_message.kind,
+                protocol_version: beacon_message.protocol_version,
+                control_address,
+                last_seen_at: std::time::Instant::now(),
+            },
+        );
+    }
+
+    pub fn run(&self) -> anyhow::Result<()> {
+        let mut buf = vec![0; BUF_SIZE];
+
+        loop {
+            if *self.closed.lock().unwrap() {
+                break;
+            }
+
+            let (n, address) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    continue;
+                }
+                Err(err) => {
+                    println!("recv_from() failed because err={:?}", err);
+                    return Err(err.into());
+                }
+            };
+
+            self.handle(&buf[0..n], address);
+        }
+
+        Ok(())
+    }
+}
+
+// listen for a while and return whatever turned up; handy for a controller picking a vehicle at
+// startup
+pub fn discover(
+    bind_address: std::net::SocketAddr,
+    duration: std::time::Duration,
+    pre_shared_key: Option<crate::auth::PreSharedKey>,
+) -> Result<Vec<DiscoveredVehicle>, anyhow::Error> {
+    let mut discoverer = Discoverer::new(bind_address)?;
+    if let Some(pre_shared_key) = pre_shared_key {
+        discoverer.set_pre_shared_key(pre_shared_key);
+    }
+
+    let closer = discoverer.get_closer();
+    let discovered_vehicles_reader = discoverer.get_discovered_vehicles_reader();
+
+    std::thread::scope(|scope| {
+        let handle = scope.spawn(|| discoverer.run());
+
+        std::thread::sleep(duration);
+        closer();
+
+        handle.join().unwrap()
+    })?;
+
+    Ok(discovered_vehicles_reader())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_beacon_message(name: &str, control_port: u16) -> crate::serialization::BeaconMessage {
+        crate::serialization::BeaconMessage {
+            name: name.to_string(),
+            kind: crate::serialization::VehicleKind::Tank,
+            protocol_version: crate::envelope::PROTOCOL_VERSION,
+            control_port,
+        }
+    }
+
+    #[test]
+    fn announce_and_discover() -> anyhow::Result<()> {
+        // shorter than ANNOUNCE_INTERVAL, so each announcer gets just the one beacon in
+        let live_timeout = std::time::Duration::from_millis(500);
+
+        let mut discoverer = Discoverer::new("127.0.0.1:0".parse()?)?;
+        discoverer.set_live_timeout(live_timeout);
+
+        let discoverer_closer = discoverer.get_closer();
+        let discovered_vehicles_reader = discoverer.get_discovered_vehicles_reader();
+
+        // all on 127.0.0.1, so they need different control ports to tell them apart
+        let announcers = [("tank-b", 13337), ("tank-a", 13338)]
+            .iter()
+            .map(|(name, control_port)| {
+                Announcer::new(
+                    get_beacon_message(name, *control_port),
+                    discoverer.get_bind_address(),
+                )
+            })
+            .collect::<Result<Vec<Announcer>, anyhow::Error>>()?;
+
+        let announcer_closers: Vec<_> = announcers
+            .iter()
+            .map(|announcer| announcer.get_closer())
+            .collect();
+
+        let discoverer_handle = std::thread::spawn(move || {
+            discoverer.run().unwrap();
+        });
+
+        let announcer_handles: Vec<_> = announcers
+            .into_iter()
+            .map(|announcer| {
+                std::thread::spawn(move || {
+                    announcer.run().unwrap();
+                })
+            })
+            .collect();
+
+        std::thread::sleep(POLL_INTERVAL * 4);
+
+        let discovered_vehicles = discovered_vehicles_reader();
+        assert_eq!(
+            discovered_vehicles
+                .iter()
+                .map(|discovered_vehicle| discovered_vehicle.name.as_str())
+                .collect::<Vec<&str>>(),
+            vec!["tank-a", "tank-b"]
+        );
+        assert_eq!(
+            discovered_vehicles
+                .iter()
+                .map(|discovered_vehicle| discovered_vehicle.control_address)
+                .collect::<Vec<std::net::SocketAddr>>(),
+            vec!["127.0.0.1:13338".parse()?, "127.0.0.1:13337".parse()?]
+        );
+        for discovered_vehicle in discovered_vehicles {
+            assert_eq!(
+                discovered_vehicle.kind,
+                crate::serialization::VehicleKind::Tank
+            );
+            assert!(discovered_vehicle.is_compatible());
+        }
+
+        // once they stop announcing, they drop off the list
+        for announcer_closer in announcer_closers {
+            announcer_closer();
+        }
+        for announcer_handle in announcer_handles {
+            announcer_handle.join().unwrap();
+        }
+
+        std::thread::sleep(live_timeout);
+        assert!(discovered_vehicles_reader().is_empty());
+
+        discoverer_closer();
+        discoverer_handle.join().unwrap();
+
+        Ok(())
+    }
+
+    #[test]
+    fn authenticated() -> anyhow::Result<()> {
+        let pre_shared_key = crate::auth::PreSharedKey::new(b"car123!@#");
+
+        let mut discoverer = Discoverer::new("127.0.0.1:0".parse()?)?;
+        discoverer.set_pre_shared_key(pre_shared_key.clone());
+
+        let discoverer_closer = discoverer.get_closer();
+        let discovered_vehicles_reader = discoverer.get_discovered_vehicles_reader();
+
+        let mut genuine_announcer = Announcer::new(
+            get_beacon_message("genuine", 13337),
+            discoverer.get_bind_address(),
+        )?;
+        genuine_announcer.set_pre_shared_key(pre_shared_key);
+
+        let spoofed_announcer = Announcer::new(
+            get_beacon_message("spoofed", 13338),
+            discoverer.get_bind_address(),
+        )?;
+
+        let genuine_announcer_closer = genuine_announcer.get_closer();
+        let spoofed_announcer_closer = spoofed_announcer.get_closer();
+
+        let discoverer_handle = std::thread::spawn(move || {
+            discoverer.run().unwrap();
+        });
+        let genuine_announcer_handle = std::thread::spawn(move || {
+            genuine_announcer.run().unwrap();
+        });
+        let spoofed_announcer_handle = std::thread::spawn(move || {
+            spoofed_announcer.run().unwrap();
+        });
+
+        std::thread::sleep(POLL_INTERVAL * 4);
+
+        let discovered_vehicles = discovered_vehicles_reader();
+        assert_eq!(discovered_vehicles.len(), 1);
+        assert_eq!(discovered_vehicles[0].name, "genuine");
+
+        genuine_announcer_closer();
+        spoofed_announcer_closer();
+        discoverer_closer();
+
+        genuine_announcer_handle.join().unwrap();
+        spoofed_announcer_handle.join().unwrap();
+        discoverer_handle.join().unwrap();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,748 @@
+use alloc::vec::Vec;
+
+// every framed datagram starts with MAGIC, PROTOCOL_VERSION, a MessageType, some flags, the
+// sender's sequence number and the sender's timestamp (microseconds since the UNIX epoch), followed
+// by the payload (encoded with the crate::codec::Codec in the top 4 bits of the flags, MessagePack
+// unless otherwise stated) and (if FLAG_SIGNED is set) a crate::auth::TAG_SIZE signature
+pub const MAGIC: [u8; 2] = [0x52, 0x43]; // "RC"
+pub const PROTOCOL_VERSION: u8 = 3;
+pub const HEADER_SIZE: usize = 21;
+pub const FLAGS_OFFSET: usize = 4;
+
+pub const FLAG_SIGNED: u8 = 0x01;
+const KNOWN_FLAGS: u8 = FLAG_SIGNED;
+
+const CODEC_SHIFT: u8 = 4;
+const CODEC_MASK: u8 = 0xf0;
+
+// version 1 has no flags, sequence number or timestamp
+const V1_HEADER_SIZE: usize = 4;
+
+// version 2 has no flags
+const V2_HEADER_SIZE: usize = 20;
+
+// version 0 is the bare (unframed) MessagePack InputMessage that predates the envelope
+pub const LEGACY_PROTOCOL_VERSION: u8 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MessageType {
+    Input = 1,
+    Telemetry = 2,
+    Lease = 3,
+    Beacon = 4,
+    Ping = 5,
+    Control = 6,
+    Descriptor = 7,
+    DriveMode = 8,
+    Session = 9,
+}
+
+impl TryFrom<u8> for MessageType {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(MessageType::Input),
+            2 => Ok(MessageType::Telemetry),
+            3 => Ok(MessageType::Lease),
+            4 => Ok(MessageType::Beacon),
+            5 => Ok(MessageType::Ping),
+            6 => Ok(MessageType::Control),
+            7 => Ok(MessageType::Descriptor),
+            8 => Ok(MessageType::DriveMode),
+            9 => Ok(MessageType::Session),
+            _ => Err(Error::UnknownMessageType(value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Input(crate::serialization::InputMessage),
+    Telemetry(crate::serialization::TelemetryMessage),
+    Lease(crate::serialization::LeaseMessage),
+    Beacon(crate::serialization::BeaconMessage),
+    Ping(crate::serialization::PingMessage),
+    Control(crate::serialization::ControlMessage),
+    Descriptor(crate::serialization::VehicleDescriptor),
+    DriveMode(crate::serialization::DriveModeMessage),
+    Session(crate::serialization::SessionMessage),
+}
+
+impl Message {
+    pub fn get_message_type(&self) -> MessageType {
+        match self {
+            Message::Input(_) => MessageType::Input,
+            Message::Telemetry(_) => MessageType::Telemetry,
+            Message::Lease(_) => MessageType::Lease,
+            Message::Beacon(_) => MessageType::Beacon,
+            Message::Ping(_) => MessageType::Ping,
+            Message::Control(_) => MessageType::Control,
+            Message::Descriptor(_) => MessageType::Descriptor,
+            Message::DriveMode(_) => MessageType::DriveMode,
+            Message::Session(_) => MessageType::Session,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Envelope {
+    pub version: u8,
+    pub signed: bool,
+    pub codec: crate::codec::Codec,
+    pub sequence: Option<u64>,
+    pub timestamp_us: Option<u64>,
+    pub message: Message,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    TooShort(usize),
+    BadMagic([u8; 2]),
+    UnsupportedVersion(u8),
+    UnsupportedFlags(u8),
+    UnknownMessageType(u8),
+    UnknownCodec(u8),
+    Encode(crate::codec::Error),
+    Decode(crate::codec::Error),
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::TooShort(n) => write!(f, "datagram too short for header; n={:?}", n),
+            Error::BadMagic(magic) => write!(f, "bad magic; magic={:?}", magic),
+            Error::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported protocol version; version={:?}, PROTOCOL_VERSION={:?}",
+                version, PROTOCOL_VERSION
+            ),
+            Error::UnsupportedFlags(flags) => write!(f, "unsupported flags; flags={:?}", flags),
+            Error::UnknownMessageType(message_type) => {
+                write!(f, "unknown message type; message_type={:?}", message_type)
+            }
+            Error::UnknownCodec(codec) => write!(f, "unknown codec; codec={:?}", codec),
+            Error::Encode(err) => write!(f, "failed to encode payload; err={}", err),
+            Error::Decode(err) => write!(f, "failed to decode payload; err={}", err),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+// the InputMessage layout from before steering_left / steering_right were added (e.g. esp32-rc-hmi)
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+struct LegacyInputMessage {
+    throttle: f32,
+    steering: f32,
+    throttle_left: f32,
+    throttle_right: f32,
+    mode_up: bool,
+    mode_down: bool,
+    mode_left: bool,
+    mode_right: bool,
+    handbrake: bool,
+}
+
+impl From<LegacyInputMessage> for crate::serialization::InputMessage {
+    fn from(legacy_input_message: LegacyInputMessage) -> Self {
+        crate::serialization::InputMessage {
+            throttle: legacy_input_message.throttle,
+            steering: legacy_input_message.steering,
+            throttle_left: legacy_input_message.throttle_left,
+            throttle_right: legacy_input_message.throttle_right,
+            steering_left: 0.0,
+            steering_right: 0.0,
+            mode_up: legacy_input_message.mode_up,
+            mode_down: legacy_input_message.mode_down,
+            mode_left: legacy_input_message.mode_left,
+            mode_right: legacy_input_message.mode_right,
+            handbrake: legacy_input_message.handbrake,
+        }
+    }
+}
+
+fn decode_legacy(buf: &[u8]) -> Result<Envelope, Error> {
+    let codec = crate::codec::Codec::MessagePack;
+
+    let input_message = match codec.decode::<crate::serialization::InputMessage>(buf) {
+        Ok(input_message) => input_message,
+        Err(_) => codec
+            .decode::<LegacyInputMessage>(buf)
+            .map_err(Error::Decode)?
+            .into(),
+    };
+
+    Ok(Envelope {
+        version: LEGACY_PROTOCOL_VERSION,
+        signed: false,
+        codec,
+        sequence: None,
+        timestamp_us: None,
+        message: Message::Input(input_message),
+    })
+}
+
+// without std there's no wall clock, so bare-metal senders supply their own timestamp_us
+#[cfg(feature = "std")]
+pub fn get_timestamp_us() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_micros() as u64)
+        .unwrap_or(0)
+}
+
+pub fn encode(sequence: u64, timestamp_us: u64, message: &Message) -> Result<Vec<u8>, Error> {
+    encode_with_codec(
+        sequence,
+        timestamp_us,
+        message,
+        crate::codec::Codec::default(),
+    )
+}
+
+fn get_header(
+    sequence: u64,
+    timestamp_us: u64,
+    message_type: MessageType,
+    codec: crate::codec::Codec,
+) -> [u8; HEADER_SIZE] {
+    let mut header = [0; HEADER_SIZE];
+    header[0..2].copy_from_slice(&MAGIC);
+    header[2] = PROTOCOL_VERSION;
+    header[3] = message_type as u8;
+    header[FLAGS_OFFSET] = (codec as u8) << CODEC_SHIFT;
+    header[FLAGS_OFFSET + 1..HEADER_SIZE - 8].copy_from_slice(&sequence.to_be_bytes());
+    header[HEADER_SIZE - 8..].copy_from_slice(&timestamp_us.to_be_bytes());
+    header
+}
+
+pub fn encode_with_codec(
+    sequence: u64,
+    timestamp_us: u64,
+    message: &Message,
+    codec: crate::codec::Codec,
+) -> Result<Vec<u8>, Error> {
+    let mut buf = Vec::with_capacity(64);
+    buf.extend_from_slice(&get_header(
+        sequence,
+        timestamp_us,
+        message.get_message_type(),
+        codec,
+    ));
+
+    match message {
+        Message::Input(input_message) => codec.encode(input_message, &mut buf),
+        Message::Telemetry(telemetry_message) => codec.encode(telemetry_message, &mut buf),
+        Message::Lease(lease_message) => codec.encode(lease_message, &mut buf),
+        Message::Beacon(beacon_message) => codec.encode(beacon_message, &mut buf),
+        Message::Ping(ping_message) => codec.encode(ping_message, &mut buf),
+        Message::Control(control_message) => codec.encode(control_message, &mut buf),
+        Message::Descriptor(vehicle_descriptor) => codec.encode(vehicle_descriptor, &mut buf),
+        Message::DriveMode(drive_mode_message) => codec.encode(drive_mode_message, &mut buf),
+        Message::Session(session_message) => codec.encode(session_message, &mut buf),
+    }
+    .map_err(Error::Encode)?;
+
+    Ok(buf)
+}
+
+// the same as encode_with_codec but into the start of buf (without allocating); returns the length
+// of the datagram
+pub fn encode_into(
+    sequence: u64,
+    timestamp_us: u64,
+    message: &Message,
+    codec: crate::codec::Codec,
+    buf: &mut [u8],
+) -> Result<usize, Error> {
+    if buf.len() < HEADER_SIZE {
+        return Err(Error::Encode(crate::codec::Error::BufferTooSmall));
+    }
+
+    let (header, payload) = buf.split_at_mut(HEADER_SIZE);
+    header.copy_from_slice(&get_header(
+        sequence,
+        timestamp_us,
+        message.get_message_type(),
+        codec,
+    ));
+
+    let n = match message {
+        Message::Input(input_message) => codec.encode_into(input_message, payload),
+        Message::Telemetry(telemetry_message) => codec.encode_into(telemetry_message, payload),
+        Message::Lease(lease_message) => codec.encode_into(lease_message, payload),
+        Message::Beacon(beacon_message) => codec.encode_into(beacon_message, payload),
+        Message::Ping(ping_message) => codec.encode_into(ping_message, payload),
+        Message::Control(control_message) => codec.encode_into(control_message, payload),
+        Message::Descriptor(vehicle_descriptor) => codec.encode_into(vehicle_descriptor, payload),
+        Message::DriveMode(drive_mode_message) => codec.encode_into(drive_mode_message, payload),
+        Message::Session(session_message) => codec.encode_into(session_message, payload),
+    }
+    .map_err(Error::Encode)?;
+
+    Ok(HEADER_SIZE + n)
+}
+
+pub fn decode(buf: &[u8]) -> Result<Envelope, Error> {
+    if buf.len() >= MAGIC.len() && buf[0..MAGIC.len()] != MAGIC {
+        // bare MessagePack arrays never start with the magic, so try the unframed format
+        if let Ok(envelope) = decode_legacy(buf) {
+            return Ok(envelope);
+        }
+
+        return Err(Error::BadMagic([buf[0], buf[1]]));
+    }
+
+    if buf.len() < V1_HEADER_SIZE {
+        return Err(Error::TooShort(buf.len()));
+    }
+
+    let version = buf[2];
+    let (flags, header_size) = match version {
+        1 => (0, V1_HEADER_SIZE),
+        2 => (0, V2_HEADER_SIZE),
+        PROTOCOL_VERSION => (*buf.get(FLAGS_OFFSET).unwrap_or(&0), HEADER_SIZE),
+        _ => return Err(Error::UnsupportedVersion(version)),
+    };
+
+    if flags & !(KNOWN_FLAGS | CODEC_MASK) != 0 {
+        return Err(Error::UnsupportedFlags(flags));
+    }
+
+    let codec = crate::codec::Codec::try_from((flags & CODEC_MASK) >> CODEC_SHIFT)
+        .map_err(Error::UnknownCodec)?;
+
+    let signed = flags & FLAG_SIGNED != 0;
+    let tag_size = if signed { crate::auth::TAG_SIZE } else { 0 };
+
+    if buf.len() < header_size + tag_size {
+        return Err(Error::TooShort(buf.len()));
+    }
+
+    let (sequence, timestamp_us) = if header_size > V1_HEADER_SIZE {
+        let sequence_offset = header_size - 16;
+        let timestamp_offset = header_size - 8;
+        (
+            Some(u64::from_be_bytes(
+                buf[sequence_offset..timestamp_offset].try_into().unwrap(),
+            )),
+            Some(u64::from_be_bytes(
+                buf[timestamp_offset..header_size].try_into().unwrap(),
+            )),
+        )
+    } else {
+        (None, None)
+    };
+
+    let message_type = MessageType::try_from(buf[3])?;
+
+    // the signature (if any) isn't checked here, see crate::auth::verify
+    let payload = &buf[header_size..buf.len() - tag_size];
+
+    let message = match message_type {
+        MessageType::Input => codec.decode(payload).map(Message::Input),
+        MessageType::Telemetry => codec.decode(payload).map(Message::Telemetry),
+        MessageType::Lease => codec.decode(payload).map(Message::Lease),
+        MessageType::Beacon => codec.decode(payload).map(Message::Beacon),
+        MessageType::Ping => codec.decode(payload).map(Message::Ping),
+        MessageType::Control => codec.decode(payload).map(Message::Control),
+        MessageType::Descriptor => codec.decode(payload).map(Message::Descriptor),
+        MessageType::DriveMode => codec.decode(payload).map(Message::DriveMode),
+        MessageType::Session => codec.decode(payload).map(Message::Session),
+    }
+    .map_err(Error::Decode)?;
+
+    Ok(Envelope {
+        version,
+        signed,
+        codec,
+        sequence,
+        timestamp_us,
+        message,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_input_message() -> crate::serialization::InputMessage {
+        crate::serialization::InputMessage {
+            throttle: 0.69,
+            steering: 0.69,
+            throttle_left: 0.69,
+            throttle_right: 0.69,
+            steering_left: 0.69,
+            steering_right: 0.69,
+            mode_up: true,
+            mode_down: true,
+            mode_left: true,
+            mode_right: true,
+            handbrake: true,
+        }
+    }
+
+    #[test]
+    fn encode_and_decode() -> anyhow::Result<()> {
+        let message = Message::Input(get_input_message());
+
+        let buf = encode(1337, 69, &message)?;
+        assert_eq!(buf[0..2], MAGIC);
+        assert_eq!(buf[2], PROTOCOL_VERSION);
+        assert_eq!(buf[3], MessageType::Input as u8);
+
+        let envelope = decode(&buf)?;
+        assert_eq!(envelope.version, PROTOCOL_VERSION);
+        assert!(!envelope.signed);
+        assert_eq!(envelope.sequence, Some(1337));
+        assert_eq!(envelope.timestamp_us, Some(69));
+        assert_eq!(envelope.message, message);
+
+        Ok(())
+    }
+
+    #[test]
+    fn encode_and_decode_telemetry() -> anyhow::Result<()> {
+        let message = Message::Telemetry(crate::serialization::TelemetryMessage {
+            throttle: 0.69,
+            steering: 0.69,
+            throttle_left: 0.69,
+            throttle_right: 0.69,
+            throttle_min: -0.69,
+            throttle_max: 0.69,
+            steering_offset: 0.01,
+            failsafe: true,
+            armed: true,
+            battery_voltage: Some(7.4),
+            drive_mode: Some("sport".into()),
+            link_stats: Some(crate::sequencing::LinkStats {
+                received: 1337,
+                ..Default::default()
+            }),
+        });
+
+        let buf = encode(1337, 69, &message)?;
+        assert_eq!(buf[3], MessageType::Telemetry as u8);
+
+        let envelope = decode(&buf)?;
+        assert_eq!(envelope.message, message);
+
+        Ok(())
+    }
+
+    #[test]
+    fn encode_and_decode_lease() -> anyhow::Result<()> {
+        for lease_message in [
+            crate::serialization::LeaseMessage::Release,
+            crate::serialization::LeaseMessage::HandOver(crate::address::Address::Socket(
+                "127.0.0.1:13337".parse()?,
+            )),
+            crate::serialization::LeaseMessage::Queued(2),
+        ] {
+            let message = Message::Lease(lease_message);
+
+            let buf = encode(1337, 69, &message)?;
+            assert_eq!(buf[3], MessageType::Lease as u8);
+
+            let envelope = decode(&buf)?;
+            assert_eq!(envelope.message, message);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn encode_into() -> anyhow::Result<()> {
+        for codec in [
+            crate::codec::Codec::MessagePack,
+            crate::codec::Codec::Postcard,
+            crate::codec::Codec::Cbor,
+            crate::codec::Codec::Json,
+        ] {
+            let message = Message::Input(get_input_message());
+
+            let mut buf = [0; 256];
+            let n = super::encode_into(1337, 69, &message, codec, &mut buf)?;
+            assert_eq!(
+                &buf[0..n],
+                encode_with_codec(1337, 69, &message, codec)?.as_slice()
+            );
+
+            assert!(super::encode_into(1337, 69, &message, codec, &mut buf[0..n - 1]).is_err());
+        }
+
+        let mut buf = [0; HEADER_SIZE - 1];
+        assert!(matches!(
+            super::encode_into(
+                1337,
+                69,
+                &Message::Input(get_input_message()),
+                Default::default(),
+                &mut buf
+            ),
+            Err(Error::Encode(crate::codec::Error::BufferTooSmall))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn encode_and_decode_ping() -> anyhow::Result<()> {
+        for ping_message in [
+            crate::serialization::PingMessage::Ping(1337),
+            crate::serialization::PingMessage::Pong(1337),
+        ] {
+            let message = Message::Ping(ping_message);
+
+            let buf = encode(1337, 69, &message)?;
+            assert_eq!(buf[3], MessageType::Ping as u8);
+
+            let envelope = decode(&buf)?;
+            assert_eq!(envelope.message, message);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn encode_and_decode_control_descriptor_drive_mode_and_session() -> anyhow::Result<()> {
+        for (message, message_type) in [
+            (
+                Message::Control(get_input_message().into()),
+                MessageType::Control,
+            ),
+            (
+                Message::Descriptor(crate::serialization::VehicleDescriptor::new_legacy(
+                    crate::serialization::VehicleKind::Drone,
+                )),
+                MessageType::Descriptor,
+            ),
+            (
+                Message::DriveMode(crate::serialization::DriveModeMessage::Set("crawl".into())),
+                MessageType::DriveMode,
+            ),
+            (
+                Message::DriveMode(crate::serialization::DriveModeMessage::Cycle),
+                MessageType::DriveMode,
+            ),
+            (
+                Message::Session(crate::serialization::SessionMessage::Request(1)),
+                MessageType::Session,
+            ),
+            (
+                Message::Session(crate::serialization::SessionMessage::Nonce {
+                    nonce: u64::MAX,
+                    challenge: 1,
+                }),
+                MessageType::Session,
+            ),
+            (
+                Message::Session(crate::serialization::SessionMessage::Expired(1337)),
+                MessageType::Session,
+            ),
+        ] {
+            let buf = encode(1337, 69, &message)?;
+            assert_eq!(buf[3], message_type as u8);
+
+            let envelope = decode(&buf)?;
+            assert_eq!(envelope.message, message);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn decode_version_2() -> anyhow::Result<()> {
+        let input_message = get_input_message();
+
+        let mut buf = vec![MAGIC[0], MAGIC[1], 2, MessageType::Input as u8];
+        buf.extend_from_slice(&1337_u64.to_be_bytes());
+        buf.extend_from_slice(&69_u64.to_be_bytes());
+        buf.extend(crate::serialization::serialize(&input_message)?);
+
+        let envelope = decode(&buf)?;
+        assert_eq!(envelope.version, 2);
+        assert!(!envelope.signed);
+        assert_eq!(envelope.sequence, Some(1337));
+        assert_eq!(envelope.timestamp_us, Some(69));
+        assert_eq!(envelope.message, Message::Input(input_message));
+
+        Ok(())
+    }
+
+    #[test]
+    fn decode_version_1() -> anyhow::Result<()> {
+        let input_message = get_input_message();
+
+        let mut buf = vec![MAGIC[0], MAGIC[1], 1, MessageType::Input as u8];
+        buf.extend(crate::serialization::serialize(&input_message)?);
+
+        let envelope = decode(&buf)?;
+        assert_eq!(envelope.version, 1);
+        assert_eq!(envelope.sequence, None);
+        assert_eq!(envelope.timestamp_us, None);
+        assert_eq!(envelope.message, Message::Input(input_message));
+
+        Ok(())
+    }
+
+    #[test]
+    fn decode_legacy_input_message() -> anyhow::Result<()> {
+        let input_message = get_input_message();
+
+        let buf = crate::serialization::serialize(&input_message)?;
+
+        let envelope = decode(&buf)?;
+        assert_eq!(envelope.version, LEGACY_PROTOCOL_VERSION);
+        assert_eq!(envelope.message, Message::Input(input_message));
+
+        Ok(())
+    }
+
+    #[test]
+    fn decode_legacy_input_message_without_steering_left_and_right() -> anyhow::Result<()> {
+        let legacy_input_message = LegacyInputMessage {
+            throttle: 0.69,
+            steering: 0.69,
+            throttle_left: 0.69,
+            throttle_right: 0.69,
+            mode_up: true,
+            mode_down: true,
+            mode_left: true,
+            mode_right: true,
+            handbrake: true,
+        };
+
+        let buf = crate::serialization::serialize(&legacy_input_message)?;
+
+        let mut expected_input_message = get_input_message();
+        expected_input_message.steering_left = 0.0;
+        expected_input_message.steering_right = 0.0;
+
+        let envelope = decode(&buf)?;
+        assert_eq!(envelope.version, LEGACY_PROTOCOL_VERSION);
+        assert_eq!(envelope.message, Message::Input(expected_input_message));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_bad_magic() {
+        let result = decode(&[0xde, 0xad, PROTOCOL_VERSION, MessageType::Input as u8]);
+        assert!(matches!(result, Err(Error::BadMagic([0xde, 0xad]))));
+    }
+
+    #[test]
+    fn reject_too_short() {
+        let result = decode(&[MAGIC[0], MAGIC[1], PROTOCOL_VERSION]);
+        assert!(matches!(result, Err(Error::TooShort(3))));
+
+        let result = decode(&[MAGIC[0], MAGIC[1], PROTOCOL_VERSION, 1, 0, 0, 0]);
+        assert!(matches!(result, Err(Error::TooShort(7))));
+    }
+
+    #[test]
+    fn reject_unsupported_version() -> anyhow::Result<()> {
+        let mut buf = encode(0, 0, &Message::Input(get_input_message()))?;
+        buf[2] = PROTOCOL_VERSION + 1;
+
+        let result = decode(&buf);
+        assert!(matches!(result, Err(Error::UnsupportedVersion(v)) if v == PROTOCOL_VERSION + 1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_unsupported_flags() -> anyhow::Result<()> {
+        let mut buf = encode(0, 0, &Message::Input(get_input_message()))?;
+        buf[FLAGS_OFFSET] = 0x02;
+
+        let result = decode(&buf);
+        assert!(matches!(result, Err(Error::UnsupportedFlags(0x02))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_unknown_codec() -> anyhow::Result<()> {
+        let mut buf = encode(0, 0, &Message::Input(get_input_message()))?;
+        buf[FLAGS_OFFSET] = 0x80;
+
+        let result = decode(&buf);
+        assert!(matches!(result, Err(Error::UnknownCodec(0x08))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn encode_and_decode_with_codec() -> anyhow::Result<()> {
+        let pre_shared_key = crate::auth::PreSharedKey::new(b"car123!@#");
+
+        for codec in [
+            crate::codec::Codec::MessagePack,
+            crate::codec::Codec::Postcard,
+            crate::codec::Codec::Cbor,
+            crate::codec::Codec::Json,
+        ] {
+            let message = Message::Input(get_input_message());
+
+            let mut buf = encode_with_codec(1337, 69, &message, codec)?;
+            crate::auth::sign(&pre_shared_key, &mut buf);
+
+            let envelope = decode(&buf)?;
+            assert_eq!(envelope.codec, codec);
+            assert!(envelope.signed);
+            assert_eq!(envelope.message, message);
+        }
+
+        // the default is byte for byte what it was before there was a choice
+        let buf = encode(1337, 69, &Message::Input(get_input_message()))?;
+        assert_eq!(buf[FLAGS_OFFSET], 0);
+        assert_eq!(
+            buf[HEADER_SIZE..],
+            crate::serialization::serialize(get_input_message())?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_unknown_message_type() -> anyhow::Result<()> {
+        let mut buf = encode(0, 0, &Message::Input(get_input_message()))?;
+        buf[3] = 0xff;
+
+        let result = decode(&buf);
+        assert!(matches!(result, Err(Error::UnknownMessageType(0xff))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn decode_random_bytes() {
+        let valid_buf = encode(1337, 69, &Message::Input(get_input_message())).unwrap();
+
+        // xorshift, so that any failure can be reproduced
+        let mut state: u64 = 0x5eed;
+        let mut get_random_u64 = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        // nothing here may panic, no matter how broken the datagram is
+        for _ in 0..10000 {
+            let n = (get_random_u64() % 64) as usize;
+            let buf: Vec<u8> = (0..n).map(|_| get_random_u64() as u8).collect();
+            _ = decode(&buf);
+
+            let mut buf = valid_buf.clone();
+            let offset = (get_random_u64() % buf.len() as u64) as usize;
+            buf[offset] ^= get_random_u64() as u8 | 0x01;
+            buf.truncate((get_random_u64() % (buf.len() as u64 + 1)) as usize);
+            _ = decode(&buf);
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use alloc::vec::Vec;
+
+// byte streams (serial, TCP) don't preserve datagram boundaries, so each datagram is framed as:
+//
+// SYNC (2 bytes) | length (u16 BE) | payload | CRC-16 (u16 BE, over length and payload)
+//
+// the sync bytes and CRC let the Decoder find its way back to a frame boundary after line noise or
+// after joining a stream part way through
+
+pub const SYNC: [u8; 2] = [0xa5, 0x5a];
+pub const HEADER_SIZE: usize = 4;
+pub const CRC_SIZE: usize = 2;
+pub const MAX_PAYLOAD_SIZE: usize = 4096;
+
+const CRC: crc::Crc<u16> = crc::Crc::<u16>::new(&crc::CRC_16_IBM_3740);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    TooLarge(usize),
+    BadCrc { expected: u16, actual: u16 },
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::TooLarge(n) => write!(
+                f,
+                "frame length {} is larger than {} bytes",
+                n, MAX_PAYLOAD_SIZE
+            ),
+            Error::BadCrc { expected, actual } => write!(
+                f,
+                "frame has bad crc expected={:#06x} actual={:#06x}",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+pub fn encode(payload: &[u8]) -> Result<Vec<u8>, Error> {
+    if payload.len() > MAX_PAYLOAD_SIZE {
+        return Err(Error::TooLarge(payload.len()));
+    }
+
+    let mut buf = Vec::with_capacity(HEADER_SIZE + payload.len() + CRC_SIZE);
+    buf.extend_from_slice(&SYNC);
+    buf.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    buf.extend_from_slice(payload);
+
+    let crc = CRC.checksum(&buf[SYNC.len()..]);
+    buf.extend_from_slice(&crc.to_be_bytes());
+
+    Ok(buf)
+}
+
+#[derive(Debug, Default)]
+pub struct Decoder {
+    buf: Vec<u8>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    // returns None until a whole frame has been pushed; an Err means some bytes were discarded
+    // and the caller should just carry on
+    pub fn next_frame(&mut self) -> Option<Result<Vec<u8>, Error>> {
+        // throw away anything before the next sync
+        match self.buf.windows(SYNC.len()).position(|w| w == SYNC) {
+            Some(0) => {}
+            Some(i) => {
+                self.buf.drain(0..i);
+            }
+            None => {
+                // keep a trailing half-sync, it might be completed by the next push
+                let keep = usize::from(self.buf.last() == Some(&SYNC[0]));
+                self.buf.drain(0..self.buf.len() - keep);
+                return None;
+            }
+        }
+
+        if self.buf.len() < HEADER_SIZE {
+            return None;
+        }
+
+        let n = u16::from_be_bytes([self.buf[2], self.buf[3]]) as usize;
+        if n > MAX_PAYLOAD_SIZE {
+            // can't be a real frame; resync from the next byte
+            self.buf.drain(0..1);
+            return Some(Err(Error::TooLarge(n)));
+        }
+
+        let frame_size = HEADER_SIZE + n + CRC_SIZE;
+        if self.buf.len() < frame_size {
+            return None;
+        }
+
+        let expected = CRC.checksum(&self.buf[SYNC.len()..HEADER_SIZE + n]);
+        let actual = u16::from_be_bytes([self.buf[frame_size - 2], self.buf[frame_size - 1]]);
+        if expected != actual {
+            self.buf.drain(0..1);
+            return Some(Err(Error::BadCrc { expected, actual }));
+        }
+
+        let payload = self.buf[HEADER_SIZE..HEADER_SIZE + n].to_vec();
+        self.buf.drain(0..frame_size);
+
+        Some(Ok(payload))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_and_decode() -> anyhow::Result<()> {
+        let mut decoder = Decoder::new();
+
+        decoder.push(&encode(b"hello")?);
+        decoder.push(&encode(b"")?);
+        decoder.push(&encode(b"world")?);
+
+        assert_eq!(decoder.next_frame(), Some(Ok(b"hello".to_vec())));
+        assert_eq!(decoder.next_frame(), Some(Ok(b"".to_vec())));
+        assert_eq!(decoder.next_frame(), Some(Ok(b"world".to_vec())));
+        assert_eq!(decoder.next_frame(), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn byte_at_a_time() -> anyhow::Result<()> {
+        let mut decoder = Decoder::new();
+
+        let mut frames = vec![];
+        for byte in encode(b"hello")?.iter().chain(encode(b"world")?.iter()) {
+            decoder.push(&[*byte]);
+            while let Some(frame) = decoder.next_frame() {
+                frames.push(frame?);
+            }
+        }
+
+        assert_eq!(frames, vec![b"hello".to_vec(), b"world".to_vec()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn resync_after_noise() -> anyhow::Result<()> {
+        let mut decoder = Decoder::new();
+
+        let mut corrupted = encode(b"corrupted")?;
+        corrupted[6] ^= 0xff;
+
+        decoder.push(&[0x00, 0xa5, 0xff, 0xa5]);
+        decoder.push(&corrupted);
+        decoder.push(&[0xa5, 0x5a, 0xff, 0xff]);
+        decoder.push(&encode(b"hello")?);
+
+        let mut frames = vec![];
+        let mut errors = 0;
+        while let Some(frame) = decoder.next_frame() {
+            match frame {
+                Ok(frame) => frames.push(frame),
+                Err(_) => errors += 1,
+            }
+        }
+
+        assert_eq!(frames, vec![b"hello".to_vec()]);
+        assert_eq!(errors, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_too_large() {
+        assert_eq!(
+            encode(&vec![0; MAX_PAYLOAD_SIZE + 1]),
+            Err(Error::TooLarge(MAX_PAYLOAD_SIZE + 1))
+        );
+    }
+}
@@ -0,0 +1,486 @@
+// a UDP proxy that sits between a Client and a Server and makes the link between them as bad as
+// asked for, so that tests can show how everything copes with (say) a vehicle at the edge of WiFi
+// range; datagrams from the Server go back to whichever address last sent something
+
+// how often the closed flag is checked and delayed datagrams are sent
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(1);
+
+// how much longer than everything else a reordered datagram is held back for
+const REORDER_DELAY: std::time::Duration = std::time::Duration::from_millis(20);
+
+const BUF_SIZE: usize = 2048;
+
+// probabilities are from 0.0 (never) to 1.0 (every datagram) and apply in both directions
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Impairment {
+    pub loss: f64,
+    pub latency: std::time::Duration,
+    // up to this much more latency, at random (which reorders datagrams too if it's big enough)
+    pub jitter: std::time::Duration,
+    pub duplication: f64,
+    // held back for long enough that those sent after it arrive first
+    pub reordering: f64,
+    // one bit flipped somewhere in the datagram
+    pub corruption: f64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImpairmentStats {
+    pub received: u64,
+    pub forwarded: u64,
+    pub dropped: u64,
+    pub duplicated: u64,
+    pub reordered: u64,
+    pub corrupted: u64,
+}
+
+struct Delayed {
+    due_at: std::time::Instant,
+    buf: Vec<u8>,
+    address: std::net::SocketAddr,
+}
+
+// xorshift; plenty for deciding what to break
+struct Random(u64);
+
+impl Random {
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    // from 0.0 up to (but not including) 1.0
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        self.next_f64() < probability
+    }
+}
+
+pub struct Proxy {
+    socket: std::net::UdpSocket,
+    upstream_address: std::net::SocketAddr,
+    impairment: std::sync::Arc<std::sync::Mutex<Impairment>>,
+    stats: std::sync::Arc<std::sync::Mutex<ImpairmentStats>>,
+    closed: std::sync::Arc<std::sync::Mutex<bool>>,
+    seed: u64,
+}
+
+impl Proxy {
+    // upstream_address is the Server's; point the Client at get_bind_address()
+    pub fn new(
+        bind_address: std::net::SocketAddr,
+        upstream_address: std::net::SocketAddr,
+    ) -> Result<Self, anyhow::Error> {
+        let socket = std::net::UdpSocket::bind(bind_address)?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+
+        Ok(Self {
+            socket,
+            upstream_address,
+            impairment: std::sync::Arc::new(std::sync::Mutex::new(Impairment::default())),
+            stats: std::sync::Arc::new(std::sync::Mutex::new(ImpairmentStats::default())),
+            closed: std::sync::Arc::new(std::sync::Mutex::new(false)),
+            seed: crate::envelope::get_timestamp_us() | 1,
+        })
+    }
+
+    // for repeatable runs; must not be 0
+    pub fn set_seed(&mut self, seed: u64) {
+        assert!(seed != 0);
+        self.seed = seed;
+    }
+
+    pub fn set_impairment(&mut self, impairment: Impairment) {
+        *self.impairment.lock().unwrap() = impairment;
+    }
+
+    // for changing how bad the link is while the Proxy is running (e.g. to cut it off entirely)
+    pub fn get_impairment_setter(&self) -> impl Fn(Impairment) {
+        let impairment = std::sync::Arc::clone(&self.impairment);
+        move |new_impairment| {
+            *impairment.lock().unwrap() = new_impairment;
+        }
+    }
+
+    pub fn get_stats_reader(&self) -> impl Fn() -> ImpairmentStats {
+        let stats = std::sync::Arc::clone(&self.stats);
+        move || stats.lock().unwrap().clone()
+    }
+
+    pub fn get_closer(&self) -> impl Fn() {
+        let closed = std::sync::Arc::clone(&self.closed);
+        move || {
+            let mut closed = closed.lock().unwrap();
+            *closed = true;
+        }
+    }
+
+    pub fn get_bind_address(&self) -> Result<std::net::SocketAddr, anyhow::Error> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    fn impair(
+        &self,
+        random: &mut Random,
+        buf: &[u8],
+        address: std::net::SocketAddr,
+        delayed: &mut Vec<Delayed>,
+    ) {
+        let impairment = self.impairment.lock().unwrap().clone();
+        let mut stats = self.stats.lock().unwrap();
+
+        stats.received += 1;
+
+        if random.chance(impairment.loss) {
+            stats.dropped += 1;
+            return;
+        }
+
+        let copies = if random.chance(impairment.duplication) {
+            stats.duplicated += 1;
+            2
+        } else {
+            1
+        };
+
+        for _ in 0..copies {
+            let mut buf = buf.to_vec();
+
+            if !buf.is_empty() && random.chance(impairment.corruption) {
+                let i = random.next_u64() as usize % buf.len();
+                buf[i] ^= 1 << (random.next_u64() % 8);
+                stats.corrupted += 1;
+            }
+
+            let mut delay = impairment.latency + impairment.jitter.mul_f64(random.next_f64());
+
+            if random.chance(impairment.reordering) {
+                delay = impairment.latency + impairment.jitter + REORDER_DELAY;
+                stats.reordered += 1;
+            }
+
+            delayed.push(Delayed {
+                due_at: std::time::Instant::now() + delay,
+                buf,
+                address,
+            });
+        }
+    }
+
+    fn send_due(&self, delayed: &mut Vec<Delayed>) {
+        let now = std::time::Instant::now();
+
+        // in the order they fell due, which isn't necessarily the order they arrived in
+        delayed.sort_by_key(|delayed| delayed.due_at);
+
+        let due = delayed
+            .iter()
+            .take_while(|delayed| delayed.due_at <= now)
+            .count();
+
+        for delayed in delayed.drain(0..due) {
+            if let Err(err) = self.socket.send_to(&delayed.buf, delayed.address) {
+                println!("send_to() failed because err={:?}", err);
+                continue;
+            }
+
+            self.stats.lock().unwrap().forwarded += 1;
+        }
+    }
+
+    pub fn run(&self) -> anyhow::Result<()> {
+        let mut buf = vec![0; BUF_SIZE];
+
+        let mut random = Random(self.seed);
+
+        let mut delayed: Vec<Delayed> = vec![];
+
+        // nowhere to send anything from upstream until the Client has been heard from
+        let mut downstream_address: Option<std::net::SocketAddr> = None;
+
+        loop {
+            if *self.closed.lock().unwrap() {
+                break;
+            }
+
+            self.send_due(&mut delayed);
+
+            let (n, address) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    continue;
+                }
+                // e.g. the Server isn't up yet; that's for the Client to find out
+                Err(err) if err.kind() == std::io::ErrorKind::ConnectionRefused => {
+                    continue;
+                }
+                Err(err) => {
+                    println!("recv_from() failed because err={:?}", err);
+                    return Err(err.into());
+                }
+            };
+
+            let address = if address == self.upstream_address {
+                let Some(downstream_address) = downstream_address else {
+                    continue;
+                };
+
+                downstream_address
+            } else {
+                downstream_address = Some(address);
+
+                self.upstream_address
+            };
+
+            self.impair(&mut random, &buf[0..n], address, &mut delayed);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Client -> Proxy -> Server, signed so that corrupted datagrams can't get through
+    struct TestResources {
+        outgoing_input_message_sender: std::sync::mpsc::Sender<crate::serialization::InputMessage>,
+        incoming_input_message_receiver:
+            std::sync::mpsc::Receiver<crate::serialization::InputMessage>,
+        impairment_stats_reader: Box<dyn Fn() -> ImpairmentStats>,
+        impairment_setter: Box<dyn Fn(Impairment)>,
+        link_stats_reader: Box<dyn Fn() -> Vec<crate::sequencing::LinkStats>>,
+        closers: Vec<Box<dyn Fn()>>,
+        handles: Vec<std::thread::JoinHandle<()>>,
+    }
+
+    impl TestResources {
+        fn new(impairment: Impairment) -> anyhow::Result<Self> {
+            let (incoming_input_message_sender, incoming_input_message_receiver) =
+                std::sync::mpsc::channel();
+
+            let pre_shared_key = crate::auth::PreSharedKey::new(b"car123!@#");
+
+            let mut server = crate::transport::Server::new(
+                "127.0.0.1:0".parse()?,
+                incoming_input_message_sender,
+            )?;
+            server.set_pre_shared_key(pre_shared_key.clone());
+
+            let mut proxy = Proxy::new(
+                "127.0.0.1:0".parse()?,
+                server.get_bind_address().get_socket_address().unwrap(),
+            )?;
+            proxy.set_seed(1337);
+            proxy.set_impairment(impairment);
+
+            let mut client = crate::transport::Client::new(proxy.get_bind_address()?)?;
+            client.set_ping_interval(None);
+            client.set_pre_shared_key(pre_shared_key);
+
+            let link_stats_reader = server.get_link_stats_reader();
+
+            Ok(Self {
+                outgoing_input_message_sender: client.get_outgoing_input_message_sender(),
+                incoming_input_message_receiver,
+                impairment_stats_reader: Box::new(proxy.get_stats_reader()),
+                impairment_setter: Box::new(proxy.get_impairment_setter()),
+                link_stats_reader: Box::new(move || link_stats_reader().into_values().collect()),
+                closers: vec![
+                    Box::new(client.get_closer()),
+                    Box::new(proxy.get_closer()),
+                    Box::new(server.get_closer()),
+                ],
+                handles: vec![
+                    std::thread::spawn(move || client.run().unwrap()),
+                    std::thread::spawn(move || proxy.run().unwrap()),
+                    std::thread::spawn(move || server.run().unwrap()),
+                ],
+            })
+        }
+
+        fn close(&mut self) {
+            for closer in self.closers.iter() {
+                closer();
+            }
+
+            for handle in self.handles.drain(..) {
+                handle.join().unwrap();
+            }
+        }
+    }
+
+    fn get_input_message(throttle: f32) -> crate::serialization::InputMessage {
+        crate::serialization::InputMessage {
+            throttle,
+            steering: 0.0,
+            throttle_left: 0.0,
+            throttle_right: 0.0,
+            steering_left: 0.0,
+            steering_right: 0.0,
+            mode_up: false,
+            mode_down: false,
+            mode_left: false,
+            mode_right: false,
+            handbrake: false,
+        }
+    }
+
+    #[test]
+    fn random() {
+        let mut random = Random(1337);
+
+        let n = 10_000;
+        let hits = (0..n).filter(|_| random.chance(0.25)).count();
+        assert!((2_000..3_000).contains(&hits));
+
+        assert!(!(0..n).any(|_| random.chance(0.0)));
+        assert!((0..n).all(|_| random.chance(1.0)));
+    }
+
+    #[test]
+    fn unimpaired() -> anyhow::Result<()> {
+        let mut test_resources = TestResources::new(Impairment::default())?;
+
+        for i in 0..10 {
+            test_resources
+                .outgoing_input_message_sender
+                .send(get_input_message(i as f32))?;
+            assert_eq!(
+                test_resources
+                    .incoming_input_message_receiver
+                    .recv_timeout(std::time::Duration::from_secs(1))?,
+                get_input_message(i as f32)
+            );
+        }
+
+        test_resources.close();
+
+        let impairment_stats = (test_resources.impairment_stats_reader)();
+        assert!(impairment_stats.received >= 10);
+        assert_eq!(impairment_stats.dropped, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn latency() -> anyhow::Result<()> {
+        let latency = std::time::Duration::from_millis(100);
+
+        let mut test_resources = TestResources::new(Impairment {
+            latency,
+            ..Default::default()
+        })?;
+
+        let sent_at = std::time::Instant::now();
+        test_resources
+            .outgoing_input_message_sender
+            .send(get_input_message(1.0))?;
+        test_resources
+            .incoming_input_message_receiver
+            .recv_timeout(std::time::Duration::from_secs(1))?;
+        assert!(sent_at.elapsed() >= latency);
+
+        test_resources.close();
+
+        Ok(())
+    }
+
+    #[test]
+    fn loss() -> anyhow::Result<()> {
+        let mut test_resources = TestResources::new(Impairment::default())?;
+
+        // a Client sends nothing until it's in the Server's session, so the link has to be up
+        // before it's cut
+        test_resources
+            .outgoing_input_message_sender
+            .send(get_input_message(-1.0))?;
+        test_resources
+            .incoming_input_message_receiver
+            .recv_timeout(std::time::Duration::from_secs(1))?;
+
+        (test_resources.impairment_setter)(Impairment {
+            loss: 1.0,
+            ..Default::default()
+        });
+
+        // for anything the Proxy was still holding on to to have gone
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        let cut_impairment_stats = (test_resources.impairment_stats_reader)();
+
+        for i in 0..10 {
+            test_resources
+                .outgoing_input_message_sender
+                .send(get_input_message(i as f32))?;
+        }
+
+        assert!(test_resources
+            .incoming_input_message_receiver
+            .recv_timeout(std::time::Duration::from_millis(200))
+            .is_err());
+
+        test_resources.close();
+
+        let impairment_stats = (test_resources.impairment_stats_reader)();
+        assert!(impairment_stats.dropped - cut_impairment_stats.dropped >= 10);
+        assert_eq!(impairment_stats.forwarded, cut_impairment_stats.forwarded);
+
+        Ok(())
+    }
+
+    #[test]
+    fn duplication_reordering_and_corruption() -> anyhow::Result<()> {
+        let mut test_resources = TestResources::new(Impairment {
+            jitter: std::time::Duration::from_millis(10),
+            duplication: 0.5,
+            reordering: 0.2,
+            corruption: 0.1,
+            ..Default::default()
+        })?;
+
+        for i in 0..100 {
+            test_resources
+                .outgoing_input_message_sender
+                .send(get_input_message(i as f32))?;
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        test_resources.close();
+
+        let impairment_stats = (test_resources.impairment_stats_reader)();
+        assert!(impairment_stats.duplicated > 0);
+        assert!(impairment_stats.reordered > 0);
+        assert!(impairment_stats.corrupted > 0);
+
+        // the Server threw away the duplicates and the stragglers, so nothing went backwards...
+        let throttles: Vec<f32> = test_resources
+            .incoming_input_message_receiver
+            .try_iter()
+            .map(|input_message| input_message.throttle)
+            .collect();
+        assert!(throttles.len() > 50);
+        assert!(throttles.windows(2).all(|pair| pair[0] < pair[1]));
+
+        // ...and counted them
+        let link_stats = (test_resources.link_stats_reader)();
+        assert_eq!(link_stats.len(), 1);
+        assert!(link_stats[0].duplicated > 0);
+        assert!(link_stats[0].reordered > 0);
+        assert!(link_stats[0].bad_signature > 0);
+
+        Ok(())
+    }
+}
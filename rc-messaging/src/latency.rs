@@ -0,0 +1,212 @@
+// a Client pings the Server every PING_INTERVAL and the Server echoes each ping straight back; the
+// stats cover the last WINDOW_SIZE pings, so they recover quickly once a bad patch is over
+pub const PING_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+
+// a ping that hasn't been answered after this long counts as lost (and a late pong is ignored)
+pub const PING_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+const WINDOW_SIZE: usize = 20;
+
+// past any of these a driver should be told the link is degraded
+pub const DEGRADED_RTT: std::time::Duration = std::time::Duration::from_millis(150);
+pub const DEGRADED_JITTER: std::time::Duration = std::time::Duration::from_millis(50);
+pub const DEGRADED_LOSS_PERCENT: f32 = 10.0;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LatencyStats {
+    pub sent: u64,
+    pub received: u64,
+    // the mean round trip time of the answered pings in the window
+    pub rtt: Option<std::time::Duration>,
+    // the mean difference between consecutive round trip times in the window
+    pub jitter: Option<std::time::Duration>,
+    // of the pings in the window that have been answered or timed out
+    pub loss_percent: f32,
+}
+
+impl LatencyStats {
+    pub fn is_degraded(&self) -> bool {
+        self.rtt.is_some_and(|rtt| rtt > DEGRADED_RTT)
+            || self.jitter.is_some_and(|jitter| jitter > DEGRADED_JITTER)
+            || self.loss_percent > DEGRADED_LOSS_PERCENT
+    }
+}
+
+#[derive(Debug)]
+struct Ping {
+    id: u64,
+    sent_at: std::time::Instant,
+    rtt: Option<std::time::Duration>,
+}
+
+#[derive(Debug, Default)]
+pub struct LatencyTracker {
+    next_id: u64,
+    sent: u64,
+    received: u64,
+    window: std::collections::VecDeque<Ping>,
+}
+
+impl LatencyTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // returns the id to send in a PingMessage::Ping
+    pub fn ping(&mut self, now: std::time::Instant) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.sent += 1;
+
+        if self.window.len() >= WINDOW_SIZE {
+            self.window.pop_front();
+        }
+
+        self.window.push_back(Ping {
+            id,
+            sent_at: now,
+            rtt: None,
+        });
+
+        id
+    }
+
+    // returns false for a pong that's unknown, late or a duplicate
+    pub fn pong(&mut self, id: u64, now: std::time::Instant) -> bool {
+        let Some(ping) = self.window.iter_mut().find(|ping| ping.id == id) else {
+            return false;
+        };
+
+        if ping.rtt.is_some() {
+            return false;
+        }
+
+        let rtt = now.saturating_duration_since(ping.sent_at);
+        if rtt > PING_TIMEOUT {
+            return false;
+        }
+
+        ping.rtt = Some(rtt);
+        self.received += 1;
+
+        true
+    }
+
+    pub fn get_stats(&self, now: std::time::Instant) -> LatencyStats {
+        let rtts: Vec<std::time::Duration> =
+            self.window.iter().filter_map(|ping| ping.rtt).collect();
+
+        // pings still waiting for a pong don't count either way
+        let lost = self
+            .window
+            .iter()
+            .filter(|ping| {
+                ping.rtt.is_none() && now.saturating_duration_since(ping.sent_at) > PING_TIMEOUT
+            })
+            .count();
+
+        let resolved = rtts.len() + lost;
+
+        let rtt = (!rtts.is_empty())
+            .then(|| rtts.iter().sum::<std::time::Duration>() / rtts.len() as u32);
+
+        let jitter = (rtts.len() > 1).then(|| {
+            rtts.windows(2)
+                .map(|w| w[0].abs_diff(w[1]))
+                .sum::<std::time::Duration>()
+                / (rtts.len() - 1) as u32
+        });
+
+        let loss_percent = if resolved > 0 {
+            lost as f32 / resolved as f32 * 100.0
+        } else {
+            0.0
+        };
+
+        LatencyStats {
+            sent: self.sent,
+            received: self.received,
+            rtt,
+            jitter,
+            loss_percent,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> std::time::Duration {
+        std::time::Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn rtt_and_jitter() {
+        let mut latency_tracker = LatencyTracker::new();
+        let now = std::time::Instant::now();
+
+        assert_eq!(latency_tracker.get_stats(now), LatencyStats::default());
+
+        for (i, rtt) in [10, 30, 20].into_iter().enumerate() {
+            let sent_at = now + PING_INTERVAL * i as u32;
+            let id = latency_tracker.ping(sent_at);
+            assert!(latency_tracker.pong(id, sent_at + ms(rtt)));
+        }
+
+        let stats = latency_tracker.get_stats(now + ms(1000));
+        assert_eq!(stats.sent, 3);
+        assert_eq!(stats.received, 3);
+        assert_eq!(stats.rtt, Some(ms(20)));
+        assert_eq!(stats.jitter, Some(ms(15)));
+        assert_eq!(stats.loss_percent, 0.0);
+        assert!(!stats.is_degraded());
+    }
+
+    #[test]
+    fn loss() {
+        let mut latency_tracker = LatencyTracker::new();
+        let now = std::time::Instant::now();
+
+        let ids: Vec<u64> = (0..4).map(|_| latency_tracker.ping(now)).collect();
+        assert!(latency_tracker.pong(ids[0], now + ms(10)));
+
+        // unanswered but not yet timed out
+        let stats = latency_tracker.get_stats(now + ms(500));
+        assert_eq!(stats.loss_percent, 0.0);
+
+        // late, duplicate and unknown pongs are ignored
+        assert!(latency_tracker.pong(ids[1], now + ms(20)));
+        assert!(!latency_tracker.pong(ids[1], now + ms(20)));
+        assert!(!latency_tracker.pong(ids[2], now + PING_TIMEOUT + ms(1)));
+        assert!(!latency_tracker.pong(1337, now + ms(20)));
+
+        let stats = latency_tracker.get_stats(now + PING_TIMEOUT + ms(1));
+        assert_eq!(stats.sent, 4);
+        assert_eq!(stats.received, 2);
+        assert_eq!(stats.loss_percent, 50.0);
+        assert!(stats.is_degraded());
+    }
+
+    #[test]
+    fn window() {
+        let mut latency_tracker = LatencyTracker::new();
+        let now = std::time::Instant::now();
+
+        // a slow patch...
+        for _ in 0..WINDOW_SIZE {
+            let id = latency_tracker.ping(now);
+            latency_tracker.pong(id, now + ms(500));
+        }
+        assert!(latency_tracker.get_stats(now + ms(500)).is_degraded());
+
+        // ...that's forgotten once a window's worth of good pings have come back
+        for _ in 0..WINDOW_SIZE {
+            let id = latency_tracker.ping(now);
+            latency_tracker.pong(id, now + ms(5));
+        }
+        let stats = latency_tracker.get_stats(now + ms(5));
+        assert_eq!(stats.rtt, Some(ms(5)));
+        assert!(!stats.is_degraded());
+    }
+}
@@ -1,2 +1,29 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod address;
+pub mod auth;
+#[cfg(feature = "std")]
+pub mod clock;
+pub mod codec;
+#[cfg(feature = "std")]
+pub mod diagnostics;
+#[cfg(feature = "std")]
+pub mod discovery;
+pub mod envelope;
+pub mod framing;
+#[cfg(feature = "std")]
+pub mod impairment;
+#[cfg(feature = "std")]
+pub mod latency;
+#[cfg(feature = "std")]
+pub mod ownership;
+#[cfg(feature = "std")]
+pub mod recording;
+pub mod sequencing;
 pub mod serialization;
+#[cfg(feature = "std")]
+pub mod session;
+#[cfg(feature = "std")]
 pub mod transport;
@@ -0,0 +1,316 @@
+// only one controller may drive a vehicle at a time; the first to send an (accepted) InputMessage
+// holds the lease until it releases it, hands it over or goes quiet for longer than the lease
+// timeout, and everybody else waits in line
+pub const DEFAULT_LEASE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+// how often a waiting controller is reminded that it's waiting
+const NOTIFY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+// don't let a flood of source addresses eat all our memory
+const MAX_WAITING: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Accept,
+    Queued { position: usize, notify: bool },
+}
+
+#[derive(Debug)]
+struct Lease {
+    address: crate::transport::Address,
+    last_seen_at: std::time::Instant,
+}
+
+#[derive(Debug)]
+struct Waiting {
+    address: crate::transport::Address,
+    last_seen_at: std::time::Instant,
+    last_notified_at: Option<std::time::Instant>,
+}
+
+#[derive(Debug)]
+pub struct Ownership {
+    lease_timeout: std::time::Duration,
+    owner: Option<Lease>,
+    waiting: std::collections::VecDeque<Waiting>,
+}
+
+impl Ownership {
+    pub fn new(lease_timeout: std::time::Duration) -> Self {
+        Self {
+            lease_timeout,
+            owner: None,
+            waiting: std::collections::VecDeque::new(),
+        }
+    }
+
+    pub fn get_owner(&self) -> Option<&crate::transport::Address> {
+        self.owner.as_ref().map(|lease| &lease.address)
+    }
+
+    pub fn get_waiting(&self) -> Vec<crate::transport::Address> {
+        self.waiting
+            .iter()
+            .map(|waiting| waiting.address.clone())
+            .collect()
+    }
+
+    fn is_expired(&self, last_seen_at: std::time::Instant, now: std::time::Instant) -> bool {
+        now.saturating_duration_since(last_seen_at) > self.lease_timeout
+    }
+
+    // forget about anybody that's gone quiet and give the lease to whoever has waited longest
+    pub fn expire(&mut self, now: std::time::Instant) {
+        if self
+            .owner
+            .as_ref()
+            .is_some_and(|lease| self.is_expired(lease.last_seen_at, now))
+        {
+            self.owner = None;
+        }
+
+        let lease_timeout = self.lease_timeout;
+        self.waiting
+            .retain(|waiting| now.saturating_duration_since(waiting.last_seen_at) <= lease_timeout);
+
+        if self.owner.is_none() {
+            self.owner = self.waiting.pop_front().map(|waiting| Lease {
+                address: waiting.address,
+                last_seen_at: waiting.last_seen_at,
+            });
+        }
+    }
+
+    pub fn check(
+        &mut self,
+        address: &crate::transport::Address,
+        now: std::time::Instant,
+    ) -> Verdict {
+        self.expire(now);
+
+        match &mut self.owner {
+            Some(lease) if lease.address == *address => {
+                lease.last_seen_at = now;
+                return Verdict::Accept;
+            }
+            Some(_) => {}
+            None => {
+                self.owner = Some(Lease {
+                    address: address.clone(),
+                    last_seen_at: now,
+                });
+                return Verdict::Accept;
+            }
+        }
+
+        let position = match self
+            .waiting
+            .iter()
+            .position(|waiting| waiting.address == *address)
+        {
+            Some(position) => position,
+            None => {
+                if self.waiting.len() >= MAX_WAITING {
+                    self.waiting.pop_back();
+                }
+
+                self.waiting.push_back(Waiting {
+                    address: address.clone(),
+                    last_seen_at: now,
+                    last_notified_at: None,
+                });
+
+                self.waiting.len() - 1
+            }
+        };
+
+        let waiting = &mut self.waiting[position];
+        waiting.last_seen_at = now;
+
+        let notify = waiting
+            .last_notified_at
+            .is_none_or(|last_notified_at| now - last_notified_at >= NOTIFY_INTERVAL);
+        if notify {
+            waiting.last_notified_at = Some(now);
+        }
+
+        Verdict::Queued {
+            position: position + 1,
+            notify,
+        }
+    }
+
+    // returns false if address didn't hold the lease
+    pub fn release(
+        &mut self,
+        address: &crate::transport::Address,
+        now: std::time::Instant,
+    ) -> bool {
+        self.waiting.retain(|waiting| waiting.address != *address);
+
+        if self.get_owner() != Some(address) {
+            return false;
+        }
+
+        self.owner = None;
+        self.expire(now);
+
+        true
+    }
+
+    // returns false if address didn't hold the lease or to isn't waiting for it
+    pub fn hand_over(
+        &mut self,
+        address: &crate::transport::Address,
+        to: &crate::transport::Address,
+        now: std::time::Instant,
+    ) -> bool {
+        self.expire(now);
+
+        if self.get_owner() != Some(address) {
+            return false;
+        }
+
+        let Some(position) = self
+            .waiting
+            .iter()
+            .position(|waiting| waiting.address == *to)
+        else {
+            return false;
+        };
+
+        let waiting = self.waiting.remove(position).unwrap();
+        self.owner = Some(Lease {
+            address: waiting.address,
+            last_seen_at: now,
+        });
+
+        true
+    }
+}
+
+impl Default for Ownership {
+    fn default() -> Self {
+        Self::new(DEFAULT_LEASE_TIMEOUT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_addresses() -> anyhow::Result<[crate::transport::Address; 3]> {
+        Ok([
+            crate::transport::Address::Socket("127.0.0.1:1".parse()?),
+            crate::transport::Address::Socket("127.0.0.1:2".parse()?),
+            crate::transport::Address::Socket("127.0.0.1:3".parse()?),
+        ])
+    }
+
+    #[test]
+    fn first_come_first_served() -> anyhow::Result<()> {
+        let [a, b, c] = get_addresses()?;
+        let mut ownership = Ownership::default();
+        let now = std::time::Instant::now();
+
+        assert_eq!(ownership.check(&a, now), Verdict::Accept);
+        assert_eq!(
+            ownership.check(&b, now),
+            Verdict::Queued {
+                position: 1,
+                notify: true
+            }
+        );
+        assert_eq!(
+            ownership.check(&c, now),
+            Verdict::Queued {
+                position: 2,
+                notify: true
+            }
+        );
+        assert_eq!(
+            ownership.check(&b, now),
+            Verdict::Queued {
+                position: 1,
+                notify: false
+            }
+        );
+        assert_eq!(ownership.check(&a, now), Verdict::Accept);
+
+        assert_eq!(ownership.get_owner(), Some(&a));
+        assert_eq!(ownership.get_waiting(), vec![b, c]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn release() -> anyhow::Result<()> {
+        let [a, b, c] = get_addresses()?;
+        let mut ownership = Ownership::default();
+        let now = std::time::Instant::now();
+
+        ownership.check(&a, now);
+        ownership.check(&b, now);
+        ownership.check(&c, now);
+
+        // only the owner can release
+        assert!(!ownership.release(&c, now));
+        assert_eq!(ownership.get_owner(), Some(&a));
+        assert_eq!(ownership.get_waiting(), vec![b.clone()]);
+
+        assert!(ownership.release(&a, now));
+        assert_eq!(ownership.get_owner(), Some(&b));
+        assert!(matches!(ownership.check(&a, now), Verdict::Queued { .. }));
+        assert_eq!(ownership.check(&b, now), Verdict::Accept);
+
+        Ok(())
+    }
+
+    #[test]
+    fn hand_over() -> anyhow::Result<()> {
+        let [a, b, c] = get_addresses()?;
+        let mut ownership = Ownership::default();
+        let now = std::time::Instant::now();
+
+        ownership.check(&a, now);
+        ownership.check(&b, now);
+
+        // only the owner can hand over, and only to somebody that's waiting
+        assert!(!ownership.hand_over(&b, &b, now));
+        assert!(!ownership.hand_over(&a, &c, now));
+        assert_eq!(ownership.get_owner(), Some(&a));
+
+        assert!(ownership.hand_over(&a, &b, now));
+        assert_eq!(ownership.get_owner(), Some(&b));
+        assert!(ownership.get_waiting().is_empty());
+        assert!(matches!(ownership.check(&a, now), Verdict::Queued { .. }));
+
+        Ok(())
+    }
+
+    #[test]
+    fn timeout() -> anyhow::Result<()> {
+        let [a, b, c] = get_addresses()?;
+        let mut ownership = Ownership::default();
+        let now = std::time::Instant::now();
+
+        ownership.check(&a, now);
+        ownership.check(&b, now);
+        ownership.check(&c, now);
+
+        // c keeps asking but b goes quiet too
+        let now = now + DEFAULT_LEASE_TIMEOUT / 2;
+        ownership.check(&c, now);
+
+        let now = now + DEFAULT_LEASE_TIMEOUT;
+        ownership.expire(now);
+        assert_eq!(ownership.get_owner(), Some(&c));
+        assert!(ownership.get_waiting().is_empty());
+
+        let now = now + DEFAULT_LEASE_TIMEOUT * 2;
+        assert_eq!(ownership.check(&a, now), Verdict::Accept);
+        assert_eq!(ownership.get_owner(), Some(&a));
+
+        Ok(())
+    }
+}
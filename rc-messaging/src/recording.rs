@@ -0,0 +1,254 @@
+// a recording is a sequence of crate::framing frames, each holding a MessagePack Record; framing
+// means a recording cut short (e.g. by pulling the power) can still be played back up to the cut
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Record {
+    // since the first Record
+    pub elapsed_us: u64,
+    pub input_message: crate::serialization::InputMessage,
+}
+
+pub struct Recorder {
+    writer: Box<dyn std::io::Write + Send>,
+    started_at: Option<std::time::Instant>,
+}
+
+impl Recorder {
+    pub fn new(writer: Box<dyn std::io::Write + Send>) -> Self {
+        Self {
+            writer,
+            started_at: None,
+        }
+    }
+
+    pub fn create(path: impl AsRef<std::path::Path>) -> Result<Self, anyhow::Error> {
+        let file = std::fs::File::create(path)?;
+
+        Ok(Self::new(Box::new(std::io::BufWriter::new(file))))
+    }
+
+    pub fn record(
+        &mut self,
+        input_message: &crate::serialization::InputMessage,
+    ) -> Result<(), anyhow::Error> {
+        self.record_at(std::time::Instant::now(), input_message)
+    }
+
+    pub fn record_at(
+        &mut self,
+        at: std::time::Instant,
+        input_message: &crate::serialization::InputMessage,
+    ) -> Result<(), anyhow::Error> {
+        let started_at = *self.started_at.get_or_insert(at);
+
+        let record = Record {
+            elapsed_us: at.saturating_duration_since(started_at).as_micros() as u64,
+            input_message: input_message.clone(),
+        };
+
+        let buf = crate::framing::encode(&crate::serialization::serialize(&record)?)?;
+        self.writer.write_all(&buf)?;
+
+        // so that as little as possible is lost if we never get to stop cleanly
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+// reads every Record that can be read, skipping any that have been corrupted
+pub fn read(reader: &mut dyn std::io::Read) -> Result<Vec<Record>, anyhow::Error> {
+    let mut buf = vec![];
+    reader.read_to_end(&mut buf)?;
+
+    let mut decoder = crate::framing::Decoder::new();
+    decoder.push(&buf);
+
+    let mut records = vec![];
+
+    while let Some(frame) = decoder.next_frame() {
+        let record = match frame.map_err(anyhow::Error::from).and_then(|frame| {
+            crate::serialization::deserialize_from_slice::<Record>(&frame).map_err(|err| err.into())
+        }) {
+            Ok(record) => record,
+            Err(err) => {
+                println!("skipping record because err={:?}", err);
+                continue;
+            }
+        };
+
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+pub fn load(path: impl AsRef<std::path::Path>) -> Result<Vec<Record>, anyhow::Error> {
+    read(&mut std::fs::File::open(path)?)
+}
+
+// replays Records to a vehicle (or anything else that takes InputMessages from a channel) with
+// the timing they were recorded with
+pub struct Player {
+    records: Vec<Record>,
+    speed: f64,
+}
+
+impl Player {
+    pub fn new(records: Vec<Record>) -> Self {
+        Self {
+            records,
+            speed: 1.0,
+        }
+    }
+
+    pub fn open(path: impl AsRef<std::path::Path>) -> Result<Self, anyhow::Error> {
+        Ok(Self::new(load(path)?))
+    }
+
+    // e.g. 2.0 plays back twice as fast as the session was recorded
+    pub fn set_speed(&mut self, speed: f64) {
+        assert!(speed > 0.0);
+        self.speed = speed;
+    }
+
+    pub fn get_records(&self) -> &[Record] {
+        &self.records
+    }
+
+    // how long playing back takes at the current speed
+    pub fn get_duration(&self) -> std::time::Duration {
+        self.records
+            .last()
+            .map(|record| self.get_offset(record))
+            .unwrap_or_default()
+    }
+
+    fn get_offset(&self, record: &Record) -> std::time::Duration {
+        std::time::Duration::from_micros(record.elapsed_us).div_f64(self.speed)
+    }
+
+    // blocks until every Record has been sent
+    pub fn run(
+        &self,
+        incoming_input_message_sender: &std::sync::mpsc::Sender<crate::serialization::InputMessage>,
+    ) -> Result<(), anyhow::Error> {
+        let started_at = std::time::Instant::now();
+
+        for record in self.records.iter() {
+            // timed from the start rather than the last Record so that errors don't add up
+            let due_at = started_at + self.get_offset(record);
+            std::thread::sleep(due_at.saturating_duration_since(std::time::Instant::now()));
+
+            incoming_input_message_sender.send(record.input_message.clone())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_input_message(throttle: f32) -> crate::serialization::InputMessage {
+        crate::serialization::InputMessage {
+            throttle,
+            steering: 0.0,
+            throttle_left: 0.0,
+            throttle_right: 0.0,
+            steering_left: 0.0,
+            steering_right: 0.0,
+            mode_up: false,
+            mode_down: false,
+            mode_left: false,
+            mode_right: false,
+            handbrake: false,
+        }
+    }
+
+    fn ms(ms: u64) -> std::time::Duration {
+        std::time::Duration::from_millis(ms)
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedWriter(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for SharedWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn record_and_read() -> anyhow::Result<()> {
+        let writer = SharedWriter::default();
+        let mut recorder = Recorder::new(Box::new(writer.clone()));
+
+        let now = std::time::Instant::now();
+        for (i, at) in [0, 50, 100, 1000].into_iter().enumerate() {
+            recorder.record_at(now + ms(at), &get_input_message(i as f32))?;
+        }
+
+        let mut buf = writer.0.lock().unwrap().clone();
+        let records = read(&mut buf.as_slice())?;
+        assert_eq!(
+            records
+                .iter()
+                .map(|record| record.elapsed_us)
+                .collect::<Vec<u64>>(),
+            vec![0, 50_000, 100_000, 1_000_000]
+        );
+        assert_eq!(records[3].input_message, get_input_message(3.0));
+
+        // corrupt the second record and cut the last one short
+        buf[crate::framing::HEADER_SIZE + 30] ^= 0xff;
+        buf.truncate(buf.len() - 1);
+
+        let records = read(&mut buf.as_slice())?;
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].input_message, get_input_message(2.0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn replay() -> anyhow::Result<()> {
+        let records: Vec<Record> = [0, 100, 200, 400]
+            .into_iter()
+            .enumerate()
+            .map(|(i, at)| Record {
+                elapsed_us: at * 1000,
+                input_message: get_input_message(i as f32),
+            })
+            .collect();
+
+        let mut player = Player::new(records.clone());
+        player.set_speed(4.0);
+        assert_eq!(player.get_duration(), ms(100));
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+
+        let started_at = std::time::Instant::now();
+        player.run(&sender)?;
+        let elapsed = started_at.elapsed();
+
+        assert!(elapsed >= ms(100));
+        assert!(elapsed < ms(400));
+
+        let input_messages: Vec<crate::serialization::InputMessage> = receiver.try_iter().collect();
+        assert_eq!(
+            input_messages,
+            records
+                .into_iter()
+                .map(|record| record.input_message)
+                .collect::<Vec<crate::serialization::InputMessage>>()
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,233 @@
+// how far behind the newest sequence number we remember which packets arrived; anything older is
+// assumed to be from a restarted sender (or a replay, if the link is authenticated)
+const WINDOW_SIZE: u64 = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Accept,
+    Duplicate,
+    Reordered,
+    Replayed,
+    Unsigned,
+    BadSignature,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct LinkStats {
+    pub received: u64,
+    pub accepted: u64,
+    pub lost: u64,
+    pub reordered: u64,
+    pub duplicated: u64,
+    pub restarted: u64,
+    pub replayed: u64,
+    pub unsigned: u64,
+    pub bad_signature: u64,
+    pub last_sequence: Option<u64>,
+    pub last_timestamp_us: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SequenceTracker {
+    stats: LinkStats,
+    // bit n set means last_sequence - n has been seen
+    seen: u64,
+    // authenticated links can't restart and must begin above minimum_sequence, so that a captured
+    // datagram can't be replayed from a new source address
+    authenticated: bool,
+    minimum_sequence: Option<u64>,
+}
+
+impl SequenceTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn new_authenticated() -> Self {
+        Self {
+            authenticated: true,
+            ..Self::default()
+        }
+    }
+
+    // only has an effect until the first sequence number has been accepted
+    pub fn set_minimum_sequence(&mut self, minimum_sequence: Option<u64>) {
+        self.minimum_sequence = minimum_sequence;
+    }
+
+    pub fn get_stats(&self) -> &LinkStats {
+        &self.stats
+    }
+
+    fn reset(&mut self, sequence: u64) {
+        self.stats.last_sequence = Some(sequence);
+        self.seen = 1;
+    }
+
+    // count a datagram that was rejected before its sequence number could be trusted
+    pub fn reject(&mut self, verdict: Verdict) {
+        self.stats.received += 1;
+
+        match verdict {
+            Verdict::Accept => {}
+            Verdict::Duplicate => self.stats.duplicated += 1,
+            Verdict::Reordered => self.stats.reordered += 1,
+            Verdict::Replayed => self.stats.replayed += 1,
+            Verdict::Unsigned => self.stats.unsigned += 1,
+            Verdict::BadSignature => self.stats.bad_signature += 1,
+        }
+    }
+
+    pub fn track(&mut self, sequence: u64, timestamp_us: u64) -> Verdict {
+        self.stats.received += 1;
+
+        let last_sequence = match self.stats.last_sequence {
+            Some(last_sequence) => last_sequence,
+            None => {
+                if self.authenticated
+                    && self
+                        .minimum_sequence
+                        .is_some_and(|minimum_sequence| sequence <= minimum_sequence)
+                {
+                    self.stats.replayed += 1;
+                    return Verdict::Replayed;
+                }
+
+                self.reset(sequence);
+                self.stats.accepted += 1;
+                self.stats.last_timestamp_us = Some(timestamp_us);
+                return Verdict::Accept;
+            }
+        };
+
+        if sequence > last_sequence {
+            let gap = sequence - last_sequence;
+
+            self.stats.lost += gap - 1;
+            self.stats.accepted += 1;
+            self.stats.last_sequence = Some(sequence);
+            self.stats.last_timestamp_us = Some(timestamp_us);
+            self.seen = if gap < WINDOW_SIZE {
+                (self.seen << gap) | 1
+            } else {
+                1
+            };
+
+            return Verdict::Accept;
+        }
+
+        let age = last_sequence - sequence;
+        if age >= WINDOW_SIZE {
+            if self.authenticated {
+                self.stats.replayed += 1;
+                return Verdict::Replayed;
+            }
+
+            // too old to be a late packet from this sender, so it must have restarted
+            self.stats.restarted += 1;
+            self.stats.accepted += 1;
+            self.stats.last_timestamp_us = Some(timestamp_us);
+            self.reset(sequence);
+
+            return Verdict::Accept;
+        }
+
+        let bit: u64 = 1 << age;
+        if self.seen & bit != 0 {
+            self.stats.duplicated += 1;
+            return Verdict::Duplicate;
+        }
+
+        // it wasn't lost after all, it's just too late to be useful
+        self.seen |= bit;
+        self.stats.lost = self.stats.lost.saturating_sub(1);
+        self.stats.reordered += 1;
+
+        Verdict::Reordered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn in_order() {
+        let mut tracker = SequenceTracker::new();
+
+        for sequence in 10..20 {
+            assert_eq!(tracker.track(sequence, sequence * 1000), Verdict::Accept);
+        }
+
+        let stats = tracker.get_stats();
+        assert_eq!(stats.received, 10);
+        assert_eq!(stats.accepted, 10);
+        assert_eq!(stats.lost, 0);
+        assert_eq!(stats.reordered, 0);
+        assert_eq!(stats.duplicated, 0);
+        assert_eq!(stats.last_sequence, Some(19));
+        assert_eq!(stats.last_timestamp_us, Some(19000));
+    }
+
+    #[test]
+    fn lost_reordered_and_duplicated() {
+        let mut tracker = SequenceTracker::new();
+
+        assert_eq!(tracker.track(1, 0), Verdict::Accept);
+        assert_eq!(tracker.track(2, 0), Verdict::Accept);
+        assert_eq!(tracker.track(5, 0), Verdict::Accept);
+        assert_eq!(tracker.track(5, 0), Verdict::Duplicate);
+        assert_eq!(tracker.track(2, 0), Verdict::Duplicate);
+        assert_eq!(tracker.track(4, 0), Verdict::Reordered);
+        assert_eq!(tracker.track(4, 0), Verdict::Duplicate);
+        assert_eq!(tracker.track(6, 0), Verdict::Accept);
+
+        let stats = tracker.get_stats();
+        assert_eq!(stats.received, 8);
+        assert_eq!(stats.accepted, 4);
+        assert_eq!(stats.lost, 1);
+        assert_eq!(stats.reordered, 1);
+        assert_eq!(stats.duplicated, 3);
+        assert_eq!(stats.last_sequence, Some(6));
+    }
+
+    #[test]
+    fn restarted() {
+        let mut tracker = SequenceTracker::new();
+
+        assert_eq!(tracker.track(1000, 0), Verdict::Accept);
+        assert_eq!(tracker.track(0, 0), Verdict::Accept);
+        assert_eq!(tracker.track(1, 0), Verdict::Accept);
+        assert_eq!(tracker.track(0, 0), Verdict::Duplicate);
+
+        let stats = tracker.get_stats();
+        assert_eq!(stats.restarted, 1);
+        assert_eq!(stats.last_sequence, Some(1));
+    }
+
+    #[test]
+    fn authenticated() {
+        let mut tracker = SequenceTracker::new_authenticated();
+        tracker.set_minimum_sequence(Some(1000));
+
+        assert_eq!(tracker.track(999, 0), Verdict::Replayed);
+        assert_eq!(tracker.track(1000, 0), Verdict::Replayed);
+        assert_eq!(tracker.track(1001, 0), Verdict::Accept);
+        assert_eq!(tracker.track(1001, 0), Verdict::Duplicate);
+        assert_eq!(tracker.track(2000, 0), Verdict::Accept);
+        assert_eq!(tracker.track(1001, 0), Verdict::Replayed);
+
+        tracker.reject(Verdict::Unsigned);
+        tracker.reject(Verdict::BadSignature);
+
+        let stats = tracker.get_stats();
+        assert_eq!(stats.received, 8);
+        assert_eq!(stats.accepted, 2);
+        assert_eq!(stats.duplicated, 1);
+        assert_eq!(stats.replayed, 3);
+        assert_eq!(stats.restarted, 0);
+        assert_eq!(stats.unsigned, 1);
+        assert_eq!(stats.bad_signature, 1);
+        assert_eq!(stats.last_sequence, Some(2000));
+    }
+}
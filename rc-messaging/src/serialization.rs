@@ -1,3 +1,6 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
 #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct InputMessage {
     // Ackermann steering
@@ -16,6 +19,214 @@ pub struct InputMessage {
     pub handbrake: bool,
 }
 
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TelemetryMessage {
+    // what was actually applied (i.e. after limiting / failsafe)
+    pub throttle: f32,
+    pub steering: f32,
+    pub throttle_left: f32,
+    pub throttle_right: f32,
+    // current limits
+    pub throttle_min: f32,
+    pub throttle_max: f32,
+    pub steering_offset: f32,
+    // Other
+    pub failsafe: bool,
+    // false means the vehicle is ignoring InputMessages until it's armed
+    pub armed: bool,
+    pub battery_voltage: Option<f32>,
+    // the name of the drive mode the vehicle is in, for vehicles that have them
+    pub drive_mode: Option<String>,
+    // filled in by the transport::Server for the link it's sending on
+    pub link_stats: Option<crate::sequencing::LinkStats>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum LeaseMessage {
+    // controller -> vehicle
+    Release,
+    HandOver(crate::address::Address),
+    // vehicle -> controller
+    Granted,
+    Revoked,
+    Queued(u32),
+}
+
+// round trip measurement, see crate::latency
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum PingMessage {
+    // controller -> vehicle
+    Ping(u64),
+    // vehicle -> controller, echoing the id of the Ping
+    Pong(u64),
+}
+
+// controller -> vehicle; the vehicle reports the drive mode it's in (by name) in its
+// TelemetryMessages
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum DriveModeMessage {
+    Set(String),
+    // to the next one along (wrapping around)
+    Cycle,
+}
+
+// an authenticated vehicle gives each controller a session of its own, with a key that's new each
+// time (see crate::auth::PreSharedKey::get_session_key), and both ends sign everything but these
+// with it; so nothing captured can be replayed from another address or after either end restarts
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum SessionMessage {
+    // controller -> vehicle, signed with the pre-shared key; the challenge is echoed back so that
+    // the controller can tell the answer from a replayed one
+    Request(u64),
+    // vehicle -> controller, signed with the pre-shared key
+    Nonce { nonce: u64, challenge: u64 },
+    // vehicle -> controller, signed with the pre-shared key; the sequence number of a datagram from
+    // an address it has no session for (e.g. one signed for a session from before it restarted)
+    Expired(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum VehicleKind {
+    Car,
+    Tank,
+    Truck,
+    Drone,
+}
+
+// periodically broadcast by a vehicle so that controllers can find it, see crate::discovery
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct BeaconMessage {
+    pub name: String,
+    pub kind: VehicleKind,
+    pub protocol_version: u8,
+    // InputMessages go to this port at the address the beacon came from
+    pub control_port: u16,
+}
+
+// a generic alternative to InputMessage; N analog axes (nominally -1.0 to 1.0) and M buttons whose
+// meanings each vehicle publishes in its VehicleDescriptor
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ControlMessage {
+    pub axes: Vec<f32>,
+    pub buttons: Vec<bool>,
+}
+
+impl ControlMessage {
+    // a channel the sender left out is centred / released
+    pub fn get_axis(&self, index: usize) -> f32 {
+        self.axes.get(index).copied().unwrap_or(0.0)
+    }
+
+    pub fn get_button(&self, index: usize) -> bool {
+        self.buttons.get(index).copied().unwrap_or(false)
+    }
+}
+
+// the channels of a ControlMessage converted from (or to) an InputMessage, in order
+pub const LEGACY_AXES: [&str; 6] = [
+    "throttle",
+    "steering",
+    "throttle_left",
+    "throttle_right",
+    "steering_left",
+    "steering_right",
+];
+pub const LEGACY_BUTTONS: [&str; 5] = [
+    "mode_up",
+    "mode_down",
+    "mode_left",
+    "mode_right",
+    "handbrake",
+];
+
+impl From<InputMessage> for ControlMessage {
+    fn from(input_message: InputMessage) -> Self {
+        ControlMessage {
+            axes: alloc::vec![
+                input_message.throttle,
+                input_message.steering,
+                input_message.throttle_left,
+                input_message.throttle_right,
+                input_message.steering_left,
+                input_message.steering_right,
+            ],
+            buttons: alloc::vec![
+                input_message.mode_up,
+                input_message.mode_down,
+                input_message.mode_left,
+                input_message.mode_right,
+                input_message.handbrake,
+            ],
+        }
+    }
+}
+
+// for vehicles that still take InputMessages; channels past the legacy ones are dropped
+impl From<ControlMessage> for InputMessage {
+    fn from(control_message: ControlMessage) -> Self {
+        InputMessage {
+            throttle: control_message.get_axis(0),
+            steering: control_message.get_axis(1),
+            throttle_left: control_message.get_axis(2),
+            throttle_right: control_message.get_axis(3),
+            steering_left: control_message.get_axis(4),
+            steering_right: control_message.get_axis(5),
+            mode_up: control_message.get_button(0),
+            mode_down: control_message.get_button(1),
+            mode_left: control_message.get_button(2),
+            mode_right: control_message.get_button(3),
+            handbrake: control_message.get_button(4),
+        }
+    }
+}
+
+// published by a vehicle (see crate::transport::Server::set_vehicle_descriptor) so a controller knows
+// what each ControlMessage channel means; an empty name is a channel the vehicle ignores
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct VehicleDescriptor {
+    pub kind: VehicleKind,
+    pub axes: Vec<String>,
+    pub buttons: Vec<String>,
+}
+
+impl VehicleDescriptor {
+    // every channel of an InputMessage, named for its field
+    pub fn new_legacy(kind: VehicleKind) -> Self {
+        VehicleDescriptor {
+            kind,
+            axes: LEGACY_AXES.iter().map(|name| String::from(*name)).collect(),
+            buttons: LEGACY_BUTTONS
+                .iter()
+                .map(|name| String::from(*name))
+                .collect(),
+        }
+    }
+
+    pub fn get_axis_index(&self, name: &str) -> Option<usize> {
+        (!name.is_empty())
+            .then(|| self.axes.iter().position(|axis| axis == name))
+            .flatten()
+    }
+
+    pub fn get_button_index(&self, name: &str) -> Option<usize> {
+        (!name.is_empty())
+            .then(|| self.buttons.iter().position(|button| button == name))
+            .flatten()
+    }
+
+    pub fn get_axis(&self, control_message: &ControlMessage, name: &str) -> Option<f32> {
+        self.get_axis_index(name)
+            .map(|index| control_message.get_axis(index))
+    }
+
+    pub fn get_button(&self, control_message: &ControlMessage, name: &str) -> Option<bool> {
+        self.get_button_index(name)
+            .map(|index| control_message.get_button(index))
+    }
+}
+
+// MessagePack, which needs std; see crate::codec for the alternatives
+#[cfg(feature = "std")]
 pub fn serialize<T>(t: T) -> Result<Vec<u8>, rmp_serde::encode::Error>
 where
     T: serde::ser::Serialize,
@@ -23,11 +234,33 @@ where
     rmp_serde::to_vec(&t)
 }
 
+#[cfg(feature = "std")]
 pub fn deserialize<T>(message: Vec<u8>) -> Result<T, rmp_serde::decode::Error>
 where
     T: serde::de::DeserializeOwned,
 {
-    rmp_serde::from_slice::<T>(message.as_slice())
+    deserialize_from_slice(message.as_slice())
+}
+
+// the allocation-free equivalents of the above; serialize_into returns how much of buf was used
+#[cfg(feature = "std")]
+pub fn serialize_into<T>(t: &T, buf: &mut [u8]) -> Result<usize, rmp_serde::encode::Error>
+where
+    T: serde::ser::Serialize,
+{
+    let available = buf.len();
+    let mut writer = &mut buf[..];
+    rmp_serde::encode::write(&mut writer, t)?;
+
+    Ok(available - writer.len())
+}
+
+#[cfg(feature = "std")]
+pub fn deserialize_from_slice<T>(message: &[u8]) -> Result<T, rmp_serde::decode::Error>
+where
+    T: serde::de::DeserializeOwned,
+{
+    rmp_serde::from_slice::<T>(message)
 }
 
 pub fn add(left: usize, right: usize) -> usize {
@@ -62,4 +295,103 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn serialize_into_and_deserialize_from_slice() -> anyhow::Result<()> {
+        let control_message = ControlMessage::from(InputMessage {
+            throttle: 0.69,
+            steering: -0.69,
+            throttle_left: 0.0,
+            throttle_right: 0.0,
+            steering_left: 0.0,
+            steering_right: 0.0,
+            mode_up: false,
+            mode_down: false,
+            mode_left: false,
+            mode_right: false,
+            handbrake: true,
+        });
+
+        let mut buf = [0; 64];
+        let n = serialize_into(&control_message, &mut buf)?;
+        assert_eq!(&buf[0..n], serialize(&control_message)?.as_slice());
+        assert_eq!(
+            deserialize_from_slice::<ControlMessage>(&buf[0..n])?,
+            control_message
+        );
+
+        assert!(serialize_into(&control_message, &mut buf[0..n - 1]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn control_message_from_and_to_input_message() {
+        let input_message = InputMessage {
+            throttle: 0.1,
+            steering: 0.2,
+            throttle_left: 0.3,
+            throttle_right: 0.4,
+            steering_left: 0.5,
+            steering_right: 0.6,
+            mode_up: true,
+            mode_down: false,
+            mode_left: true,
+            mode_right: false,
+            handbrake: true,
+        };
+
+        let control_message = ControlMessage::from(input_message.clone());
+        assert_eq!(control_message.axes.len(), LEGACY_AXES.len());
+        assert_eq!(control_message.buttons.len(), LEGACY_BUTTONS.len());
+        assert_eq!(InputMessage::from(control_message.clone()), input_message);
+
+        let descriptor = VehicleDescriptor::new_legacy(VehicleKind::Car);
+        assert_eq!(
+            descriptor.get_axis(&control_message, "throttle_right"),
+            Some(0.4)
+        );
+        assert_eq!(
+            descriptor.get_button(&control_message, "handbrake"),
+            Some(true)
+        );
+        assert_eq!(descriptor.get_axis(&control_message, "tray"), None);
+
+        // missing channels are centred / released and extra ones are dropped
+        let control_message = ControlMessage {
+            axes: vec![1.0, -1.0, 0.0, 0.0, 0.0, 0.0, 0.5],
+            buttons: vec![true],
+        };
+        let input_message = InputMessage::from(control_message);
+        assert_eq!(input_message.throttle, 1.0);
+        assert_eq!(input_message.steering, -1.0);
+        assert!(input_message.mode_up);
+        assert!(!input_message.handbrake);
+    }
+
+    #[test]
+    fn vehicle_descriptor() {
+        let descriptor = VehicleDescriptor {
+            kind: VehicleKind::Truck,
+            axes: vec![
+                "throttle".to_string(),
+                "steering".to_string(),
+                "".to_string(),
+                "tray".to_string(),
+            ],
+            buttons: vec![],
+        };
+
+        let control_message = ControlMessage {
+            axes: vec![0.5, 0.0, 0.69, -1.0],
+            buttons: vec![],
+        };
+
+        assert_eq!(descriptor.get_axis_index("tray"), Some(3));
+        assert_eq!(descriptor.get_axis(&control_message, "tray"), Some(-1.0));
+
+        // unused channels can't be looked up
+        assert_eq!(descriptor.get_axis_index(""), None);
+        assert_eq!(descriptor.get_button(&control_message, "handbrake"), None);
+    }
 }
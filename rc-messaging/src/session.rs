@@ -0,0 +1,398 @@
+// the controller's half of a session (see crate::serialization::SessionMessage), for anything that
+// talks to an authenticated vehicle; whatever it sends goes as a Request until the vehicle has
+// answered, and only what the vehicle has signed for the session since then gets through
+
+// a vehicle has no idea of the time of day (so it can't tell an old datagram by its timestamp), but
+// std seeds RandomState from the OS's random number generator, which is different every start
+pub(crate) fn get_nonce() -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u64(crate::envelope::get_timestamp_us());
+    hasher.finish()
+}
+
+pub struct ClientSession {
+    pre_shared_key: crate::auth::PreSharedKey,
+    // what the next Request is sent with; a new one for each session, so an old Nonce is no good
+    challenge: u64,
+    session_key: Option<crate::auth::PreSharedKey>,
+    // the first sequence number signed with the session_key, so that an Expired about something
+    // sent before the session was joined can be ignored
+    joined_at_sequence: Option<u64>,
+    // the vehicle's sequence numbers only go up, so anything it sent before the Nonce is old
+    sequence_tracker: crate::sequencing::SequenceTracker,
+}
+
+impl ClientSession {
+    pub fn new(pre_shared_key: crate::auth::PreSharedKey) -> Self {
+        Self {
+            pre_shared_key,
+            challenge: get_nonce(),
+            session_key: None,
+            joined_at_sequence: None,
+            sequence_tracker: crate::sequencing::SequenceTracker::new_authenticated(),
+        }
+    }
+
+    pub fn is_joined(&self) -> bool {
+        self.session_key.is_some()
+    }
+
+    // encodes (and signs) into the start of buf; until the session is joined, that's a Request
+    // rather than the message; returns the length of the datagram
+    pub fn encode_into(
+        &mut self,
+        sequence: u64,
+        message: &crate::envelope::Message,
+        codec: crate::codec::Codec,
+        buf: &mut [u8],
+    ) -> Result<usize, crate::envelope::Error> {
+        let Some(session_key) = &self.session_key else {
+            return self.encode_request_into(sequence, codec, buf);
+        };
+
+        self.joined_at_sequence.get_or_insert(sequence);
+
+        crate::transport::encode_into(sequence, message, codec, Some(session_key), buf)
+    }
+
+    pub fn encode_request_into(
+        &self,
+        sequence: u64,
+        codec: crate::codec::Codec,
+        buf: &mut [u8],
+    ) -> Result<usize, crate::envelope::Error> {
+        crate::transport::encode_into(
+            sequence,
+            &crate::envelope::Message::Session(crate::serialization::SessionMessage::Request(
+                self.challenge,
+            )),
+            codec,
+            Some(&self.pre_shared_key),
+            buf,
+        )
+    }
+
+    // checks a datagram from the vehicle, acting on it if it's a SessionMessage; returns the
+    // Envelope if it's anything else (signed for the session and not replayed)
+    pub fn receive(
+        &mut self,
+        address: &crate::address::Address,
+        buf: &[u8],
+    ) -> Option<crate::envelope::Envelope> {
+        if let Some(session_key) = &self.session_key {
+            if crate::auth::verify(session_key, buf).is_ok() {
+                return self.track(address, decode(address, buf)?);
+            }
+        }
+
+        if let Err(err) = crate::auth::verify(&self.pre_shared_key, buf) {
+            println!(
+                "dropping message from address={} because err={}",
+                address, err
+            );
+            return None;
+        }
+
+        let envelope = decode(address, buf)?;
+
+        match envelope.message {
+            crate::envelope::Message::Session(crate::serialization::SessionMessage::Nonce {
+                nonce,
+                challenge,
+            }) => {
+                if challenge != self.challenge {
+                    println!(
+                        "ignoring session nonce={} from address={} because it isn't for challenge={}",
+                        nonce, address, self.challenge
+                    );
+                    return None;
+                }
+
+                println!("joining session nonce={}", nonce);
+
+                self.session_key = Some(self.pre_shared_key.get_session_key(nonce));
+                self.joined_at_sequence = None;
+                self.challenge = get_nonce();
+                self.sequence_tracker = crate::sequencing::SequenceTracker::new_authenticated();
+                self.sequence_tracker
+                    .set_minimum_sequence(envelope.sequence);
+            }
+            crate::envelope::Message::Session(crate::serialization::SessionMessage::Expired(
+                sequence,
+            )) => {
+                if self
+                    .joined_at_sequence
+                    .is_some_and(|joined_at_sequence| sequence >= joined_at_sequence)
+                {
+                    println!(
+                        "leaving session because address={} has no session for sequence={}",
+                        address, sequence
+                    );
+
+                    self.session_key = None;
+                    self.joined_at_sequence = None;
+                }
+            }
+            _ => {
+                println!(
+                    "ignoring message_type={:?} from address={} because it isn't signed for the session",
+                    envelope.message.get_message_type(),
+                    address
+                );
+            }
+        }
+
+        None
+    }
+
+    fn track(
+        &mut self,
+        address: &crate::address::Address,
+        envelope: crate::envelope::Envelope,
+    ) -> Option<crate::envelope::Envelope> {
+        let (Some(sequence), Some(timestamp_us)) = (envelope.sequence, envelope.timestamp_us)
+        else {
+            return None;
+        };
+
+        let verdict = self.sequence_tracker.track(sequence, timestamp_us);
+        if verdict != crate::sequencing::Verdict::Accept {
+            println!(
+                "dropping message from address={} because verdict={:?}, sequence={:?}",
+                address, verdict, sequence
+            );
+            return None;
+        }
+
+        Some(envelope)
+    }
+}
+
+fn decode(address: &crate::address::Address, buf: &[u8]) -> Option<crate::envelope::Envelope> {
+    match crate::envelope::decode(buf) {
+        Ok(envelope) => Some(envelope),
+        Err(err) => {
+            println!(
+                "dropping message from address={} because err={}",
+                address, err
+            );
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // what state answers a Ping with (whatever the session has made of it), as the datagram the
+    // vehicle would send
+    fn get_answer(
+        state: &crate::transport::ServerState,
+        address: &crate::address::Address,
+        session: &mut ClientSession,
+        sequences: (u64, u64),
+    ) -> anyhow::Result<Vec<u8>> {
+        let (client_sequence, server_sequence) = sequences;
+
+        let mut buf = vec![0; 1024];
+        let n = session.encode_into(
+            client_sequence,
+            &crate::envelope::Message::Ping(crate::serialization::PingMessage::Ping(1)),
+            crate::codec::Codec::default(),
+            &mut buf,
+        )?;
+
+        let mut outbox = vec![];
+        state.handle(address, &buf[0..n], &mut outbox);
+        assert_eq!(outbox.len(), 1);
+
+        let n = state.encode_into(server_sequence, address, &outbox[0].1, &mut buf)?;
+        buf.truncate(n);
+
+        Ok(buf)
+    }
+
+    #[test]
+    fn join_and_receive() -> anyhow::Result<()> {
+        let pre_shared_key = crate::auth::PreSharedKey::new(b"car123!@#");
+        let address: crate::address::Address =
+            "127.0.0.1:1337".parse::<std::net::SocketAddr>()?.into();
+
+        let mut state = crate::transport::ServerState::new();
+        state.set_pre_shared_key(pre_shared_key.clone());
+
+        // whatever's sent before the vehicle has answered goes as a Request
+        let mut session = ClientSession::new(pre_shared_key.clone());
+        let nonce = get_answer(&state, &address, &mut session, (1, 100))?;
+        assert!(matches!(
+            crate::envelope::decode(&nonce)?.message,
+            crate::envelope::Message::Session(crate::serialization::SessionMessage::Nonce { .. })
+        ));
+        assert!(session.receive(&address, &nonce).is_none());
+        assert!(session.is_joined());
+
+        // from then on, only what the vehicle has signed for the session (since the Nonce, and
+        // just the once) gets through
+        let pong = get_answer(&state, &address, &mut session, (2, 99))?;
+        assert!(session.receive(&address, &pong).is_none());
+        let pong = get_answer(&state, &address, &mut session, (3, 101))?;
+        assert!(session.receive(&address, &pong).is_some());
+        assert!(session.receive(&address, &pong).is_none());
+
+        // a restarted controller won't take a replayed Nonce, or anything from the old session
+        let mut restarted_session = ClientSession::new(pre_shared_key.clone());
+        assert!(restarted_session.receive(&address, &nonce).is_none());
+        assert!(!restarted_session.is_joined());
+
+        let restarted_nonce = get_answer(&state, &address, &mut restarted_session, (1, 200))?;
+        assert!(restarted_session
+            .receive(&address, &restarted_nonce)
+            .is_none());
+        assert!(restarted_session.is_joined());
+        assert!(restarted_session.receive(&address, &pong).is_none());
+
+        // a restarted vehicle has no session for what's sent to it, so it's asked for a new one
+        let mut state = crate::transport::ServerState::new();
+        state.set_pre_shared_key(pre_shared_key);
+
+        let expired = get_answer(&state, &address, &mut restarted_session, (2, 300))?;
+        assert_eq!(
+            crate::envelope::decode(&expired)?.message,
+            crate::envelope::Message::Session(crate::serialization::SessionMessage::Expired(2))
+        );
+        assert!(restarted_session.receive(&address, &expired).is_none());
+        assert!(!restarted_session.is_joined());
+
+        // but an Expired about something sent before the session was joined is ignored
+        let nonce = get_answer(&state, &address, &mut restarted_session, (3, 301))?;
+        assert!(restarted_session.receive(&address, &nonce).is_none());
+        assert!(restarted_session.is_joined());
+        get_answer(&state, &address, &mut restarted_session, (4, 302))?;
+        assert!(restarted_session.receive(&address, &expired).is_none());
+        assert!(restarted_session.is_joined());
+
+        Ok(())
+    }
+
+    // the way rc-client goes about it: a socket of its own, with every frame sent as it comes
+    #[test]
+    fn reaches_a_server() -> anyhow::Result<()> {
+        let pre_shared_key = crate::auth::PreSharedKey::new(b"car123!@#");
+
+        let (incoming_input_message_sender, incoming_input_message_receiver) =
+            std::sync::mpsc::channel();
+
+        let mut server =
+            crate::transport::Server::new("127.0.0.1:0".parse()?, incoming_input_message_sender)?;
+        server.set_pre_shared_key(pre_shared_key.clone());
+
+        let server_address = server.get_bind_address();
+        let server_closer = server.get_closer();
+        let outgoing_telemetry_message_sender = server.get_outgoing_telemetry_message_sender();
+
+        let server_handle = std::thread::spawn(move || {
+            server.run().unwrap();
+        });
+
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0")?;
+        socket.connect(server_address.get_socket_address().unwrap())?;
+        socket.set_read_timeout(Some(std::time::Duration::from_millis(10)))?;
+
+        let mut session = ClientSession::new(pre_shared_key);
+        let mut sequence = crate::envelope::get_timestamp_us();
+        let mut buf = vec![0; 1024];
+
+        let mut receive = |session: &mut ClientSession| {
+            let mut messages = vec![];
+            while let Ok(n) = socket.recv(&mut buf) {
+                if let Some(envelope) = session.receive(&server_address, &buf[0..n]) {
+                    messages.push(envelope.message);
+                }
+            }
+            messages
+        };
+
+        let outgoing_input_message = crate::serialization::InputMessage {
+            throttle: 0.69,
+            steering: 0.0,
+            throttle_left: 0.0,
+            throttle_right: 0.0,
+            steering_left: 0.0,
+            steering_right: 0.0,
+            mode_up: false,
+            mode_down: false,
+            mode_left: false,
+            mode_right: false,
+            handbrake: false,
+        };
+
+        // the first frame goes as a Request, and the next one gets through
+        let mut send_buf = vec![0; 1024];
+        let mut incoming_input_messages = vec![];
+        for _ in 0..10 {
+            let n = session.encode_into(
+                sequence,
+                &crate::envelope::Message::Input(outgoing_input_message.clone()),
+                crate::codec::Codec::default(),
+                &mut send_buf,
+            )?;
+            sequence += 1;
+            socket.send(&send_buf[0..n])?;
+
+            receive(&mut session);
+
+            incoming_input_messages.extend(incoming_input_message_receiver.try_iter());
+            if !incoming_input_messages.is_empty() {
+                break;
+            }
+        }
+        assert_eq!(incoming_input_messages, vec![outgoing_input_message]);
+
+        // and what the vehicle sends back gets through too
+        let outgoing_telemetry_message = crate::serialization::TelemetryMessage {
+            throttle: 0.69,
+            steering: 0.0,
+            throttle_left: 0.0,
+            throttle_right: 0.0,
+            throttle_min: -1.0,
+            throttle_max: 1.0,
+            steering_offset: 0.0,
+            failsafe: false,
+            armed: true,
+            battery_voltage: None,
+            drive_mode: None,
+            link_stats: None,
+        };
+        outgoing_telemetry_message_sender.send(outgoing_telemetry_message.clone())?;
+
+        let started_at = std::time::Instant::now();
+        let incoming_telemetry_message = loop {
+            assert!(started_at.elapsed() < std::time::Duration::from_secs(1));
+
+            let telemetry_message =
+                receive(&mut session)
+                    .into_iter()
+                    .find_map(|message| match message {
+                        crate::envelope::Message::Telemetry(telemetry_message) => {
+                            Some(telemetry_message)
+                        }
+                        _ => None,
+                    });
+            if let Some(telemetry_message) = telemetry_message {
+                break telemetry_message;
+            }
+        };
+        assert_eq!(
+            incoming_telemetry_message.throttle,
+            outgoing_telemetry_message.throttle
+        );
+
+        server_closer();
+        server_handle.join().unwrap();
+
+        Ok(())
+    }
+}
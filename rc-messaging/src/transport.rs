@@ -1,3 +1,12 @@
+#[cfg(feature = "tokio")]
+pub mod asynchronous;
+pub mod loopback;
+#[cfg(feature = "serial")]
+pub mod serial;
+pub mod stream;
+pub mod tcp;
+pub mod udp;
+
 const BUF_SIZE: usize = 1024;
 const MESSAGE_TIMEOUT_HZ: f64 = 20.0;
 
@@ -6,12 +15,68 @@ const MESSAGE_TIMEOUT: std::time::Duration =
 const READ_TIMEOUT: std::time::Duration = MESSAGE_TIMEOUT;
 const WRITE_TIMEOUT: std::time::Duration = MESSAGE_TIMEOUT;
 
-fn get_socket(bind_address: std::net::SocketAddr) -> Result<std::net::UdpSocket, std::io::Error> {
-    let socket = std::net::UdpSocket::bind(bind_address)?;
-    socket.set_read_timeout(Some(READ_TIMEOUT))?;
-    socket.set_write_timeout(Some(WRITE_TIMEOUT))?;
+pub use crate::address::Address;
+
+impl std::net::ToSocketAddrs for Address {
+    type Iter = std::option::IntoIter<std::net::SocketAddr>;
+
+    fn to_socket_addrs(&self) -> std::io::Result<Self::Iter> {
+        self.get_socket_address()
+            .map(|address| Some(address).into_iter())
+            .ok_or(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("address={} is not a socket address", self),
+            ))
+    }
+}
+
+// moves datagrams between a Server and its Clients; stream-oriented implementations frame each
+// datagram with crate::framing
+pub trait Transport: Send + Sync {
+    fn send_to(&self, buf: &[u8], address: &Address) -> Result<(), std::io::Error>;
+
+    // waits for up to READ_TIMEOUT and then fails with WouldBlock (or TimedOut); like a UDP
+    // socket, a datagram that doesn't fit in buf is truncated
+    fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, Address), std::io::Error>;
+
+    fn get_local_address(&self) -> Address;
+}
+
+// for binaries that let the link be chosen at runtime; kind is one of "udp", "tcp" or
+// "serial:<path>:<baud_rate>" (if built with the serial feature)
+pub fn get_server_transport(
+    kind: &str,
+    bind_address: std::net::SocketAddr,
+) -> Result<Box<dyn Transport>, anyhow::Error> {
+    match kind.split(':').collect::<Vec<&str>>()[..] {
+        ["udp"] => Ok(Box::new(udp::UdpTransport::new(bind_address)?)),
+        ["tcp"] => Ok(Box::new(tcp::TcpServerTransport::new(bind_address)?)),
+        #[cfg(feature = "serial")]
+        ["serial", path, baud_rate] => Ok(Box::new(serial::get_serial_transport(
+            path,
+            baud_rate.parse()?,
+        )?)),
+        _ => Err(anyhow::anyhow!("unsupported transport kind={:?}", kind)),
+    }
+}
+
+fn is_timeout(err: &std::io::Error) -> bool {
+    matches!(
+        err.kind(),
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+    )
+}
 
-    Ok(socket)
+// the other end isn't there (yet), which is worth a log but not worth stopping for
+fn is_disconnected(err: &std::io::Error) -> bool {
+    matches!(
+        err.kind(),
+        std::io::ErrorKind::ConnectionRefused
+            | std::io::ErrorKind::ConnectionReset
+            | std::io::ErrorKind::ConnectionAborted
+            | std::io::ErrorKind::NotConnected
+            | std::io::ErrorKind::BrokenPipe
+    )
 }
 
 fn get_input_message_sender_and_receiver() -> (
@@ -21,259 +86,2776 @@ fn get_input_message_sender_and_receiver() -> (
     std::sync::mpsc::channel()
 }
 
+fn get_telemetry_message_sender_and_receiver() -> (
+    std::sync::mpsc::Sender<crate::serialization::TelemetryMessage>,
+    std::sync::mpsc::Receiver<crate::serialization::TelemetryMessage>,
+) {
+    std::sync::mpsc::channel()
+}
+
+// encodes (and signs, if there's a key) into the start of buf without allocating; returns the length
+// of the datagram
+pub(crate) fn encode_into(
+    sequence: u64,
+    message: &crate::envelope::Message,
+    codec: crate::codec::Codec,
+    pre_shared_key: Option<&crate::auth::PreSharedKey>,
+    buf: &mut [u8],
+) -> Result<usize, crate::envelope::Error> {
+    let timestamp_us = crate::envelope::get_timestamp_us();
+
+    let Some(pre_shared_key) = pre_shared_key else {
+        return crate::envelope::encode_into(sequence, timestamp_us, message, codec, buf);
+    };
+
+    // leaving room for the tag
+    let unsigned_size = buf.len().saturating_sub(crate::auth::TAG_SIZE);
+    let n = crate::envelope::encode_into(
+        sequence,
+        timestamp_us,
+        message,
+        codec,
+        &mut buf[..unsigned_size],
+    )?;
+
+    Ok(crate::auth::sign_in_place(pre_shared_key, buf, n))
+}
+
+type SequenceTrackers = std::collections::HashMap<Address, crate::sequencing::SequenceTracker>;
+
+// what a Client signs with (and is answered in), see crate::serialization::SessionMessage
+struct Session {
+    challenge: u64,
+    nonce: u64,
+    session_key: crate::auth::PreSharedKey,
+}
+
+#[derive(Default)]
+struct ClientSessions {
+    current: Option<Session>,
+    // answered but not signed with yet; it only takes over once it is, so that a replayed Request
+    // can't knock a Client off the session it's in
+    pending: Option<Session>,
+}
+
+impl ClientSessions {
+    fn verify(&self, buf: &[u8]) -> bool {
+        self.current
+            .as_ref()
+            .is_some_and(|session| crate::auth::verify(&session.session_key, buf).is_ok())
+    }
+
+    // the pending session takes over if buf is signed for it
+    fn verify_pending(&mut self, buf: &[u8]) -> bool {
+        let is_pending = self
+            .pending
+            .as_ref()
+            .is_some_and(|session| crate::auth::verify(&session.session_key, buf).is_ok());
+        if is_pending {
+            self.current = self.pending.take();
+        }
+
+        is_pending
+    }
+
+    fn get_nonce(&self, challenge: u64) -> Option<u64> {
+        [&self.current, &self.pending]
+            .into_iter()
+            .flatten()
+            .find(|session| session.challenge == challenge)
+            .map(|session| session.nonce)
+    }
+}
+
+// how a datagram that got through ServerState::authenticate was signed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Authentication {
+    // there's no key
+    Unauthenticated,
+    Session,
+    // only for Requests
+    PreSharedKey,
+}
+
+// messages to be sent (in order) as a result of handling a datagram
+pub(crate) type Outbox = Vec<(Address, crate::envelope::Message)>;
+
+// everything a Server knows about its Clients, kept apart from the sending and receiving so that
+// the blocking Server and asynchronous::Server behave the same
+pub(crate) struct ServerState {
+    sequence_trackers: std::sync::Arc<std::sync::Mutex<SequenceTrackers>>,
+    diagnostics: std::sync::Arc<std::sync::Mutex<crate::diagnostics::Diagnostics>>,
+    ownership: std::sync::Arc<std::sync::Mutex<crate::ownership::Ownership>>,
+    // whatever each Client last spoke, so that it gets answered in kind
+    codecs: std::sync::Mutex<std::collections::HashMap<Address, crate::codec::Codec>>,
+    pre_shared_key: Option<crate::auth::PreSharedKey>,
+    sessions: std::sync::Mutex<std::collections::HashMap<Address, ClientSessions>>,
+    vehicle_descriptor: Option<crate::serialization::VehicleDescriptor>,
+    drive_mode_message_sender:
+        Option<std::sync::mpsc::Sender<crate::serialization::DriveModeMessage>>,
+    clock: std::sync::Arc<dyn crate::clock::Clock>,
+}
+
+impl ServerState {
+    pub(crate) fn new() -> Self {
+        Self {
+            sequence_trackers: std::sync::Arc::new(std::sync::Mutex::new(
+                std::collections::HashMap::new(),
+            )),
+            diagnostics: std::sync::Arc::new(std::sync::Mutex::new(
+                crate::diagnostics::Diagnostics::new(),
+            )),
+            ownership: std::sync::Arc::new(std::sync::Mutex::new(
+                crate::ownership::Ownership::default(),
+            )),
+            codecs: std::sync::Mutex::new(std::collections::HashMap::new()),
+            pre_shared_key: None,
+            sessions: std::sync::Mutex::new(std::collections::HashMap::new()),
+            vehicle_descriptor: None,
+            drive_mode_message_sender: None,
+            clock: crate::clock::get_system_clock(),
+        }
+    }
+
+    pub(crate) fn set_lease_timeout(&mut self, lease_timeout: std::time::Duration) {
+        self.ownership = std::sync::Arc::new(std::sync::Mutex::new(
+            crate::ownership::Ownership::new(lease_timeout),
+        ));
+    }
+
+    pub(crate) fn set_pre_shared_key(&mut self, pre_shared_key: crate::auth::PreSharedKey) {
+        self.pre_shared_key = Some(pre_shared_key);
+    }
+
+    pub(crate) fn set_vehicle_descriptor(
+        &mut self,
+        vehicle_descriptor: crate::serialization::VehicleDescriptor,
+    ) {
+        self.vehicle_descriptor = Some(vehicle_descriptor);
+    }
+
+    pub(crate) fn set_drive_mode_message_sender(
+        &mut self,
+        drive_mode_message_sender: std::sync::mpsc::Sender<crate::serialization::DriveModeMessage>,
+    ) {
+        self.drive_mode_message_sender = Some(drive_mode_message_sender);
+    }
+
+    pub(crate) fn set_clock(&mut self, clock: std::sync::Arc<dyn crate::clock::Clock>) {
+        self.clock = clock;
+    }
+
+    fn get_codec(&self, address: &Address) -> crate::codec::Codec {
+        let codecs = self.codecs.lock().unwrap();
+        codecs.get(address).copied().unwrap_or_default()
+    }
+
+    // encodes a message for address (see the module-level encode_into); SessionMessages are signed
+    // with the pre-shared key and everything else for address's session
+    pub(crate) fn encode_into(
+        &self,
+        sequence: u64,
+        address: &Address,
+        message: &crate::envelope::Message,
+        buf: &mut [u8],
+    ) -> anyhow::Result<usize> {
+        let codec = self.get_codec(address);
+
+        let Some(pre_shared_key) = &self.pre_shared_key else {
+            return Ok(encode_into(sequence, message, codec, None, buf)?);
+        };
+
+        if let crate::envelope::Message::Session(_) = message {
+            return Ok(encode_into(
+                sequence,
+                message,
+                codec,
+                Some(pre_shared_key),
+                buf,
+            )?);
+        }
+
+        let sessions = self.sessions.lock().unwrap();
+        let Some(session) = sessions
+            .get(address)
+            .and_then(|client_sessions| client_sessions.current.as_ref())
+        else {
+            anyhow::bail!("address={} isn't in a session", address);
+        };
+
+        Ok(encode_into(
+            sequence,
+            message,
+            codec,
+            Some(&session.session_key),
+            buf,
+        )?)
+    }
+
+    // only for datagrams that have been authenticated and sequenced, so spoofed datagrams can't
+    // change what a Client is answered in (or add to the map)
+    fn set_codec(&self, address: &Address, codec: crate::codec::Codec) {
+        let mut codecs = self.codecs.lock().unwrap();
+        if codecs.get(address) != Some(&codec) {
+            codecs.insert(address.clone(), codec);
+        }
+    }
+
+    pub(crate) fn get_link_stats_reader(
+        &self,
+    ) -> impl Fn() -> std::collections::HashMap<Address, crate::sequencing::LinkStats> {
+        let sequence_trackers = std::sync::Arc::clone(&self.sequence_trackers);
+        move || {
+            let sequence_trackers = sequence_trackers.lock().unwrap();
+            sequence_trackers
+                .iter()
+                .map(|(address, tracker)| (address.clone(), tracker.get_stats().clone()))
+                .collect()
+        }
+    }
+
+    pub(crate) fn get_malformed_stats_reader(
+        &self,
+    ) -> impl Fn() -> std::collections::HashMap<Address, crate::diagnostics::MalformedStats> {
+        let diagnostics = std::sync::Arc::clone(&self.diagnostics);
+        move || diagnostics.lock().unwrap().get_malformed_stats()
+    }
+
+    pub(crate) fn get_owner_reader(&self) -> impl Fn() -> Option<Address> {
+        let ownership = std::sync::Arc::clone(&self.ownership);
+        move || ownership.lock().unwrap().get_owner().cloned()
+    }
+
+    fn record_malformed(
+        &self,
+        address: &Address,
+        malformation: crate::diagnostics::Malformation,
+        reason: &dyn std::fmt::Display,
+    ) {
+        let mut diagnostics = self.diagnostics.lock().unwrap();
+        diagnostics.record(address, malformation, reason, self.clock.now());
+    }
+
+    // only datagrams signed for address's session (or Requests for one) get through; one from an
+    // address with no session is answered with an Expired, which is no bigger than what it sent so
+    // it can't be used to amplify anything
+    fn authenticate(
+        &self,
+        address: &Address,
+        buf: &[u8],
+        outbox: &mut Outbox,
+    ) -> Option<Authentication> {
+        let Some(pre_shared_key) = &self.pre_shared_key else {
+            return Some(Authentication::Unauthenticated);
+        };
+
+        let is_in_session = match self.sessions.lock().unwrap().get_mut(address) {
+            Some(client_sessions) => {
+                if client_sessions.verify(buf) {
+                    return Some(Authentication::Session);
+                }
+
+                if client_sessions.verify_pending(buf) {
+                    // nothing's been signed for the new session before, so it can start anywhere
+                    let mut sequence_trackers = self.sequence_trackers.lock().unwrap();
+                    sequence_trackers.insert(
+                        address.clone(),
+                        crate::sequencing::SequenceTracker::new_authenticated(),
+                    );
+
+                    return Some(Authentication::Session);
+                }
+
+                client_sessions.current.is_some()
+            }
+            None => false,
+        };
+
+        let Err(err) = crate::auth::verify(pre_shared_key, buf) else {
+            return Some(Authentication::PreSharedKey);
+        };
+
+        let verdict = match err {
+            crate::auth::Error::Unsigned => crate::sequencing::Verdict::Unsigned,
+            crate::auth::Error::BadSignature => {
+                // most likely signed for a session from before a restart (rather than corrupted,
+                // which a Client in a session shouldn't be thrown out of it for)
+                if !is_in_session {
+                    if let Some(sequence) = crate::envelope::decode(buf)
+                        .ok()
+                        .and_then(|envelope| envelope.sequence)
+                    {
+                        outbox.push((
+                            address.clone(),
+                            crate::envelope::Message::Session(
+                                crate::serialization::SessionMessage::Expired(sequence),
+                            ),
+                        ));
+                    }
+                }
+
+                crate::sequencing::Verdict::BadSignature
+            }
+        };
+
+        println!(
+            "dropping message from address={} because err={}",
+            address, err
+        );
+
+        let mut sequence_trackers = self.sequence_trackers.lock().unwrap();
+        self.get_sequence_tracker(&mut sequence_trackers, address)
+            .reject(verdict);
+
+        None
+    }
+
+    // the same Request again (e.g. resent while the answer was on its way) gets the same answer
+    fn handle_session_request(&self, address: &Address, challenge: u64, outbox: &mut Outbox) {
+        let Some(pre_shared_key) = &self.pre_shared_key else {
+            return;
+        };
+
+        let mut sessions = self.sessions.lock().unwrap();
+        let client_sessions = sessions.entry(address.clone()).or_default();
+
+        let nonce = client_sessions.get_nonce(challenge).unwrap_or_else(|| {
+            let nonce = crate::session::get_nonce();
+            client_sessions.pending = Some(Session {
+                challenge,
+                nonce,
+                session_key: pre_shared_key.get_session_key(nonce),
+            });
+            nonce
+        });
+
+        outbox.push((
+            address.clone(),
+            crate::envelope::Message::Session(crate::serialization::SessionMessage::Nonce {
+                nonce,
+                challenge,
+            }),
+        ));
+    }
+
+    fn get_sequence_tracker<'a>(
+        &self,
+        sequence_trackers: &'a mut SequenceTrackers,
+        address: &Address,
+    ) -> &'a mut crate::sequencing::SequenceTracker {
+        if self.pre_shared_key.is_none() {
+            return sequence_trackers.entry(address.clone()).or_default();
+        }
+
+        sequence_trackers
+            .entry(address.clone())
+            .or_insert_with(crate::sequencing::SequenceTracker::new_authenticated)
+    }
+
+    fn track(&self, address: &Address, envelope: &crate::envelope::Envelope) -> bool {
+        // unsequenced (older protocol version) messages can only be taken in arrival order; they
+        // can't be signed either, so authenticate() has already dropped them if there's a key
+        let (Some(sequence), Some(timestamp_us)) = (envelope.sequence, envelope.timestamp_us)
+        else {
+            return true;
+        };
+
+        let mut sequence_trackers = self.sequence_trackers.lock().unwrap();
+        let tracker = self.get_sequence_tracker(&mut sequence_trackers, address);
+
+        let verdict = tracker.track(sequence, timestamp_us);
+        if verdict != crate::sequencing::Verdict::Accept {
+            println!(
+                "dropping message from address={} because verdict={:?}, sequence={:?}, last_sequence={:?}",
+                address,
+                verdict,
+                sequence,
+                tracker.get_stats().last_sequence
+            );
+            return false;
+        }
+
+        true
+    }
+
+    // fills in the link stats for the address the TelemetryMessage is going to
+    pub(crate) fn get_telemetry_message(
+        &self,
+        address: &Address,
+        mut telemetry_message: crate::serialization::TelemetryMessage,
+    ) -> crate::envelope::Message {
+        let sequence_trackers = self.sequence_trackers.lock().unwrap();
+        telemetry_message.link_stats = sequence_trackers
+            .get(address)
+            .map(|sequence_tracker| sequence_tracker.get_stats().clone());
+
+        crate::envelope::Message::Telemetry(telemetry_message)
+    }
+
+    // lets the previous and the new owner know if f changed who holds the lease
+    fn update_ownership<T>(
+        &self,
+        outbox: &mut Outbox,
+        f: impl FnOnce(&mut crate::ownership::Ownership) -> T,
+    ) -> (T, Option<Address>) {
+        let (result, previous_owner, owner) = {
+            let mut ownership = self.ownership.lock().unwrap();
+            let previous_owner = ownership.get_owner().cloned();
+            let result = f(&mut ownership);
+            (result, previous_owner, ownership.get_owner().cloned())
+        };
+
+        if owner != previous_owner {
+            println!(
+                "lease changed hands from previous_owner={:?} to owner={:?}",
+                previous_owner, owner
+            );
+
+            if let Some(previous_owner) = &previous_owner {
+                outbox.push((
+                    previous_owner.clone(),
+                    crate::envelope::Message::Lease(crate::serialization::LeaseMessage::Revoked),
+                ));
+            }
+
+            if let Some(owner) = &owner {
+                outbox.push((
+                    owner.clone(),
+                    crate::envelope::Message::Lease(crate::serialization::LeaseMessage::Granted),
+                ));
+
+                // so the new owner knows what the channels of a ControlMessage mean
+                if let Some(vehicle_descriptor) = &self.vehicle_descriptor {
+                    outbox.push((
+                        owner.clone(),
+                        crate::envelope::Message::Descriptor(vehicle_descriptor.clone()),
+                    ));
+                }
+            }
+        }
+
+        (result, owner)
+    }
+
+    // returns whoever holds the lease (if anyone still does)
+    pub(crate) fn expire(&self, outbox: &mut Outbox) -> Option<Address> {
+        let (_, owner) =
+            self.update_ownership(outbox, |ownership| ownership.expire(self.clock.now()));
+
+        owner
+    }
+
+    fn handle_lease_message(
+        &self,
+        address: &Address,
+        lease_message: crate::serialization::LeaseMessage,
+        outbox: &mut Outbox,
+    ) {
+        let now = self.clock.now();
+
+        let (ok, _) = match &lease_message {
+            crate::serialization::LeaseMessage::Release => {
+                self.update_ownership(outbox, |ownership| ownership.release(address, now))
+            }
+            crate::serialization::LeaseMessage::HandOver(to) => {
+                self.update_ownership(outbox, |ownership| ownership.hand_over(address, to, now))
+            }
+            _ => (false, None),
+        };
+
+        if !ok {
+            println!(
+                "ignoring lease_message={:?} from address={}",
+                lease_message, address
+            );
+        }
+    }
+
+    // whether address holds the lease (taking it if nobody does); if not, it's told where it is in
+    // the queue
+    fn check_ownership(&self, address: &Address, outbox: &mut Outbox) -> bool {
+        let (verdict, owner) = self.update_ownership(outbox, |ownership| {
+            ownership.check(address, self.clock.now())
+        });
+
+        match verdict {
+            crate::ownership::Verdict::Accept => return true,
+            crate::ownership::Verdict::Queued { position, notify } => {
+                if notify {
+                    println!(
+                        "dropping message from address={} because owner={:?}, position={:?}",
+                        address, owner, position
+                    );
+
+                    outbox.push((
+                        address.clone(),
+                        crate::envelope::Message::Lease(
+                            crate::serialization::LeaseMessage::Queued(position as u32),
+                        ),
+                    ));
+                }
+            }
+        }
+
+        false
+    }
+
+    fn handle_input_message(
+        &self,
+        address: &Address,
+        input_message: crate::serialization::InputMessage,
+        outbox: &mut Outbox,
+    ) -> Option<crate::serialization::InputMessage> {
+        self.check_ownership(address, outbox)
+            .then_some(input_message)
+    }
+
+    // only from whoever holds the lease, as with InputMessages
+    fn handle_drive_mode_message(
+        &self,
+        address: &Address,
+        drive_mode_message: crate::serialization::DriveModeMessage,
+        outbox: &mut Outbox,
+    ) {
+        if !self.check_ownership(address, outbox) {
+            return;
+        }
+
+        let Some(drive_mode_message_sender) = &self.drive_mode_message_sender else {
+            println!(
+                "ignoring drive_mode_message={:?} from address={} because the vehicle has no drive modes",
+                drive_mode_message, address
+            );
+            return;
+        };
+
+        // nobody listening any more is fine
+        _ = drive_mode_message_sender.send(drive_mode_message);
+    }
+
+    // returns the InputMessage if buf held one that should be forwarded to the vehicle; buf may
+    // be one byte larger than BUF_SIZE so that an oversize datagram can be told from one that
+    // just fits
+    pub(crate) fn handle(
+        &self,
+        address: &Address,
+        buf: &[u8],
+        outbox: &mut Outbox,
+    ) -> Option<crate::serialization::InputMessage> {
+        if buf.len() > BUF_SIZE {
+            self.record_malformed(
+                address,
+                crate::diagnostics::Malformation::Oversize,
+                &format!("datagram is larger than {} bytes", BUF_SIZE),
+            );
+            return None;
+        }
+
+        let authentication = self.authenticate(address, buf, outbox)?;
+
+        let envelope = match crate::envelope::decode(buf) {
+            Ok(envelope) => envelope,
+            Err(err) => {
+                self.record_malformed(address, (&err).into(), &err);
+                return None;
+            }
+        };
+
+        // Requests aren't sequenced, as they're from before there's a session to track
+        if authentication == Authentication::PreSharedKey {
+            match envelope.message {
+                crate::envelope::Message::Session(
+                    crate::serialization::SessionMessage::Request(challenge),
+                ) => self.handle_session_request(address, challenge, outbox),
+                _ => println!(
+                    "ignoring message_type={:?} from address={} because it isn't signed for a session",
+                    envelope.message.get_message_type(),
+                    address
+                ),
+            }
+
+            return None;
+        }
+
+        if !self.track(address, &envelope) {
+            return None;
+        }
+
+        self.set_codec(address, envelope.codec);
+
+        match envelope.message {
+            crate::envelope::Message::Input(input_message) => {
+                return self.handle_input_message(address, input_message, outbox);
+            }
+            // the vehicle still takes InputMessages, so its channels are laid out the legacy way
+            crate::envelope::Message::Control(control_message) => {
+                return self.handle_input_message(address, control_message.into(), outbox);
+            }
+            crate::envelope::Message::Lease(lease_message) => {
+                self.handle_lease_message(address, lease_message, outbox);
+            }
+            crate::envelope::Message::DriveMode(drive_mode_message) => {
+                self.handle_drive_mode_message(address, drive_mode_message, outbox);
+            }
+            // answered whether or not address holds the lease, so a waiting controller can tell
+            // how good its link is too
+            crate::envelope::Message::Ping(crate::serialization::PingMessage::Ping(id)) => {
+                outbox.push((
+                    address.clone(),
+                    crate::envelope::Message::Ping(crate::serialization::PingMessage::Pong(id)),
+                ));
+            }
+            crate::envelope::Message::Telemetry(_)
+            | crate::envelope::Message::Beacon(_)
+            | crate::envelope::Message::Descriptor(_)
+            | crate::envelope::Message::Session(_)
+            | crate::envelope::Message::Ping(crate::serialization::PingMessage::Pong(_)) => {
+                println!(
+                    "ignoring unexpected message_type={:?} from address={}",
+                    envelope.message.get_message_type(),
+                    address
+                );
+            }
+        }
+
+        None
+    }
+}
+
 pub struct Server {
-    bind_address: std::net::SocketAddr,
-    socket: std::net::UdpSocket,
+    bind_address: Address,
+    transport: Box<dyn Transport>,
     incoming_input_message_sender: std::sync::mpsc::Sender<crate::serialization::InputMessage>,
+    outgoing_telemetry_message_sender:
+        std::sync::mpsc::Sender<crate::serialization::TelemetryMessage>,
+    outgoing_telemetry_message_receiver:
+        std::sync::mpsc::Receiver<crate::serialization::TelemetryMessage>,
     closed: std::sync::Arc<std::sync::Mutex<bool>>,
+    state: ServerState,
+    recorder: Option<std::sync::Mutex<crate::recording::Recorder>>,
 }
 
-impl Server {
-    pub fn new(
-        bind_address: std::net::SocketAddr,
-        incoming_input_message_sender: std::sync::mpsc::Sender<crate::serialization::InputMessage>,
-    ) -> Result<Self, anyhow::Error> {
-        let socket = get_socket(bind_address)?;
+impl Server {
+    pub fn new(
+        bind_address: std::net::SocketAddr,
+        incoming_input_message_sender: std::sync::mpsc::Sender<crate::serialization::InputMessage>,
+    ) -> Result<Self, anyhow::Error> {
+        Ok(Self::new_with_transport(
+            Box::new(udp::UdpTransport::new(bind_address)?),
+            incoming_input_message_sender,
+        ))
+    }
+
+    pub fn new_with_transport(
+        transport: Box<dyn Transport>,
+        incoming_input_message_sender: std::sync::mpsc::Sender<crate::serialization::InputMessage>,
+    ) -> Self {
+        let (outgoing_telemetry_message_sender, outgoing_telemetry_message_receiver) =
+            get_telemetry_message_sender_and_receiver();
+
+        Self {
+            bind_address: transport.get_local_address(),
+            transport,
+            incoming_input_message_sender,
+            outgoing_telemetry_message_sender,
+            outgoing_telemetry_message_receiver,
+            closed: std::sync::Arc::new(std::sync::Mutex::new(false)),
+            state: ServerState::new(),
+            recorder: None,
+        }
+    }
+
+    // how long the controller holding the lease can go quiet before somebody else can take over
+    pub fn set_lease_timeout(&mut self, lease_timeout: std::time::Duration) {
+        self.state.set_lease_timeout(lease_timeout);
+    }
+
+    // once set, only datagrams signed with this key (and not replayed) are accepted
+    pub fn set_pre_shared_key(&mut self, pre_shared_key: crate::auth::PreSharedKey) {
+        self.state.set_pre_shared_key(pre_shared_key);
+    }
+
+    // sent to each controller as it's granted the lease
+    pub fn set_vehicle_descriptor(
+        &mut self,
+        vehicle_descriptor: crate::serialization::VehicleDescriptor,
+    ) {
+        self.state.set_vehicle_descriptor(vehicle_descriptor);
+    }
+
+    // every InputMessage passed on to the vehicle is recorded here (see crate::recording)
+    pub fn set_recorder(&mut self, recorder: crate::recording::Recorder) {
+        self.recorder = Some(std::sync::Mutex::new(recorder));
+    }
+
+    // DriveModeMessages from whoever holds the lease are sent here; they're ignored otherwise
+    pub fn set_incoming_drive_mode_message_sender(
+        &mut self,
+        incoming_drive_mode_message_sender: std::sync::mpsc::Sender<
+            crate::serialization::DriveModeMessage,
+        >,
+    ) {
+        self.state
+            .set_drive_mode_message_sender(incoming_drive_mode_message_sender);
+    }
+
+    // what leases are timed against; the system clock by default
+    pub fn set_clock(&mut self, clock: std::sync::Arc<dyn crate::clock::Clock>) {
+        self.state.set_clock(clock);
+    }
+
+    pub fn get_closer(&self) -> impl Fn() {
+        let closed = std::sync::Arc::clone(&self.closed);
+        move || {
+            let mut closed = closed.lock().unwrap();
+            *closed = true;
+        }
+    }
+
+    pub fn get_bind_address(&self) -> Address {
+        self.bind_address.clone()
+    }
+
+    // TelemetryMessages sent here go to whichever address last sent an accepted InputMessage
+    pub fn get_outgoing_telemetry_message_sender(
+        &self,
+    ) -> std::sync::mpsc::Sender<crate::serialization::TelemetryMessage> {
+        self.outgoing_telemetry_message_sender.clone()
+    }
+
+    pub fn get_link_stats_reader(
+        &self,
+    ) -> impl Fn() -> std::collections::HashMap<Address, crate::sequencing::LinkStats> {
+        self.state.get_link_stats_reader()
+    }
+
+    pub fn get_malformed_stats_reader(
+        &self,
+    ) -> impl Fn() -> std::collections::HashMap<Address, crate::diagnostics::MalformedStats> {
+        self.state.get_malformed_stats_reader()
+    }
+
+    pub fn get_owner_reader(&self) -> impl Fn() -> Option<Address> {
+        self.state.get_owner_reader()
+    }
+
+    fn send(&self, outbox: Outbox, sequence: &mut u64, buf: &mut [u8]) {
+        for (address, message) in outbox {
+            let n = match self.state.encode_into(*sequence, &address, &message, buf) {
+                Ok(n) => n,
+                Err(err) => {
+                    println!("encode_into() failed because err={:?}", err);
+                    continue;
+                }
+            };
+            *sequence += 1;
+
+            if let Err(err) = self.transport.send_to(&buf[0..n], &address) {
+                println!("send_to() failed because err={:?}", err);
+            }
+        }
+    }
+
+    pub fn run(&self) -> anyhow::Result<()> {
+        // one byte spare so that we can tell an oversize datagram from one that just fits
+        let mut buf = vec![0; BUF_SIZE + 1];
+
+        // anything bigger wouldn't be accepted at the other end
+        let mut send_buf = vec![0; BUF_SIZE];
+
+        let mut sequence: u64 = crate::envelope::get_timestamp_us();
+
+        loop {
+            {
+                let closed = self.closed.lock().unwrap();
+                if *closed {
+                    break;
+                }
+            }
+
+            // TelemetryMessages go to whoever holds the lease (if anyone still does)
+            let mut outbox = vec![];
+            let owner = self.state.expire(&mut outbox);
+            for telemetry_message in self.outgoing_telemetry_message_receiver.try_iter() {
+                // nobody to send to yet
+                let Some(owner) = &owner else {
+                    continue;
+                };
+
+                outbox.push((
+                    owner.clone(),
+                    self.state.get_telemetry_message(owner, telemetry_message),
+                ));
+            }
+            self.send(outbox, &mut sequence, &mut send_buf);
+
+            let recv_from_result = self.transport.recv_from(&mut buf);
+            if recv_from_result.is_err() {
+                let err = recv_from_result.err().unwrap();
+                if is_timeout(&err) {
+                    continue;
+                }
+
+                println!("recv_from() failed because err={:?}", err);
+                return Err(err.into());
+            }
+
+            let (n, address) = recv_from_result?;
+
+            let mut outbox = vec![];
+            let input_message = self.state.handle(&address, &buf[0..n], &mut outbox);
+            self.send(outbox, &mut sequence, &mut send_buf);
+
+            if let Some(input_message) = input_message {
+                record(&self.recorder, &input_message);
+                self.incoming_input_message_sender.send(input_message)?;
+            }
+        }
+
+        drop(self.incoming_input_message_sender.clone());
+
+        Ok(())
+    }
+}
+
+// a failed recording shouldn't stop the vehicle (or the controller)
+fn record(
+    recorder: &Option<std::sync::Mutex<crate::recording::Recorder>>,
+    input_message: &crate::serialization::InputMessage,
+) {
+    let Some(recorder) = recorder else {
+        return;
+    };
+
+    if let Err(err) = recorder.lock().unwrap().record(input_message) {
+        println!("record() failed because err={:?}", err);
+    }
+}
+
+// returns the message in buf if it's one a Client should pass on (i.e. a TelemetryMessage, a
+// LeaseMessage or a VehicleDescriptor); pongs and (if there's a key) SessionMessages are handled here
+pub(crate) fn handle_client_datagram(
+    address: &Address,
+    buf: &[u8],
+    session: Option<&mut crate::session::ClientSession>,
+    latency_tracker: &std::sync::Mutex<crate::latency::LatencyTracker>,
+    clock: &dyn crate::clock::Clock,
+) -> Option<crate::envelope::Message> {
+    let envelope = match session {
+        Some(session) => session.receive(address, buf)?,
+        None => match crate::envelope::decode(buf) {
+            Ok(envelope) => envelope,
+            Err(err) => {
+                println!(
+                    "dropping message from address={} because err={}",
+                    address, err
+                );
+                return None;
+            }
+        },
+    };
+
+    match envelope.message {
+        crate::envelope::Message::Telemetry(_)
+        | crate::envelope::Message::Lease(_)
+        | crate::envelope::Message::Descriptor(_) => Some(envelope.message),
+        crate::envelope::Message::Ping(crate::serialization::PingMessage::Pong(id)) => {
+            let mut latency_tracker = latency_tracker.lock().unwrap();
+            latency_tracker.pong(id, clock.now());
+            None
+        }
+        crate::envelope::Message::Input(_)
+        | crate::envelope::Message::Control(_)
+        | crate::envelope::Message::DriveMode(_)
+        | crate::envelope::Message::Beacon(_)
+        | crate::envelope::Message::Session(_)
+        | crate::envelope::Message::Ping(crate::serialization::PingMessage::Ping(_)) => None,
+    }
+}
+
+// wherever run_client_receive passes on what it receives to (if anywhere)
+struct IncomingSenders<'a> {
+    telemetry_message_sender:
+        &'a Option<std::sync::mpsc::Sender<crate::serialization::TelemetryMessage>>,
+    lease_message_sender: &'a Option<std::sync::mpsc::Sender<crate::serialization::LeaseMessage>>,
+    vehicle_descriptor_sender:
+        &'a Option<std::sync::mpsc::Sender<crate::serialization::VehicleDescriptor>>,
+}
+
+fn run_client_receive(
+    transport: &dyn Transport,
+    closed: &std::sync::Mutex<bool>,
+    session: &std::sync::Mutex<Option<crate::session::ClientSession>>,
+    joined: &std::sync::Condvar,
+    latency_tracker: &std::sync::Mutex<crate::latency::LatencyTracker>,
+    clock: &dyn crate::clock::Clock,
+    incoming_senders: IncomingSenders,
+) -> anyhow::Result<()> {
+    let IncomingSenders {
+        telemetry_message_sender: incoming_telemetry_message_sender,
+        lease_message_sender: incoming_lease_message_sender,
+        vehicle_descriptor_sender: incoming_vehicle_descriptor_sender,
+    } = incoming_senders;
+
+    let mut buf = vec![0; BUF_SIZE];
+
+    loop {
+        if *closed.lock().unwrap() {
+            break;
+        }
+
+        let recv_from_result = transport.recv_from(&mut buf);
+        if recv_from_result.is_err() {
+            let err = recv_from_result.err().unwrap();
+            // nothing yet or the Server isn't there (yet)
+            if is_timeout(&err) || is_disconnected(&err) {
+                continue;
+            }
+
+            println!("recv_from() failed because err={:?}", err);
+            return Err(err.into());
+        }
+
+        let (n, address) = recv_from_result?;
+
+        let message = {
+            let mut session = session.lock().unwrap();
+            let message = handle_client_datagram(
+                &address,
+                &buf[0..n],
+                session.as_mut(),
+                latency_tracker,
+                clock,
+            );
+
+            // run_send may be waiting for the Server to answer
+            if session.as_ref().is_some_and(|session| session.is_joined()) {
+                joined.notify_all();
+            }
+
+            message
+        };
+
+        // nobody listening is fine
+        match message {
+            Some(crate::envelope::Message::Telemetry(telemetry_message)) => {
+                if let Some(incoming_telemetry_message_sender) = incoming_telemetry_message_sender {
+                    _ = incoming_telemetry_message_sender.send(telemetry_message);
+                }
+            }
+            Some(crate::envelope::Message::Lease(lease_message)) => {
+                if let Some(incoming_lease_message_sender) = incoming_lease_message_sender {
+                    _ = incoming_lease_message_sender.send(lease_message);
+                }
+            }
+            Some(crate::envelope::Message::Descriptor(vehicle_descriptor)) => {
+                if let Some(incoming_vehicle_descriptor_sender) = incoming_vehicle_descriptor_sender
+                {
+                    _ = incoming_vehicle_descriptor_sender.send(vehicle_descriptor);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+pub struct Client {
+    send_address: Address,
+    transport: Box<dyn Transport>,
+    outgoing_input_message_sender: std::sync::mpsc::Sender<crate::serialization::InputMessage>,
+    outgoing_input_message_receiver: std::sync::mpsc::Receiver<crate::serialization::InputMessage>,
+    incoming_telemetry_message_sender:
+        Option<std::sync::mpsc::Sender<crate::serialization::TelemetryMessage>>,
+    outgoing_lease_message_sender: std::sync::mpsc::Sender<crate::serialization::LeaseMessage>,
+    outgoing_lease_message_receiver: std::sync::mpsc::Receiver<crate::serialization::LeaseMessage>,
+    incoming_lease_message_sender:
+        Option<std::sync::mpsc::Sender<crate::serialization::LeaseMessage>>,
+    outgoing_control_message_sender: std::sync::mpsc::Sender<crate::serialization::ControlMessage>,
+    outgoing_control_message_receiver:
+        std::sync::mpsc::Receiver<crate::serialization::ControlMessage>,
+    incoming_vehicle_descriptor_sender:
+        Option<std::sync::mpsc::Sender<crate::serialization::VehicleDescriptor>>,
+    outgoing_drive_mode_message_sender:
+        std::sync::mpsc::Sender<crate::serialization::DriveModeMessage>,
+    outgoing_drive_mode_message_receiver:
+        std::sync::mpsc::Receiver<crate::serialization::DriveModeMessage>,
+    closed: std::sync::Arc<std::sync::Mutex<bool>>,
+    // only if there's a key, shared between run_send and run_client_receive
+    session: std::sync::Mutex<Option<crate::session::ClientSession>>,
+    joined: std::sync::Condvar,
+    latency_tracker: std::sync::Arc<std::sync::Mutex<crate::latency::LatencyTracker>>,
+    ping_interval: Option<std::time::Duration>,
+    codec: crate::codec::Codec,
+    recorder: Option<std::sync::Mutex<crate::recording::Recorder>>,
+    clock: std::sync::Arc<dyn crate::clock::Clock>,
+}
+
+impl Client {
+    pub fn new(send_address: std::net::SocketAddr) -> Result<Self, anyhow::Error> {
+        Ok(Self::new_with_transport(
+            Box::new(udp::UdpTransport::new("0.0.0.0:0".parse()?)?),
+            send_address.into(),
+        ))
+    }
+
+    pub fn new_with_transport(transport: Box<dyn Transport>, send_address: Address) -> Self {
+        let (outgoing_input_message_sender, outgoing_input_message_receiver) =
+            get_input_message_sender_and_receiver();
+        let (outgoing_lease_message_sender, outgoing_lease_message_receiver) =
+            std::sync::mpsc::channel();
+        let (outgoing_control_message_sender, outgoing_control_message_receiver) =
+            std::sync::mpsc::channel();
+        let (outgoing_drive_mode_message_sender, outgoing_drive_mode_message_receiver) =
+            std::sync::mpsc::channel();
+
+        Self {
+            send_address,
+            transport,
+            outgoing_input_message_sender,
+            outgoing_input_message_receiver,
+            incoming_telemetry_message_sender: None,
+            outgoing_lease_message_sender,
+            outgoing_lease_message_receiver,
+            incoming_lease_message_sender: None,
+            outgoing_control_message_sender,
+            outgoing_control_message_receiver,
+            incoming_vehicle_descriptor_sender: None,
+            outgoing_drive_mode_message_sender,
+            outgoing_drive_mode_message_receiver,
+            closed: std::sync::Arc::new(std::sync::Mutex::new(false)),
+            session: std::sync::Mutex::new(None),
+            joined: std::sync::Condvar::new(),
+            latency_tracker: std::sync::Arc::new(std::sync::Mutex::new(
+                crate::latency::LatencyTracker::new(),
+            )),
+            ping_interval: Some(crate::latency::PING_INTERVAL),
+            codec: crate::codec::Codec::default(),
+            recorder: None,
+            clock: crate::clock::get_system_clock(),
+        }
+    }
+
+    // what to encode with; the Server answers in kind
+    pub fn set_codec(&mut self, codec: crate::codec::Codec) {
+        self.codec = codec;
+    }
+
+    // None stops the Client pinging the Server (e.g. for a vehicle too old to answer)
+    pub fn set_ping_interval(&mut self, ping_interval: Option<std::time::Duration>) {
+        self.ping_interval = ping_interval;
+    }
+
+    // TelemetryMessages received from the Server are sent here
+    pub fn set_incoming_telemetry_message_sender(
+        &mut self,
+        incoming_telemetry_message_sender: std::sync::mpsc::Sender<
+            crate::serialization::TelemetryMessage,
+        >,
+    ) {
+        self.incoming_telemetry_message_sender = Some(incoming_telemetry_message_sender);
+    }
+
+    // LeaseMessages (Granted, Revoked, Queued) received from the Server are sent here
+    pub fn set_incoming_lease_message_sender(
+        &mut self,
+        incoming_lease_message_sender: std::sync::mpsc::Sender<crate::serialization::LeaseMessage>,
+    ) {
+        self.incoming_lease_message_sender = Some(incoming_lease_message_sender);
+    }
+
+    // the Server's VehicleDescriptor (if it has one) is sent here whenever the lease is granted
+    pub fn set_incoming_vehicle_descriptor_sender(
+        &mut self,
+        incoming_vehicle_descriptor_sender: std::sync::mpsc::Sender<
+            crate::serialization::VehicleDescriptor,
+        >,
+    ) {
+        self.incoming_vehicle_descriptor_sender = Some(incoming_vehicle_descriptor_sender);
+    }
+
+    // once set, every datagram is signed for a session the Server gives out with this key (see
+    // crate::session), so nothing's sent until the Server has answered
+    pub fn set_pre_shared_key(&mut self, pre_shared_key: crate::auth::PreSharedKey) {
+        *self.session.get_mut().unwrap() = Some(crate::session::ClientSession::new(pre_shared_key));
+    }
+
+    // every InputMessage (and ControlMessage, as an InputMessage) sent is recorded here (see
+    // crate::recording)
+    pub fn set_recorder(&mut self, recorder: crate::recording::Recorder) {
+        self.recorder = Some(std::sync::Mutex::new(recorder));
+    }
+
+    // what pings are timed against; the system clock by default
+    pub fn set_clock(&mut self, clock: std::sync::Arc<dyn crate::clock::Clock>) {
+        self.clock = clock;
+    }
+
+    pub fn get_closer(&self) -> impl Fn() {
+        let closed = std::sync::Arc::clone(&self.closed);
+        move || {
+            let mut closed = closed.lock().unwrap();
+            *closed = true;
+        }
+    }
+
+    pub fn get_bind_address(&self) -> Address {
+        self.transport.get_local_address()
+    }
+
+    pub fn get_send_address(&self) -> Address {
+        self.send_address.clone()
+    }
+
+    pub fn get_outgoing_input_message_sender(
+        &self,
+    ) -> std::sync::mpsc::Sender<crate::serialization::InputMessage> {
+        self.outgoing_input_message_sender.clone()
+    }
+
+    // for LeaseMessage::Release and LeaseMessage::HandOver
+    pub fn get_outgoing_lease_message_sender(
+        &self,
+    ) -> std::sync::mpsc::Sender<crate::serialization::LeaseMessage> {
+        self.outgoing_lease_message_sender.clone()
+    }
+
+    // the generic alternative to get_outgoing_input_message_sender
+    pub fn get_outgoing_control_message_sender(
+        &self,
+    ) -> std::sync::mpsc::Sender<crate::serialization::ControlMessage> {
+        self.outgoing_control_message_sender.clone()
+    }
+
+    // for switching the vehicle's drive mode (see TelemetryMessage::drive_mode for the one it's in)
+    pub fn get_outgoing_drive_mode_message_sender(
+        &self,
+    ) -> std::sync::mpsc::Sender<crate::serialization::DriveModeMessage> {
+        self.outgoing_drive_mode_message_sender.clone()
+    }
+
+    // round trip time, jitter and loss as measured by pinging the Server
+    pub fn get_latency_stats_reader(&self) -> impl Fn() -> crate::latency::LatencyStats {
+        let latency_tracker = std::sync::Arc::clone(&self.latency_tracker);
+        let clock = std::sync::Arc::clone(&self.clock);
+        move || {
+            let latency_tracker = latency_tracker.lock().unwrap();
+            latency_tracker.get_stats(clock.now())
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        *self.closed.lock().unwrap()
+    }
+
+    fn get_ping_message(
+        &self,
+        last_pinged_at: &mut Option<std::time::Instant>,
+    ) -> Option<crate::envelope::Message> {
+        let ping_interval = self.ping_interval?;
+
+        let now = self.clock.now();
+
+        if last_pinged_at.is_some_and(|last_pinged_at| now - last_pinged_at < ping_interval) {
+            return None;
+        }
+        *last_pinged_at = Some(now);
+
+        let id = self.latency_tracker.lock().unwrap().ping(now);
+
+        Some(crate::envelope::Message::Ping(
+            crate::serialization::PingMessage::Ping(id),
+        ))
+    }
+
+    // asks the Server for a session (if there's a key and it isn't in one) and waits a while for
+    // the answer; returns whether there's a session to sign with
+    fn join_session(&self, sequence: &mut u64, buf: &mut [u8]) -> anyhow::Result<bool> {
+        let session = self.session.lock().unwrap();
+        let Some(client_session) = session.as_ref() else {
+            return Ok(true);
+        };
+
+        if client_session.is_joined() {
+            return Ok(true);
+        }
+
+        let n = client_session.encode_request_into(*sequence, self.codec, buf)?;
+        *sequence += 1;
+
+        self.send(&buf[0..n])?;
+
+        let (session, _) = self
+            .joined
+            .wait_timeout_while(session, MESSAGE_TIMEOUT, |session| {
+                !session.as_ref().is_some_and(|session| session.is_joined())
+            })
+            .unwrap();
+        if session.as_ref().is_some_and(|session| session.is_joined()) {
+            return Ok(true);
+        }
+
+        // only the latest InputMessage / ControlMessage is worth sending once there's a session,
+        // rather than everything that's piled up while the Server wasn't there
+        if let Some(input_message) = self.outgoing_input_message_receiver.try_iter().last() {
+            _ = self.outgoing_input_message_sender.send(input_message);
+        }
+        if let Some(control_message) = self.outgoing_control_message_receiver.try_iter().last() {
+            _ = self.outgoing_control_message_sender.send(control_message);
+        }
+
+        Ok(false)
+    }
+
+    fn run_send(&self) -> anyhow::Result<()> {
+        // seeded from the clock so that sequence numbers keep increasing across restarts, which
+        // an authenticated Server relies on to tell a new Client from a replay
+        let mut sequence: u64 = crate::envelope::get_timestamp_us();
+
+        let mut last_pinged_at = None;
+
+        let mut buf = vec![0; BUF_SIZE];
+
+        loop {
+            if self.is_closed() {
+                break;
+            }
+
+            if !self.join_session(&mut sequence, &mut buf)? {
+                continue;
+            }
+
+            let lease_messages = self
+                .outgoing_lease_message_receiver
+                .try_iter()
+                .map(crate::envelope::Message::Lease);
+
+            let input_message = self
+                .outgoing_input_message_receiver
+                .recv_timeout(MESSAGE_TIMEOUT)
+                .ok()
+                .map(crate::envelope::Message::Input);
+
+            let control_messages = self
+                .outgoing_control_message_receiver
+                .try_iter()
+                .map(crate::envelope::Message::Control);
+
+            let drive_mode_messages = self
+                .outgoing_drive_mode_message_receiver
+                .try_iter()
+                .map(crate::envelope::Message::DriveMode);
+
+            // after waiting for input, so the ping is timed from when it's actually sent
+            let ping_message = self.get_ping_message(&mut last_pinged_at);
+
+            for message in lease_messages
+                .chain(input_message)
+                .chain(control_messages)
+                .chain(drive_mode_messages)
+                .chain(ping_message)
+            {
+                match &message {
+                    crate::envelope::Message::Input(input_message) => {
+                        record(&self.recorder, input_message)
+                    }
+                    crate::envelope::Message::Control(control_message) => {
+                        record(&self.recorder, &control_message.clone().into())
+                    }
+                    _ => {}
+                }
+
+                // goes as a Request instead if the session has expired since join_session
+                let n = match self.session.lock().unwrap().as_mut() {
+                    Some(session) => {
+                        session.encode_into(sequence, &message, self.codec, &mut buf)?
+                    }
+                    None => encode_into(sequence, &message, self.codec, None, &mut buf)?,
+                };
+                sequence += 1;
+
+                self.send(&buf[0..n])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn send(&self, buf: &[u8]) -> anyhow::Result<()> {
+        if let Err(err) = self.transport.send_to(buf, &self.send_address) {
+            if !is_disconnected(&err) {
+                return Err(err.into());
+            }
+
+            println!("send_to() failed because err={:?}", err);
+        }
+
+        Ok(())
+    }
+
+    pub fn run(&self) -> anyhow::Result<()> {
+        let transport = self.transport.as_ref();
+        let closed = &self.closed;
+        let session = &self.session;
+        let joined = &self.joined;
+        let latency_tracker = self.latency_tracker.as_ref();
+        let clock = self.clock.as_ref();
+        let incoming_senders = IncomingSenders {
+            telemetry_message_sender: &self.incoming_telemetry_message_sender,
+            lease_message_sender: &self.incoming_lease_message_sender,
+            vehicle_descriptor_sender: &self.incoming_vehicle_descriptor_sender,
+        };
+
+        std::thread::scope(|scope| {
+            let receive_handle = scope.spawn(|| {
+                run_client_receive(
+                    transport,
+                    closed,
+                    session,
+                    joined,
+                    latency_tracker,
+                    clock,
+                    incoming_senders,
+                )
+            });
+
+            let send_result = self.run_send();
+            if send_result.is_err() {
+                // make sure the receive side stops too
+                *self.closed.lock().unwrap() = true;
+            }
+
+            let receive_result = receive_handle.join().unwrap();
+
+            send_result.and(receive_result)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // InputMessages one way and TelemetryMessages the other, over any pair of Transports
+    pub(super) fn assert_round_trip(
+        server_transport: Box<dyn Transport>,
+        client_transport: Box<dyn Transport>,
+    ) -> anyhow::Result<()> {
+        let (incoming_input_message_sender, incoming_input_message_receiver) =
+            get_input_message_sender_and_receiver();
+        let (incoming_telemetry_message_sender, incoming_telemetry_message_receiver) =
+            get_telemetry_message_sender_and_receiver();
+
+        let server = Server::new_with_transport(server_transport, incoming_input_message_sender);
+        let mut client = Client::new_with_transport(client_transport, server.get_bind_address());
+        client.set_incoming_telemetry_message_sender(incoming_telemetry_message_sender);
+        client.set_ping_interval(None);
+
+        let server_closer = server.get_closer();
+        let client_closer = client.get_closer();
+
+        let outgoing_input_message_sender = client.get_outgoing_input_message_sender();
+        let outgoing_telemetry_message_sender = server.get_outgoing_telemetry_message_sender();
+
+        let server_handle = std::thread::spawn(move || {
+            server.run().unwrap();
+        });
+
+        let client_handle = std::thread::spawn(move || {
+            client.run().unwrap();
+        });
+
+        let outgoing_input_message = crate::serialization::InputMessage {
+            throttle: 0.69,
+            steering: 0.69,
+            throttle_left: 0.69,
+            throttle_right: 0.69,
+            steering_left: 0.69,
+            steering_right: 0.69,
+            mode_up: true,
+            mode_down: true,
+            mode_left: true,
+            mode_right: true,
+            handbrake: true,
+        };
+
+        for _ in 0..3 {
+            outgoing_input_message_sender.send(outgoing_input_message.clone())?;
+            let incoming_input_message =
+                incoming_input_message_receiver.recv_timeout(std::time::Duration::from_secs(1))?;
+            assert_eq!(outgoing_input_message, incoming_input_message);
+        }
+
+        let outgoing_telemetry_message = crate::serialization::TelemetryMessage {
+            throttle: 0.69,
+            steering: 0.69,
+            throttle_left: 0.69,
+            throttle_right: 0.69,
+            throttle_min: -0.69,
+            throttle_max: 0.69,
+            steering_offset: 0.01,
+            failsafe: false,
+            armed: true,
+            battery_voltage: Some(7.4),
+            drive_mode: None,
+            link_stats: None,
+        };
+
+        outgoing_telemetry_message_sender.send(outgoing_telemetry_message.clone())?;
+        let incoming_telemetry_message =
+            incoming_telemetry_message_receiver.recv_timeout(std::time::Duration::from_secs(1))?;
+        assert_eq!(incoming_telemetry_message.link_stats.unwrap().accepted, 3);
+
+        server_closer();
+        client_closer();
+
+        server_handle.join().unwrap();
+        client_handle.join().unwrap();
+
+        Ok(())
+    }
+
+    #[test]
+    fn happy_path() -> anyhow::Result<()> {
+        let (incoming_input_message_sender, incoming_input_message_receiver) =
+            get_input_message_sender_and_receiver();
+
+        let server = Server::new("0.0.0.0:0".parse()?, incoming_input_message_sender)?;
+        let client = Client::new(server.get_bind_address().get_socket_address().unwrap())?;
+
+        let server_closer = server.get_closer();
+        let client_closer = client.get_closer();
+
+        let outgoing_input_message_sender = client.get_outgoing_input_message_sender();
+
+        let server_handle = std::thread::spawn(move || {
+            server.run().unwrap();
+        });
+
+        let client_handle = std::thread::spawn(move || {
+            client.run().unwrap();
+        });
+
+        let outgoing_input_message = crate::serialization::InputMessage {
+            throttle: 0.69,
+            steering: 0.69,
+            throttle_left: 0.69,
+            throttle_right: 0.69,
+            steering_left: 0.69,
+            steering_right: 0.69,
+            mode_up: true,
+            mode_down: true,
+            mode_left: true,
+            mode_right: true,
+            handbrake: true,
+        };
+
+        let incoming_input_message = {
+            outgoing_input_message_sender.send(outgoing_input_message.clone())?;
+            incoming_input_message_receiver.recv()?
+        };
+
+        assert_eq!(outgoing_input_message, incoming_input_message);
+
+        server_closer();
+        client_closer();
+
+        server_handle.join().unwrap();
+        client_handle.join().unwrap();
+
+        Ok(())
+    }
+
+    #[test]
+    fn no_server() -> anyhow::Result<()> {
+        let client = Client::new("127.0.0.1:13337".parse()?)?;
+
+        let client_closer = client.get_closer();
+
+        let outgoing_input_message_sender = client.get_outgoing_input_message_sender();
+
+        let client_handle = std::thread::spawn(move || {
+            client.run().unwrap();
+        });
+
+        let outgoing_input_message = crate::serialization::InputMessage {
+            throttle: 0.69,
+            steering: 0.69,
+            throttle_left: 0.69,
+            throttle_right: 0.69,
+            steering_left: 0.69,
+            steering_right: 0.69,
+            mode_up: true,
+            mode_down: true,
+            mode_left: true,
+            mode_right: true,
+            handbrake: true,
+        };
+
+        outgoing_input_message_sender.send(outgoing_input_message.clone())?;
+
+        client_closer();
+
+        client_handle.join().unwrap();
+
+        Ok(())
+    }
+
+    #[test]
+    fn no_client() -> anyhow::Result<()> {
+        let (incoming_input_message_sender, incoming_input_message_receiver) =
+            get_input_message_sender_and_receiver();
+
+        let server = Server::new("0.0.0.0:0".parse()?, incoming_input_message_sender)?;
+
+        let server_closer = server.get_closer();
+
+        let server_handle = std::thread::spawn(move || {
+            server.run().unwrap();
+        });
+
+        _ = incoming_input_message_receiver.recv_timeout(std::time::Duration::from_secs(1));
+
+        server_closer();
+
+        server_handle.join().unwrap();
+
+        Ok(())
+    }
+
+    #[test]
+    fn out_of_order_and_duplicates() -> anyhow::Result<()> {
+        let (incoming_input_message_sender, incoming_input_message_receiver) =
+            get_input_message_sender_and_receiver();
+
+        let server = Server::new("127.0.0.1:0".parse()?, incoming_input_message_sender)?;
+
+        let server_closer = server.get_closer();
+        let link_stats_reader = server.get_link_stats_reader();
+        let server_address = server.get_bind_address();
+
+        let server_handle = std::thread::spawn(move || {
+            server.run().unwrap();
+        });
+
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0")?;
+
+        let mut outgoing_input_message = crate::serialization::InputMessage {
+            throttle: 0.0,
+            steering: 0.0,
+            throttle_left: 0.0,
+            throttle_right: 0.0,
+            steering_left: 0.0,
+            steering_right: 0.0,
+            mode_up: false,
+            mode_down: false,
+            mode_left: false,
+            mode_right: false,
+            handbrake: false,
+        };
+
+        for sequence in [1, 2, 4, 4, 3, 5] {
+            outgoing_input_message.throttle = sequence as f32;
+            let buf = crate::envelope::encode(
+                sequence,
+                crate::envelope::get_timestamp_us(),
+                &crate::envelope::Message::Input(outgoing_input_message.clone()),
+            )?;
+            socket.send_to(&buf, &server_address)?;
+        }
+
+        let mut throttles = vec![];
+        for _ in 0..4 {
+            let incoming_input_message =
+                incoming_input_message_receiver.recv_timeout(std::time::Duration::from_secs(1))?;
+            throttles.push(incoming_input_message.throttle);
+        }
+
+        assert_eq!(throttles, vec![1.0, 2.0, 4.0, 5.0]);
+        assert!(incoming_input_message_receiver
+            .recv_timeout(MESSAGE_TIMEOUT)
+            .is_err());
+
+        let link_stats = link_stats_reader();
+        let link_stats = link_stats.get(&socket.local_addr()?.into()).unwrap();
+        assert_eq!(link_stats.received, 6);
+        assert_eq!(link_stats.accepted, 4);
+        assert_eq!(link_stats.lost, 0);
+        assert_eq!(link_stats.reordered, 1);
+        assert_eq!(link_stats.duplicated, 1);
+
+        server_closer();
+
+        server_handle.join().unwrap();
+
+        Ok(())
+    }
+
+    #[test]
+    fn authenticated() -> anyhow::Result<()> {
+        let pre_shared_key = crate::auth::PreSharedKey::new(b"car123!@#");
+
+        let (incoming_input_message_sender, incoming_input_message_receiver) =
+            get_input_message_sender_and_receiver();
+
+        let mut server = Server::new("127.0.0.1:0".parse()?, incoming_input_message_sender)?;
+        server.set_pre_shared_key(pre_shared_key.clone());
+
+        let mut client = Client::new(server.get_bind_address().get_socket_address().unwrap())?;
+        client.set_pre_shared_key(pre_shared_key.clone());
+        client.set_ping_interval(None);
+
+        let server_closer = server.get_closer();
+        let client_closer = client.get_closer();
+        let link_stats_reader = server.get_link_stats_reader();
+        let server_address = server.get_bind_address();
+        let client_address = client.get_bind_address();
+
+        let outgoing_input_message_sender = client.get_outgoing_input_message_sender();
+
+        let server_handle = std::thread::spawn(move || {
+            server.run().unwrap();
+        });
+
+        let client_handle = std::thread::spawn(move || {
+            client.run().unwrap();
+        });
+
+        let mut outgoing_input_message = crate::serialization::InputMessage {
+            throttle: 0.0,
+            steering: 0.0,
+            throttle_left: 0.0,
+            throttle_right: 0.0,
+            steering_left: 0.0,
+            steering_right: 0.0,
+            mode_up: false,
+            mode_down: false,
+            mode_left: false,
+            mode_right: false,
+            handbrake: false,
+        };
+
+        let incoming_input_message = {
+            outgoing_input_message_sender.send(outgoing_input_message.clone())?;
+            incoming_input_message_receiver.recv_timeout(std::time::Duration::from_secs(1))?
+        };
+
+        assert_eq!(outgoing_input_message, incoming_input_message);
+
+        outgoing_input_message.throttle = 1.0;
+        let message = crate::envelope::Message::Input(outgoing_input_message.clone());
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0")?;
+        socket.set_read_timeout(Some(std::time::Duration::from_secs(1)))?;
+
+        // anybody with the key can get a session of their own (which doesn't count as a datagram)
+        let mut session = crate::session::ClientSession::new(pre_shared_key);
+        let mut buf = vec![0; BUF_SIZE];
+        let n = session.encode_request_into(1, crate::codec::Codec::default(), &mut buf)?;
+        socket.send_to(&buf[0..n], &server_address)?;
+
+        let (n, _) = socket.recv_from(&mut buf)?;
+        assert!(session.receive(&server_address, &buf[0..n]).is_none());
+        assert!(session.is_joined());
+
+        // signed for that session, but whoever holds the lease is still the only one driving
+        let n = session.encode_into(10, &message, crate::codec::Codec::default(), &mut buf)?;
+        socket.send_to(&buf[0..n], &server_address)?;
+        socket.send_to(&buf[0..n], &server_address)?;
+
+        // unsigned
+        let buf = crate::envelope::encode(u64::MAX, 0, &message)?;
+        socket.send_to(&buf, &server_address)?;
+
+        // signed with the wrong key
+        let mut buf = crate::envelope::encode(u64::MAX, 0, &message)?;
+        crate::auth::sign(&crate::auth::PreSharedKey::new(b"truck123!@#"), &mut buf);
+        socket.send_to(&buf, &server_address)?;
+
+        assert!(incoming_input_message_receiver
+            .recv_timeout(MESSAGE_TIMEOUT * 4)
+            .is_err());
+
+        let link_stats = link_stats_reader();
+
+        let (_, client_link_stats) = link_stats
+            .iter()
+            .find(|(address, _)| {
+                address.get_socket_address().map(|address| address.port())
+                    == client_address
+                        .get_socket_address()
+                        .map(|address| address.port())
+            })
+            .unwrap();
+        assert_eq!(client_link_stats.accepted, 1);
+
+        let spoofer_link_stats = link_stats.get(&socket.local_addr()?.into()).unwrap();
+        assert_eq!(spoofer_link_stats.received, 4);
+        assert_eq!(spoofer_link_stats.accepted, 1);
+        assert_eq!(spoofer_link_stats.duplicated, 1);
+        assert_eq!(spoofer_link_stats.unsigned, 1);
+        assert_eq!(spoofer_link_stats.bad_signature, 1);
+
+        server_closer();
+        client_closer();
+
+        server_handle.join().unwrap();
+        client_handle.join().unwrap();
+
+        Ok(())
+    }
+
+    #[test]
+    fn telemetry() -> anyhow::Result<()> {
+        let pre_shared_key = crate::auth::PreSharedKey::new(b"car123!@#");
+
+        let (incoming_input_message_sender, incoming_input_message_receiver) =
+            get_input_message_sender_and_receiver();
+        let (incoming_telemetry_message_sender, incoming_telemetry_message_receiver) =
+            get_telemetry_message_sender_and_receiver();
+
+        let mut server = Server::new("127.0.0.1:0".parse()?, incoming_input_message_sender)?;
+        server.set_pre_shared_key(pre_shared_key.clone());
+
+        let mut client = Client::new(server.get_bind_address().get_socket_address().unwrap())?;
+        client.set_pre_shared_key(pre_shared_key);
+        client.set_incoming_telemetry_message_sender(incoming_telemetry_message_sender);
+        client.set_ping_interval(None);
+
+        let server_closer = server.get_closer();
+        let client_closer = client.get_closer();
+
+        let outgoing_input_message_sender = client.get_outgoing_input_message_sender();
+        let outgoing_telemetry_message_sender = server.get_outgoing_telemetry_message_sender();
+
+        let server_handle = std::thread::spawn(move || {
+            server.run().unwrap();
+        });
+
+        let client_handle = std::thread::spawn(move || {
+            client.run().unwrap();
+        });
+
+        let outgoing_telemetry_message = crate::serialization::TelemetryMessage {
+            throttle: 0.69,
+            steering: 0.69,
+            throttle_left: 0.69,
+            throttle_right: 0.69,
+            throttle_min: -0.69,
+            throttle_max: 0.69,
+            steering_offset: 0.01,
+            failsafe: false,
+            armed: true,
+            battery_voltage: None,
+            drive_mode: None,
+            link_stats: None,
+        };
+
+        // there's no client address to send to yet, so this goes nowhere
+        outgoing_telemetry_message_sender.send(outgoing_telemetry_message.clone())?;
+        assert!(incoming_telemetry_message_receiver
+            .recv_timeout(MESSAGE_TIMEOUT * 4)
+            .is_err());
+
+        outgoing_input_message_sender.send(crate::serialization::InputMessage {
+            throttle: 0.69,
+            steering: 0.69,
+            throttle_left: 0.69,
+            throttle_right: 0.69,
+            steering_left: 0.69,
+            steering_right: 0.69,
+            mode_up: false,
+            mode_down: false,
+            mode_left: false,
+            mode_right: false,
+            handbrake: false,
+        })?;
+        incoming_input_message_receiver.recv_timeout(std::time::Duration::from_secs(1))?;
+
+        outgoing_telemetry_message_sender.send(outgoing_telemetry_message.clone())?;
+        let incoming_telemetry_message =
+            incoming_telemetry_message_receiver.recv_timeout(std::time::Duration::from_secs(1))?;
+
+        let link_stats = incoming_telemetry_message.link_stats.clone().unwrap();
+        assert_eq!(link_stats.accepted, 1);
+
+        let mut expected_telemetry_message = outgoing_telemetry_message;
+        expected_telemetry_message.link_stats = Some(link_stats);
+        assert_eq!(incoming_telemetry_message, expected_telemetry_message);
+
+        server_closer();
+        client_closer();
+
+        server_handle.join().unwrap();
+        client_handle.join().unwrap();
+
+        Ok(())
+    }
+
+    // deterministic so that any failure can be reproduced
+    #[test]
+    fn latency() -> anyhow::Result<()> {
+        let (incoming_input_message_sender, _incoming_input_message_receiver) =
+            get_input_message_sender_and_receiver();
+
+        let server = Server::new("127.0.0.1:0".parse()?, incoming_input_message_sender)?;
+        let client = Client::new(server.get_bind_address().get_socket_address().unwrap())?;
+
+        let server_closer = server.get_closer();
+        let client_closer = client.get_closer();
+
+        let owner_reader = server.get_owner_reader();
+        let latency_stats_reader = client.get_latency_stats_reader();
+
+        let server_handle = std::thread::spawn(move || {
+            server.run().unwrap();
+        });
+
+        let client_handle = std::thread::spawn(move || {
+            client.run().unwrap();
+        });
+
+        std::thread::sleep(crate::latency::PING_INTERVAL * 4);
+
+        let latency_stats = latency_stats_reader();
+        assert!(latency_stats.sent >= 3);
+        assert!(latency_stats.received >= 3);
+        assert!(latency_stats.rtt.is_some());
+        assert_eq!(latency_stats.loss_percent, 0.0);
+        assert!(!latency_stats.is_degraded());
+
+        // pinging doesn't take the lease
+        assert_eq!(owner_reader(), None);
+
+        server_closer();
+        client_closer();
+
+        server_handle.join().unwrap();
+        client_handle.join().unwrap();
+
+        Ok(())
+    }
+
+    #[test]
+    fn codec() -> anyhow::Result<()> {
+        let (incoming_input_message_sender, incoming_input_message_receiver) =
+            get_input_message_sender_and_receiver();
+
+        let server = Server::new("127.0.0.1:0".parse()?, incoming_input_message_sender)?;
+        let server_address = server.get_bind_address().get_socket_address().unwrap();
+
+        let server_closer = server.get_closer();
+        let outgoing_telemetry_message_sender = server.get_outgoing_telemetry_message_sender();
+
+        let server_handle = std::thread::spawn(move || {
+            server.run().unwrap();
+        });
+
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0")?;
+        socket.set_read_timeout(Some(std::time::Duration::from_secs(1)))?;
+
+        let buf = crate::envelope::encode_with_codec(
+            0,
+            0,
+            &crate::envelope::Message::Input(get_sentinel_input_message()),
+            crate::codec::Codec::Json,
+        )?;
+        socket.send_to(&buf, server_address)?;
+
+        assert_eq!(
+            incoming_input_message_receiver.recv_timeout(std::time::Duration::from_secs(1))?,
+            get_sentinel_input_message()
+        );
+
+        outgoing_telemetry_message_sender.send(crate::serialization::TelemetryMessage {
+            throttle: 13.37,
+            steering: 0.0,
+            throttle_left: 0.0,
+            throttle_right: 0.0,
+            throttle_min: -1.0,
+            throttle_max: 1.0,
+            steering_offset: 0.0,
+            failsafe: false,
+            armed: true,
+            battery_voltage: None,
+            drive_mode: None,
+            link_stats: None,
+        })?;
+
+        // the Server answers in the Codec it was spoken to in (the lease grant and the telemetry)
+        let mut buf = [0u8; BUF_SIZE];
+        let mut telemetry_message = None;
+        while telemetry_message.is_none() {
+            let (n, _) = socket.recv_from(&mut buf)?;
+            let envelope = crate::envelope::decode(&buf[..n])?;
+            assert_eq!(envelope.codec, crate::codec::Codec::Json);
+
+            if let crate::envelope::Message::Telemetry(message) = envelope.message {
+                telemetry_message = Some(message);
+            }
+        }
+        assert_eq!(telemetry_message.unwrap().throttle, 13.37);
+
+        server_closer();
+
+        server_handle.join().unwrap();
+
+        Ok(())
+    }
+
+    #[test]
+    fn control() -> anyhow::Result<()> {
+        let (incoming_input_message_sender, incoming_input_message_receiver) =
+            get_input_message_sender_and_receiver();
+        let (incoming_vehicle_descriptor_sender, incoming_vehicle_descriptor_receiver) =
+            std::sync::mpsc::channel();
+
+        let vehicle_descriptor = crate::serialization::VehicleDescriptor {
+            kind: crate::serialization::VehicleKind::Truck,
+            axes: vec![
+                "throttle".to_string(),
+                "steering".to_string(),
+                "".to_string(),
+                "tray".to_string(),
+            ],
+            buttons: vec![],
+        };
+
+        let mut server = Server::new("127.0.0.1:0".parse()?, incoming_input_message_sender)?;
+        server.set_vehicle_descriptor(vehicle_descriptor.clone());
+
+        let mut client = Client::new(server.get_bind_address().get_socket_address().unwrap())?;
+        client.set_incoming_vehicle_descriptor_sender(incoming_vehicle_descriptor_sender);
+
+        let server_closer = server.get_closer();
+        let client_closer = client.get_closer();
+
+        let outgoing_control_message_sender = client.get_outgoing_control_message_sender();
+
+        let server_handle = std::thread::spawn(move || {
+            server.run().unwrap();
+        });
+
+        let client_handle = std::thread::spawn(move || {
+            client.run().unwrap();
+        });
+
+        outgoing_control_message_sender.send(crate::serialization::ControlMessage {
+            axes: vec![0.5, -0.25, 0.0, 1.0],
+            buttons: vec![],
+        })?;
+
+        // laid out as an InputMessage for the vehicle...
+        let incoming_input_message =
+            incoming_input_message_receiver.recv_timeout(std::time::Duration::from_secs(1))?;
+        assert_eq!(incoming_input_message.throttle, 0.5);
+        assert_eq!(incoming_input_message.steering, -0.25);
+        assert_eq!(incoming_input_message.throttle_right, 1.0);
+        assert!(!incoming_input_message.handbrake);
+
+        // ...and the controller is told what the channels mean when it's granted the lease
+        assert_eq!(
+            incoming_vehicle_descriptor_receiver.recv_timeout(std::time::Duration::from_secs(1))?,
+            vehicle_descriptor
+        );
+
+        server_closer();
+        client_closer();
+
+        server_handle.join().unwrap();
+        client_handle.join().unwrap();
+
+        Ok(())
+    }
+
+    #[test]
+    fn record() -> anyhow::Result<()> {
+        let (incoming_input_message_sender, incoming_input_message_receiver) =
+            get_input_message_sender_and_receiver();
+
+        let server_path = std::env::temp_dir().join(format!(
+            "rc-messaging-record-server-{}.bin",
+            std::process::id()
+        ));
+        let client_path = std::env::temp_dir().join(format!(
+            "rc-messaging-record-client-{}.bin",
+            std::process::id()
+        ));
+
+        let mut server = Server::new("127.0.0.1:0".parse()?, incoming_input_message_sender)?;
+        server.set_recorder(crate::recording::Recorder::create(&server_path)?);
+
+        let mut client = Client::new(server.get_bind_address().get_socket_address().unwrap())?;
+        client.set_recorder(crate::recording::Recorder::create(&client_path)?);
+
+        let server_closer = server.get_closer();
+        let client_closer = client.get_closer();
+
+        let outgoing_input_message_sender = client.get_outgoing_input_message_sender();
+
+        let server_handle = std::thread::spawn(move || {
+            server.run().unwrap();
+        });
+
+        let client_handle = std::thread::spawn(move || {
+            client.run().unwrap();
+        });
+
+        let mut input_messages = vec![];
+        for i in 0..3 {
+            let mut input_message = get_sentinel_input_message();
+            input_message.steering = i as f32;
+
+            outgoing_input_message_sender.send(input_message.clone())?;
+            assert_eq!(
+                incoming_input_message_receiver.recv_timeout(std::time::Duration::from_secs(1))?,
+                input_message
+            );
+
+            input_messages.push(input_message);
+
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+
+        server_closer();
+        client_closer();
+
+        server_handle.join().unwrap();
+        client_handle.join().unwrap();
+
+        // both ends saw the same session, 50ms or so apart
+        for path in [server_path, client_path] {
+            let records = crate::recording::load(&path)?;
+            std::fs::remove_file(&path)?;
 
-        Ok(Self {
-            bind_address: socket.local_addr()?,
-            socket,
-            incoming_input_message_sender,
-            closed: std::sync::Arc::new(std::sync::Mutex::new(false)),
-        })
+            assert_eq!(
+                records
+                    .iter()
+                    .map(|record| record.input_message.clone())
+                    .collect::<Vec<crate::serialization::InputMessage>>(),
+                input_messages
+            );
+
+            assert_eq!(records[0].elapsed_us, 0);
+            assert!(records[2].elapsed_us >= 100_000);
+        }
+
+        Ok(())
     }
 
-    pub fn get_closer(&self) -> impl Fn() {
-        let closed = std::sync::Arc::clone(&self.closed);
-        return move || {
-            let mut closed = closed.lock().unwrap();
-            *closed = true;
-        };
+    fn get_random_u64(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
     }
 
-    pub fn get_bind_address(&self) -> std::net::SocketAddr {
-        self.bind_address
+    fn get_sentinel_input_message() -> crate::serialization::InputMessage {
+        crate::serialization::InputMessage {
+            throttle: 13.37,
+            steering: 0.0,
+            throttle_left: 0.0,
+            throttle_right: 0.0,
+            steering_left: 0.0,
+            steering_right: 0.0,
+            mode_up: false,
+            mode_down: false,
+            mode_left: false,
+            mode_right: false,
+            handbrake: false,
+        }
     }
 
-    pub fn run(&self) -> anyhow::Result<()> {
-        let mut buf = vec![0; BUF_SIZE];
+    // keep sending a valid message until it comes out the other side, skipping anything else
+    fn assert_still_delivers(
+        server_address: &Address,
+        incoming_input_message_receiver: &std::sync::mpsc::Receiver<
+            crate::serialization::InputMessage,
+        >,
+    ) -> anyhow::Result<()> {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0")?;
 
-        loop {
+        let sentinel_input_message = get_sentinel_input_message();
+
+        // the noise may have taken the lease, so allow for it timing out
+        for sequence in 1..=50 {
+            let buf = crate::envelope::encode(
+                sequence,
+                crate::envelope::get_timestamp_us(),
+                &crate::envelope::Message::Input(sentinel_input_message.clone()),
+            )?;
+            socket.send_to(&buf, server_address)?;
+
+            while let Ok(incoming_input_message) =
+                incoming_input_message_receiver.recv_timeout(MESSAGE_TIMEOUT * 2)
             {
-                let closed = self.closed.lock().unwrap();
-                if *closed {
-                    break;
+                if incoming_input_message == sentinel_input_message {
+                    return Ok(());
                 }
             }
+        }
 
-            let recv_from_result = self.socket.recv_from(&mut buf);
-            if recv_from_result.is_err() {
-                let err = recv_from_result.err().unwrap();
-                if err.kind() == std::io::ErrorKind::WouldBlock {
-                    continue;
-                }
+        Err(anyhow::anyhow!("server stopped delivering input messages"))
+    }
 
-                println!("recv_from() failed because err={:?}", err);
-                return Err(err.into());
-            }
+    #[test]
+    fn malformed_datagrams() -> anyhow::Result<()> {
+        let (incoming_input_message_sender, incoming_input_message_receiver) =
+            get_input_message_sender_and_receiver();
+
+        let server = Server::new("127.0.0.1:0".parse()?, incoming_input_message_sender)?;
+
+        let server_closer = server.get_closer();
+        let malformed_stats_reader = server.get_malformed_stats_reader();
+        let server_address = server.get_bind_address();
+
+        let server_handle = std::thread::spawn(move || {
+            server.run().unwrap();
+        });
+
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0")?;
+
+        let valid_buf = crate::envelope::encode(
+            1,
+            crate::envelope::get_timestamp_us(),
+            &crate::envelope::Message::Input(get_sentinel_input_message()),
+        )?;
 
-            let (n, _) = recv_from_result?;
-            let buf = buf[0..n].to_vec();
+        let mut bad_magic_buf = valid_buf.clone();
+        bad_magic_buf[0] = 0xff;
 
-            let input_message = crate::serialization::deserialize(buf)?;
+        let mut unsupported_version_buf = valid_buf.clone();
+        unsupported_version_buf[2] = 0xff;
 
-            self.incoming_input_message_sender.send(input_message)?;
+        let mut unsupported_flags_buf = valid_buf.clone();
+        unsupported_flags_buf[crate::envelope::FLAGS_OFFSET] = 0x80;
+
+        let mut unknown_message_type_buf = valid_buf.clone();
+        unknown_message_type_buf[3] = 0xff;
+
+        let bad_payload_buf = valid_buf[0..valid_buf.len() - 1].to_vec();
+
+        for buf in [
+            vec![0; BUF_SIZE + 1],
+            valid_buf[0..3].to_vec(),
+            bad_magic_buf,
+            unsupported_version_buf,
+            unsupported_flags_buf,
+            unknown_message_type_buf,
+            bad_payload_buf.clone(),
+            bad_payload_buf,
+        ] {
+            socket.send_to(&buf, &server_address)?;
         }
 
-        drop(self.incoming_input_message_sender.clone());
+        assert_still_delivers(&server_address, &incoming_input_message_receiver)?;
 
-        Ok(())
-    }
-}
+        let malformed_stats = malformed_stats_reader();
+        let malformed_stats = malformed_stats.get(&socket.local_addr()?.into()).unwrap();
+        assert_eq!(malformed_stats.oversize, 1);
+        assert_eq!(malformed_stats.too_short, 1);
+        assert_eq!(malformed_stats.bad_magic, 1);
+        assert_eq!(malformed_stats.unsupported_version, 1);
+        assert_eq!(malformed_stats.unsupported_flags, 1);
+        assert_eq!(malformed_stats.unknown_message_type, 1);
+        assert_eq!(malformed_stats.bad_payload, 2);
+        assert_eq!(malformed_stats.get_total(), 8);
+        // all within a second of each other, so only the first one was logged
+        assert_eq!(malformed_stats.suppressed_logs, 7);
 
-pub struct Client {
-    send_address: std::net::SocketAddr,
-    bind_address: std::net::SocketAddr,
-    socket: std::net::UdpSocket,
-    outgoing_input_message_sender: std::sync::mpsc::Sender<crate::serialization::InputMessage>,
-    outgoing_input_message_receiver: std::sync::mpsc::Receiver<crate::serialization::InputMessage>,
-    closed: std::sync::Arc<std::sync::Mutex<bool>>,
-}
+        server_closer();
 
-impl Client {
-    pub fn new(send_address: std::net::SocketAddr) -> Result<Self, anyhow::Error> {
-        let socket = get_socket("0.0.0.0:0".parse()?)?;
+        server_handle.join().unwrap();
 
-        let (outgoing_input_message_sender, outgoing_input_message_receiver) =
+        Ok(())
+    }
+
+    #[test]
+    fn random_datagrams() -> anyhow::Result<()> {
+        let (incoming_input_message_sender, incoming_input_message_receiver) =
             get_input_message_sender_and_receiver();
 
-        Ok(Self {
-            send_address,
-            bind_address: socket.local_addr()?,
-            socket,
-            outgoing_input_message_sender,
-            outgoing_input_message_receiver,
-            closed: std::sync::Arc::new(std::sync::Mutex::new(false)),
-        })
-    }
+        let server = Server::new("127.0.0.1:0".parse()?, incoming_input_message_sender)?;
 
-    pub fn get_closer(&self) -> impl Fn() {
-        let closed = std::sync::Arc::clone(&self.closed);
-        return move || {
-            let mut closed = closed.lock().unwrap();
-            *closed = true;
-        };
-    }
+        let server_closer = server.get_closer();
+        let server_address = server.get_bind_address();
 
-    pub fn get_bind_address(&self) -> std::net::SocketAddr {
-        self.bind_address
-    }
+        let server_handle = std::thread::spawn(move || {
+            server.run().unwrap();
+        });
 
-    pub fn get_send_address(&self) -> std::net::SocketAddr {
-        self.send_address
-    }
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0")?;
 
-    pub fn get_outgoing_input_message_sender(
-        &self,
-    ) -> std::sync::mpsc::Sender<crate::serialization::InputMessage> {
-        self.outgoing_input_message_sender.clone()
-    }
+        let valid_buf = crate::envelope::encode(
+            1,
+            crate::envelope::get_timestamp_us(),
+            &crate::envelope::Message::Input(get_sentinel_input_message()),
+        )?;
 
-    pub fn run(&self) -> anyhow::Result<()> {
-        loop {
-            {
-                let closed = self.closed.lock().unwrap();
-                if *closed {
-                    break;
+        let mut state: u64 = 0x5eed;
+
+        for i in 0..1000 {
+            let buf = if i % 2 == 0 {
+                // pure noise of any length, including oversize and empty
+                let n = (get_random_u64(&mut state) % (BUF_SIZE as u64 * 2)) as usize;
+                (0..n)
+                    .map(|_| get_random_u64(&mut state) as u8)
+                    .collect::<Vec<u8>>()
+            } else {
+                // a valid datagram with a few bytes flipped and maybe truncated
+                let mut buf = valid_buf.clone();
+                for _ in 0..(1 + get_random_u64(&mut state) % 4) {
+                    let offset = (get_random_u64(&mut state) % buf.len() as u64) as usize;
+                    buf[offset] ^= get_random_u64(&mut state) as u8 | 0x01;
                 }
-            }
+                buf.truncate((get_random_u64(&mut state) % (buf.len() as u64 + 1)) as usize);
+                buf
+            };
 
-            let recv_timeout_result = self
-                .outgoing_input_message_receiver
-                .recv_timeout(std::time::Duration::from_secs(1));
-            if recv_timeout_result.is_err() {
-                continue;
+            socket.send_to(&buf, &server_address)?;
+
+            // don't overrun the socket buffer
+            if i % 100 == 0 {
+                std::thread::sleep(std::time::Duration::from_millis(10));
             }
+        }
 
-            let input_message = recv_timeout_result?;
+        assert_still_delivers(&server_address, &incoming_input_message_receiver)?;
 
-            let buf = crate::serialization::serialize(&input_message)?;
+        server_closer();
 
-            let _ = self.socket.send_to(&buf, self.send_address)?;
-        }
+        server_handle.join().unwrap();
 
         Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    struct TestClient {
+        address: Address,
+        input_message_sender: std::sync::mpsc::Sender<crate::serialization::InputMessage>,
+        lease_message_sender: std::sync::mpsc::Sender<crate::serialization::LeaseMessage>,
+        lease_message_receiver: std::sync::mpsc::Receiver<crate::serialization::LeaseMessage>,
+        drive_mode_message_sender: std::sync::mpsc::Sender<crate::serialization::DriveModeMessage>,
+        closer: Box<dyn Fn()>,
+        handle: std::thread::JoinHandle<()>,
+    }
+
+    fn start_test_client(server_address: Address) -> anyhow::Result<TestClient> {
+        // bound to 127.0.0.1 so that the Server sees the same address as the Client
+        let mut client = Client::new_with_transport(
+            Box::new(udp::UdpTransport::new("127.0.0.1:0".parse()?)?),
+            server_address,
+        );
+
+        let (lease_message_sender, lease_message_receiver) = std::sync::mpsc::channel();
+        client.set_incoming_lease_message_sender(lease_message_sender);
+
+        Ok(TestClient {
+            address: client.get_bind_address(),
+            input_message_sender: client.get_outgoing_input_message_sender(),
+            lease_message_sender: client.get_outgoing_lease_message_sender(),
+            lease_message_receiver,
+            drive_mode_message_sender: client.get_outgoing_drive_mode_message_sender(),
+            closer: Box::new(client.get_closer()),
+            handle: std::thread::spawn(move || {
+                client.run().unwrap();
+            }),
+        })
+    }
 
     #[test]
-    fn happy_path() -> anyhow::Result<()> {
+    fn two_clients() -> anyhow::Result<()> {
         let (incoming_input_message_sender, incoming_input_message_receiver) =
             get_input_message_sender_and_receiver();
 
-        let server = Server::new("0.0.0.0:0".parse()?, incoming_input_message_sender)?;
-        let client = Client::new(server.get_bind_address())?;
+        let mut server = Server::new("127.0.0.1:0".parse()?, incoming_input_message_sender)?;
+        server.set_lease_timeout(std::time::Duration::from_millis(300));
 
         let server_closer = server.get_closer();
-        let client_closer = client.get_closer();
+        let owner_reader = server.get_owner_reader();
 
-        let outgoing_input_message_sender = client.get_outgoing_input_message_sender();
+        let a = start_test_client(server.get_bind_address())?;
+        let b = start_test_client(server.get_bind_address())?;
 
         let server_handle = std::thread::spawn(move || {
             server.run().unwrap();
         });
 
-        let client_handle = std::thread::spawn(move || {
-            client.run().unwrap();
-        });
-
-        let outgoing_input_message = crate::serialization::InputMessage {
-            throttle: 0.69,
-            steering: 0.69,
-            throttle_left: 0.69,
-            throttle_right: 0.69,
-            steering_left: 0.69,
-            steering_right: 0.69,
-            mode_up: true,
-            mode_down: true,
-            mode_left: true,
-            mode_right: true,
-            handbrake: true,
+        let get_input_message = |throttle: f32| crate::serialization::InputMessage {
+            throttle,
+            steering: 0.0,
+            throttle_left: 0.0,
+            throttle_right: 0.0,
+            steering_left: 0.0,
+            steering_right: 0.0,
+            mode_up: false,
+            mode_down: false,
+            mode_left: false,
+            mode_right: false,
+            handbrake: false,
         };
 
-        let incoming_input_message = {
-            outgoing_input_message_sender.send(outgoing_input_message.clone())?;
-            incoming_input_message_receiver.recv()?
+        let timeout = std::time::Duration::from_secs(1);
+
+        // a gets there first
+        a.input_message_sender.send(get_input_message(1.0))?;
+        assert_eq!(
+            incoming_input_message_receiver
+                .recv_timeout(timeout)?
+                .throttle,
+            1.0
+        );
+        assert_eq!(
+            a.lease_message_receiver.recv_timeout(timeout)?,
+            crate::serialization::LeaseMessage::Granted
+        );
+        assert_eq!(owner_reader(), Some(a.address.clone()));
+
+        // so b has to wait
+        b.input_message_sender.send(get_input_message(2.0))?;
+        assert_eq!(
+            b.lease_message_receiver.recv_timeout(timeout)?,
+            crate::serialization::LeaseMessage::Queued(1)
+        );
+        assert!(incoming_input_message_receiver
+            .recv_timeout(MESSAGE_TIMEOUT * 2)
+            .is_err());
+
+        // until a lets go
+        a.lease_message_sender
+            .send(crate::serialization::LeaseMessage::Release)?;
+        assert_eq!(
+            a.lease_message_receiver.recv_timeout(timeout)?,
+            crate::serialization::LeaseMessage::Revoked
+        );
+        assert_eq!(
+            b.lease_message_receiver.recv_timeout(timeout)?,
+            crate::serialization::LeaseMessage::Granted
+        );
+        assert_eq!(owner_reader(), Some(b.address.clone()));
+
+        b.input_message_sender.send(get_input_message(2.0))?;
+        assert_eq!(
+            incoming_input_message_receiver
+                .recv_timeout(timeout)?
+                .throttle,
+            2.0
+        );
+
+        // now a has to wait
+        a.input_message_sender.send(get_input_message(1.0))?;
+        assert_eq!(
+            a.lease_message_receiver.recv_timeout(timeout)?,
+            crate::serialization::LeaseMessage::Queued(1)
+        );
+
+        // until b hands back over
+        b.lease_message_sender
+            .send(crate::serialization::LeaseMessage::HandOver(
+                a.address.clone(),
+            ))?;
+        assert_eq!(
+            b.lease_message_receiver.recv_timeout(timeout)?,
+            crate::serialization::LeaseMessage::Revoked
+        );
+        assert_eq!(
+            a.lease_message_receiver.recv_timeout(timeout)?,
+            crate::serialization::LeaseMessage::Granted
+        );
+
+        a.input_message_sender.send(get_input_message(1.0))?;
+        assert_eq!(
+            incoming_input_message_receiver
+                .recv_timeout(timeout)?
+                .throttle,
+            1.0
+        );
+
+        // a goes quiet, so b (which keeps asking) gets the lease once it times out
+        let started_at = std::time::Instant::now();
+        let throttle = loop {
+            assert!(started_at.elapsed() < timeout * 2);
+
+            b.input_message_sender.send(get_input_message(2.0))?;
+
+            if let Ok(incoming_input_message) =
+                incoming_input_message_receiver.recv_timeout(MESSAGE_TIMEOUT)
+            {
+                break incoming_input_message.throttle;
+            }
         };
 
-        assert_eq!(outgoing_input_message, incoming_input_message);
+        assert_eq!(throttle, 2.0);
+        assert!(started_at.elapsed() >= std::time::Duration::from_millis(200));
+        assert_eq!(owner_reader(), Some(b.address));
 
         server_closer();
-        client_closer();
+        (a.closer)();
+        (b.closer)();
 
         server_handle.join().unwrap();
-        client_handle.join().unwrap();
+        a.handle.join().unwrap();
+        b.handle.join().unwrap();
 
         Ok(())
     }
 
     #[test]
-    fn no_server() -> anyhow::Result<()> {
-        let client = Client::new("127.0.0.1:13337".parse()?)?;
+    fn drive_mode() -> anyhow::Result<()> {
+        let (incoming_input_message_sender, _incoming_input_message_receiver) =
+            get_input_message_sender_and_receiver();
+        let (incoming_drive_mode_message_sender, incoming_drive_mode_message_receiver) =
+            std::sync::mpsc::channel();
 
-        let client_closer = client.get_closer();
+        let mut server = Server::new("127.0.0.1:0".parse()?, incoming_input_message_sender)?;
+        server.set_incoming_drive_mode_message_sender(incoming_drive_mode_message_sender);
 
-        let outgoing_input_message_sender = client.get_outgoing_input_message_sender();
+        let server_closer = server.get_closer();
 
-        let client_handle = std::thread::spawn(move || {
-            client.run().unwrap();
+        let a = start_test_client(server.get_bind_address())?;
+        let b = start_test_client(server.get_bind_address())?;
+
+        let server_handle = std::thread::spawn(move || {
+            server.run().unwrap();
         });
 
-        let outgoing_input_message = crate::serialization::InputMessage {
-            throttle: 0.69,
-            steering: 0.69,
-            throttle_left: 0.69,
-            throttle_right: 0.69,
-            steering_left: 0.69,
-            steering_right: 0.69,
-            mode_up: true,
-            mode_down: true,
-            mode_left: true,
-            mode_right: true,
-            handbrake: true,
-        };
+        let timeout = std::time::Duration::from_secs(1);
 
-        outgoing_input_message_sender.send(outgoing_input_message.clone())?;
+        // takes the lease like an InputMessage would...
+        a.drive_mode_message_sender
+            .send(crate::serialization::DriveModeMessage::Set(
+                "crawl".to_string(),
+            ))?;
+        assert_eq!(
+            a.lease_message_receiver.recv_timeout(timeout)?,
+            crate::serialization::LeaseMessage::Granted
+        );
+        assert_eq!(
+            incoming_drive_mode_message_receiver.recv_timeout(timeout)?,
+            crate::serialization::DriveModeMessage::Set("crawl".to_string())
+        );
 
-        client_closer();
+        // ...so nobody else can change the drive mode from under the driver
+        b.drive_mode_message_sender
+            .send(crate::serialization::DriveModeMessage::Cycle)?;
+        assert_eq!(
+            b.lease_message_receiver.recv_timeout(timeout)?,
+            crate::serialization::LeaseMessage::Queued(1)
+        );
+        assert!(incoming_drive_mode_message_receiver
+            .recv_timeout(MESSAGE_TIMEOUT * 4)
+            .is_err());
 
-        client_handle.join().unwrap();
+        a.drive_mode_message_sender
+            .send(crate::serialization::DriveModeMessage::Cycle)?;
+        assert_eq!(
+            incoming_drive_mode_message_receiver.recv_timeout(timeout)?,
+            crate::serialization::DriveModeMessage::Cycle
+        );
+
+        server_closer();
+        (a.closer)();
+        (b.closer)();
+
+        server_handle.join().unwrap();
+        a.handle.join().unwrap();
+        b.handle.join().unwrap();
 
         Ok(())
     }
 
     #[test]
-    fn no_client() -> anyhow::Result<()> {
+    fn simulated_lease_timeout() -> anyhow::Result<()> {
         let (incoming_input_message_sender, incoming_input_message_receiver) =
             get_input_message_sender_and_receiver();
 
-        let server = Server::new("0.0.0.0:0".parse()?, incoming_input_message_sender)?;
+        let clock = crate::clock::SimulatedClock::new();
+
+        let mut server = Server::new("127.0.0.1:0".parse()?, incoming_input_message_sender)?;
+        server.set_lease_timeout(std::time::Duration::from_millis(300));
+        server.set_clock(std::sync::Arc::new(clock.clone()));
 
         let server_closer = server.get_closer();
+        let owner_reader = server.get_owner_reader();
+
+        let a = start_test_client(server.get_bind_address())?;
+        let b = start_test_client(server.get_bind_address())?;
 
         let server_handle = std::thread::spawn(move || {
             server.run().unwrap();
         });
 
-        _ = incoming_input_message_receiver.recv_timeout(std::time::Duration::from_secs(1));
+        let get_input_message = |throttle: f32| crate::serialization::InputMessage {
+            throttle,
+            steering: 0.0,
+            throttle_left: 0.0,
+            throttle_right: 0.0,
+            steering_left: 0.0,
+            steering_right: 0.0,
+            mode_up: false,
+            mode_down: false,
+            mode_left: false,
+            mode_right: false,
+            handbrake: false,
+        };
+
+        let timeout = std::time::Duration::from_secs(1);
+
+        a.input_message_sender.send(get_input_message(1.0))?;
+        assert_eq!(
+            a.lease_message_receiver.recv_timeout(timeout)?,
+            crate::serialization::LeaseMessage::Granted
+        );
+        incoming_input_message_receiver.recv_timeout(timeout)?;
+
+        b.input_message_sender.send(get_input_message(2.0))?;
+        assert_eq!(
+            b.lease_message_receiver.recv_timeout(timeout)?,
+            crate::serialization::LeaseMessage::Queued(1)
+        );
+
+        // however long a is really quiet for, the lease only expires once it's been quiet for more
+        // than the lease timeout on simulated time
+        clock.advance(std::time::Duration::from_millis(300));
+        b.input_message_sender.send(get_input_message(2.0))?;
+        assert!(incoming_input_message_receiver
+            .recv_timeout(MESSAGE_TIMEOUT * 4)
+            .is_err());
+        assert_eq!(owner_reader(), Some(a.address.clone()));
+
+        clock.advance(std::time::Duration::from_millis(1));
+        b.input_message_sender.send(get_input_message(2.0))?;
+        assert_eq!(
+            b.lease_message_receiver.recv_timeout(timeout)?,
+            crate::serialization::LeaseMessage::Granted
+        );
+        assert_eq!(
+            incoming_input_message_receiver
+                .recv_timeout(timeout)?
+                .throttle,
+            2.0
+        );
+        assert_eq!(owner_reader(), Some(b.address));
 
         server_closer();
+        (a.closer)();
+        (b.closer)();
 
         server_handle.join().unwrap();
+        a.handle.join().unwrap();
+        b.handle.join().unwrap();
+
+        Ok(())
+    }
+
+    // what a Client's ClientSession and state say to each other, without any sockets in between
+    fn exchange(
+        state: &ServerState,
+        address: &Address,
+        session: &mut crate::session::ClientSession,
+        buf: &[u8],
+    ) -> anyhow::Result<(Option<crate::serialization::InputMessage>, Outbox)> {
+        let mut outbox = vec![];
+        let input_message = state.handle(address, buf, &mut outbox);
+
+        let mut send_buf = vec![0; BUF_SIZE];
+        for (_, message) in &outbox {
+            let n = state.encode_into(0, address, message, &mut send_buf)?;
+            session.receive(address, &send_buf[0..n]);
+        }
+
+        Ok((input_message, outbox))
+    }
+
+    fn join(
+        state: &ServerState,
+        address: &Address,
+        session: &mut crate::session::ClientSession,
+        sequence: u64,
+    ) -> anyhow::Result<Vec<u8>> {
+        let mut buf = vec![0; BUF_SIZE];
+        let n = session.encode_request_into(sequence, crate::codec::Codec::default(), &mut buf)?;
+        buf.truncate(n);
+
+        exchange(state, address, session, &buf)?;
+        assert!(session.is_joined());
+
+        Ok(buf)
+    }
+
+    fn encode_for_session(
+        session: &mut crate::session::ClientSession,
+        sequence: u64,
+    ) -> anyhow::Result<Vec<u8>> {
+        let mut buf = vec![0; BUF_SIZE];
+        let n = session.encode_into(
+            sequence,
+            &crate::envelope::Message::Input(get_sentinel_input_message()),
+            crate::codec::Codec::default(),
+            &mut buf,
+        )?;
+        buf.truncate(n);
+
+        Ok(buf)
+    }
+
+    #[test]
+    fn replayed_into_a_restarted_server() -> anyhow::Result<()> {
+        let pre_shared_key = crate::auth::PreSharedKey::new(b"car123!@#");
+        let address: Address = "127.0.0.1:1337".parse::<std::net::SocketAddr>()?.into();
+
+        let mut state = ServerState::new();
+        state.set_pre_shared_key(pre_shared_key.clone());
+
+        let mut session = crate::session::ClientSession::new(pre_shared_key.clone());
+        join(&state, &address, &mut session, 1)?;
+
+        // a session worth capturing
+        let captured = (2..10)
+            .map(|sequence| encode_for_session(&mut session, sequence))
+            .collect::<anyhow::Result<Vec<Vec<u8>>>>()?;
+
+        for buf in &captured {
+            let mut outbox = vec![];
+            assert!(state.handle(&address, buf, &mut outbox).is_some());
+        }
+
+        // nothing about the vehicle having restarted gets it to take any of it again; it just
+        // says there's no session for it
+        let mut state = ServerState::new();
+        state.set_pre_shared_key(pre_shared_key.clone());
+
+        for (sequence, buf) in (2..10).zip(&captured) {
+            let mut outbox = vec![];
+            assert_eq!(state.handle(&address, buf, &mut outbox), None);
+            assert_eq!(
+                outbox,
+                vec![(
+                    address.clone(),
+                    crate::envelope::Message::Session(
+                        crate::serialization::SessionMessage::Expired(sequence)
+                    )
+                )]
+            );
+        }
+
+        let link_stats = state.get_link_stats_reader()();
+        let link_stats = link_stats.get(&address).unwrap();
+        assert_eq!(link_stats.accepted, 0);
+        assert_eq!(link_stats.bad_signature, captured.len() as u64);
+
+        Ok(())
+    }
+
+    #[test]
+    fn sessions_are_per_client() -> anyhow::Result<()> {
+        let pre_shared_key = crate::auth::PreSharedKey::new(b"car123!@#");
+        let a: Address = "127.0.0.1:1337".parse::<std::net::SocketAddr>()?.into();
+        let b: Address = "127.0.0.1:1338".parse::<std::net::SocketAddr>()?.into();
+        let reconnected_a: Address = "127.0.0.1:1339".parse::<std::net::SocketAddr>()?.into();
+
+        let mut state = ServerState::new();
+        state.set_pre_shared_key(pre_shared_key.clone());
+        let link_stats_reader = state.get_link_stats_reader();
+
+        let mut a_session = crate::session::ClientSession::new(pre_shared_key.clone());
+        join(&state, &a, &mut a_session, 1_000_000)?;
+        let captured = encode_for_session(&mut a_session, 1_000_001)?;
+        assert!(exchange(&state, &a, &mut a_session, &captured)?.0.is_some());
+
+        // a controller with no idea of the time (so its sequence numbers are way behind) still
+        // gets in, if only to be queued for the lease
+        let mut b_session = crate::session::ClientSession::new(pre_shared_key.clone());
+        join(&state, &b, &mut b_session, 1)?;
+        let buf = encode_for_session(&mut b_session, 2)?;
+        exchange(&state, &b, &mut b_session, &buf)?;
+        assert_eq!(link_stats_reader()[&b].accepted, 1);
+
+        // but what a sent can't be replayed from anywhere else
+        assert!(exchange(&state, &b, &mut b_session, &captured)?.0.is_none());
+        assert_eq!(link_stats_reader()[&b].bad_signature, 1);
+        assert!(b_session.is_joined());
+
+        let (input_message, outbox) = exchange(&state, &reconnected_a, &mut b_session, &captured)?;
+        assert!(input_message.is_none());
+        assert_eq!(link_stats_reader()[&reconnected_a].bad_signature, 1);
+        assert_eq!(
+            outbox,
+            vec![(
+                reconnected_a.clone(),
+                crate::envelope::Message::Session(crate::serialization::SessionMessage::Expired(
+                    1_000_001
+                ))
+            )]
+        );
+
+        // and a reconnecting from a new port (having restarted, with its clock behind) is fine
+        let mut reconnected_a_session = crate::session::ClientSession::new(pre_shared_key);
+        join(&state, &reconnected_a, &mut reconnected_a_session, 10)?;
+        let buf = encode_for_session(&mut reconnected_a_session, 11)?;
+        exchange(&state, &reconnected_a, &mut reconnected_a_session, &buf)?;
+        assert_eq!(link_stats_reader()[&reconnected_a].accepted, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn replayed_request() -> anyhow::Result<()> {
+        let pre_shared_key = crate::auth::PreSharedKey::new(b"car123!@#");
+        let address: Address = "127.0.0.1:1337".parse::<std::net::SocketAddr>()?.into();
+
+        let mut state = ServerState::new();
+        state.set_pre_shared_key(pre_shared_key.clone());
+
+        let mut session = crate::session::ClientSession::new(pre_shared_key.clone());
+        let captured = join(&state, &address, &mut session, 1_000_000)?;
+        let buf = encode_for_session(&mut session, 1_000_001)?;
+        assert!(exchange(&state, &address, &mut session, &buf)?.0.is_some());
+
+        // restarted (from the same port, as it happens) with its clock behind
+        let mut session = crate::session::ClientSession::new(pre_shared_key);
+        join(&state, &address, &mut session, 1)?;
+        let buf = encode_for_session(&mut session, 2)?;
+        assert!(exchange(&state, &address, &mut session, &buf)?.0.is_some());
+
+        // the Request from before is answered, but that doesn't get in the way of the session
+        // the Client is actually in (and the answer is no use to it)
+        let (_, outbox) = exchange(&state, &address, &mut session, &captured)?;
+        assert!(matches!(
+            outbox[..],
+            [(
+                _,
+                crate::envelope::Message::Session(
+                    crate::serialization::SessionMessage::Nonce { .. }
+                )
+            )]
+        ));
+
+        let buf = encode_for_session(&mut session, 3)?;
+        assert!(exchange(&state, &address, &mut session, &buf)?.0.is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_logs_on_the_server_clock() -> anyhow::Result<()> {
+        let clock = crate::clock::SimulatedClock::new();
+        let address: Address = "127.0.0.1:1337".parse::<std::net::SocketAddr>()?.into();
+
+        let mut state = ServerState::new();
+        state.set_clock(std::sync::Arc::new(clock.clone()));
+        let malformed_stats_reader = state.get_malformed_stats_reader();
+        let get_suppressed_logs = || malformed_stats_reader()[&address].suppressed_logs;
+
+        let mut outbox = vec![];
+        state.handle(&address, b"garbage", &mut outbox);
+        state.handle(&address, b"garbage", &mut outbox);
+        assert_eq!(get_suppressed_logs(), 1);
+
+        clock.advance(std::time::Duration::from_secs(1));
+        state.handle(&address, b"garbage", &mut outbox);
+        assert_eq!(get_suppressed_logs(), 1);
+        state.handle(&address, b"garbage", &mut outbox);
+        assert_eq!(get_suppressed_logs(), 2);
 
         Ok(())
     }
@@ -0,0 +1,572 @@
+// async (tokio) equivalents of Server and Client for binaries that would rather multiplex
+// everything on one runtime than run a thread per job; UDP only
+//
+// a Server is a Stream of the InputMessages it accepts and a Sink for TelemetryMessages (which go
+// to whoever holds the lease); a Client is a Stream of the TelemetryMessages and LeaseMessages it
+// receives and a Sink for the messages it sends; use futures::StreamExt::split to drive each half
+// from a different task
+//
+// either stops (the Stream ends and the Sink fails) as soon as its CancellationToken is cancelled,
+// or it can simply be dropped
+
+use crate::transport::{Address, Outbox, ServerState, BUF_SIZE, MESSAGE_TIMEOUT};
+
+type Cancelled = std::pin::Pin<Box<tokio_util::sync::WaitForCancellationFutureOwned>>;
+
+fn get_cancelled(cancellation_token: &tokio_util::sync::CancellationToken) -> Cancelled {
+    Box::pin(cancellation_token.clone().cancelled_owned())
+}
+
+fn get_cancelled_error() -> anyhow::Error {
+    anyhow::anyhow!("cancelled")
+}
+
+pub struct Server {
+    socket: tokio::net::UdpSocket,
+    bind_address: Address,
+    state: ServerState,
+    sequence: u64,
+    buf: Vec<u8>,
+    send_buf: Vec<u8>,
+    expire_interval: tokio::time::Interval,
+    cancellation_token: tokio_util::sync::CancellationToken,
+    cancelled: Cancelled,
+}
+
+impl Server {
+    pub async fn new(bind_address: std::net::SocketAddr) -> Result<Self, anyhow::Error> {
+        let socket = tokio::net::UdpSocket::bind(bind_address).await?;
+
+        // wakes the Stream up every so often so that a lease can expire while nobody is talking
+        let mut expire_interval = tokio::time::interval(MESSAGE_TIMEOUT);
+        expire_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+        let cancellation_token = tokio_util::sync::CancellationToken::new();
+
+        Ok(Self {
+            bind_address: Address::Socket(socket.local_addr()?),
+            socket,
+            state: ServerState::new(),
+            sequence: crate::envelope::get_timestamp_us(),
+            // one byte spare so that we can tell an oversize datagram from one that just fits
+            buf: vec![0; BUF_SIZE + 1],
+            send_buf: vec![0; BUF_SIZE],
+            expire_interval,
+            cancelled: get_cancelled(&cancellation_token),
+            cancellation_token,
+        })
+    }
+
+    // how long the controller holding the lease can go quiet before somebody else can take over
+    pub fn set_lease_timeout(&mut self, lease_timeout: std::time::Duration) {
+        self.state.set_lease_timeout(lease_timeout);
+    }
+
+    // once set, only datagrams signed with this key (and not replayed) are accepted
+    pub fn set_pre_shared_key(&mut self, pre_shared_key: crate::auth::PreSharedKey) {
+        self.state.set_pre_shared_key(pre_shared_key);
+    }
+
+    // sent to each controller as it's granted the lease
+    pub fn set_vehicle_descriptor(
+        &mut self,
+        vehicle_descriptor: crate::serialization::VehicleDescriptor,
+    ) {
+        self.state.set_vehicle_descriptor(vehicle_descriptor);
+    }
+
+    // DriveModeMessages from whoever holds the lease are sent here; they're ignored otherwise
+    pub fn set_incoming_drive_mode_message_sender(
+        &mut self,
+        incoming_drive_mode_message_sender: std::sync::mpsc::Sender<
+            crate::serialization::DriveModeMessage,
+        >,
+    ) {
+        self.state
+            .set_drive_mode_message_sender(incoming_drive_mode_message_sender);
+    }
+
+    // what leases are timed against; the system clock by default
+    pub fn set_clock(&mut self, clock: std::sync::Arc<dyn crate::clock::Clock>) {
+        self.state.set_clock(clock);
+    }
+
+    pub fn get_cancellation_token(&self) -> tokio_util::sync::CancellationToken {
+        self.cancellation_token.clone()
+    }
+
+    pub fn get_bind_address(&self) -> Address {
+        self.bind_address.clone()
+    }
+
+    pub fn get_link_stats_reader(
+        &self,
+    ) -> impl Fn() -> std::collections::HashMap<Address, crate::sequencing::LinkStats> {
+        self.state.get_link_stats_reader()
+    }
+
+    pub fn get_malformed_stats_reader(
+        &self,
+    ) -> impl Fn() -> std::collections::HashMap<Address, crate::diagnostics::MalformedStats> {
+        self.state.get_malformed_stats_reader()
+    }
+
+    pub fn get_owner_reader(&self) -> impl Fn() -> Option<Address> {
+        self.state.get_owner_reader()
+    }
+
+    fn send_outbox(&mut self, outbox: Outbox) {
+        for (address, message) in outbox {
+            let Some(address) = address.get_socket_address() else {
+                continue;
+            };
+
+            let n = match self.state.encode_into(
+                self.sequence,
+                &Address::Socket(address),
+                &message,
+                &mut self.send_buf,
+            ) {
+                Ok(n) => n,
+                Err(err) => {
+                    println!("encode_into() failed because err={:?}", err);
+                    continue;
+                }
+            };
+            self.sequence += 1;
+
+            // a UDP socket only refuses to send straight away if its buffer is full, in which case
+            // the datagram may as well be dropped
+            if let Err(err) = self.socket.try_send_to(&self.send_buf[0..n], address) {
+                println!("try_send_to() failed because err={:?}", err);
+            }
+        }
+    }
+
+    // returns whoever holds the lease (if anyone still does)
+    fn expire(&mut self) -> Option<Address> {
+        let mut outbox = vec![];
+        let owner = self.state.expire(&mut outbox);
+        self.send_outbox(outbox);
+
+        owner
+    }
+}
+
+impl futures::Stream for Server {
+    type Item = anyhow::Result<crate::serialization::InputMessage>;
+
+    fn poll_next(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        if std::future::Future::poll(this.cancelled.as_mut(), cx).is_ready() {
+            return std::task::Poll::Ready(None);
+        }
+
+        loop {
+            while this.expire_interval.poll_tick(cx).is_ready() {}
+            this.expire();
+
+            let mut buf = tokio::io::ReadBuf::new(&mut this.buf);
+            let address = match this.socket.poll_recv_from(cx, &mut buf) {
+                std::task::Poll::Ready(Ok(address)) => Address::Socket(address),
+                std::task::Poll::Ready(Err(err)) => {
+                    println!("poll_recv_from() failed because err={:?}", err);
+                    return std::task::Poll::Ready(Some(Err(err.into())));
+                }
+                std::task::Poll::Pending => return std::task::Poll::Pending,
+            };
+            let n = buf.filled().len();
+
+            let mut outbox = vec![];
+            let input_message = this.state.handle(&address, &this.buf[0..n], &mut outbox);
+            this.send_outbox(outbox);
+
+            if let Some(input_message) = input_message {
+                return std::task::Poll::Ready(Some(Ok(input_message)));
+            }
+        }
+    }
+}
+
+impl futures::Sink<crate::serialization::TelemetryMessage> for Server {
+    type Error = anyhow::Error;
+
+    fn poll_ready(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        if self.cancellation_token.is_cancelled() {
+            return std::task::Poll::Ready(Err(get_cancelled_error()));
+        }
+
+        self.socket.poll_send_ready(cx).map_err(Into::into)
+    }
+
+    fn start_send(
+        self: std::pin::Pin<&mut Self>,
+        telemetry_message: crate::serialization::TelemetryMessage,
+    ) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+
+        // TelemetryMessages go to whoever holds the lease; nobody to send to yet is fine
+        let Some(owner) = this.expire() else {
+            return Ok(());
+        };
+
+        let message = this.state.get_telemetry_message(&owner, telemetry_message);
+        this.send_outbox(vec![(owner, message)]);
+
+        Ok(())
+    }
+
+    fn poll_flush(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+}
+
+pub struct Client {
+    socket: tokio::net::UdpSocket,
+    send_address: std::net::SocketAddr,
+    // only if there's a key
+    session: Option<crate::session::ClientSession>,
+    sequence: u64,
+    buf: Vec<u8>,
+    send_buf: Vec<u8>,
+    latency_tracker: std::sync::Arc<std::sync::Mutex<crate::latency::LatencyTracker>>,
+    ping_interval: tokio::time::Interval,
+    codec: crate::codec::Codec,
+    clock: std::sync::Arc<dyn crate::clock::Clock>,
+    cancellation_token: tokio_util::sync::CancellationToken,
+    cancelled: Cancelled,
+}
+
+impl Client {
+    pub async fn new(send_address: std::net::SocketAddr) -> Result<Self, anyhow::Error> {
+        let socket = tokio::net::UdpSocket::bind("0.0.0.0:0").await?;
+
+        // pings go out while the Stream is being polled
+        let mut ping_interval = tokio::time::interval(crate::latency::PING_INTERVAL);
+        ping_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+        let cancellation_token = tokio_util::sync::CancellationToken::new();
+
+        Ok(Self {
+            socket,
+            send_address,
+            session: None,
+            // seeded from the clock for the same reason as the blocking Client
+            sequence: crate::envelope::get_timestamp_us(),
+            buf: vec![0; BUF_SIZE],
+            send_buf: vec![0; BUF_SIZE],
+            latency_tracker: std::sync::Arc::new(std::sync::Mutex::new(
+                crate::latency::LatencyTracker::new(),
+            )),
+            ping_interval,
+            codec: crate::codec::Codec::default(),
+            clock: crate::clock::get_system_clock(),
+            cancelled: get_cancelled(&cancellation_token),
+            cancellation_token,
+        })
+    }
+
+    // once set, every datagram is signed for a session the Server gives out with this key (see
+    // crate::session), so whatever's sent before the Server has answered goes as a Request instead;
+    // the first ping is enough to get an answer
+    pub fn set_pre_shared_key(&mut self, pre_shared_key: crate::auth::PreSharedKey) {
+        self.session = Some(crate::session::ClientSession::new(pre_shared_key));
+    }
+
+    // what to encode with; the Server answers in kind
+    pub fn set_codec(&mut self, codec: crate::codec::Codec) {
+        self.codec = codec;
+    }
+
+    // what pings are timed against; the system clock by default
+    pub fn set_clock(&mut self, clock: std::sync::Arc<dyn crate::clock::Clock>) {
+        self.clock = clock;
+    }
+
+    pub fn get_cancellation_token(&self) -> tokio_util::sync::CancellationToken {
+        self.cancellation_token.clone()
+    }
+
+    pub fn get_bind_address(&self) -> Result<Address, anyhow::Error> {
+        Ok(Address::Socket(self.socket.local_addr()?))
+    }
+
+    pub fn get_send_address(&self) -> Address {
+        Address::Socket(self.send_address)
+    }
+
+    // round trip time, jitter and loss as measured by pinging the Server
+    pub fn get_latency_stats_reader(&self) -> impl Fn() -> crate::latency::LatencyStats {
+        let latency_tracker = std::sync::Arc::clone(&self.latency_tracker);
+        let clock = std::sync::Arc::clone(&self.clock);
+        move || {
+            let latency_tracker = latency_tracker.lock().unwrap();
+            latency_tracker.get_stats(clock.now())
+        }
+    }
+
+    fn send_message(&mut self, message: &crate::envelope::Message) -> Result<(), anyhow::Error> {
+        let n = match &mut self.session {
+            Some(session) => {
+                session.encode_into(self.sequence, message, self.codec, &mut self.send_buf)?
+            }
+            None => crate::transport::encode_into(
+                self.sequence,
+                message,
+                self.codec,
+                None,
+                &mut self.send_buf,
+            )?,
+        };
+        self.sequence += 1;
+
+        if let Err(err) = self
+            .socket
+            .try_send_to(&self.send_buf[0..n], self.send_address)
+        {
+            if !crate::transport::is_disconnected(&err) && !crate::transport::is_timeout(&err) {
+                return Err(err.into());
+            }
+
+            println!("try_send_to() failed because err={:?}", err);
+        }
+
+        Ok(())
+    }
+
+    fn ping(&mut self) -> Result<(), anyhow::Error> {
+        let id = self.latency_tracker.lock().unwrap().ping(self.clock.now());
+
+        self.send_message(&crate::envelope::Message::Ping(
+            crate::serialization::PingMessage::Ping(id),
+        ))
+    }
+}
+
+// yields TelemetryMessages, LeaseMessages (Granted, Revoked, Queued) and VehicleDescriptors from
+// the Server
+impl futures::Stream for Client {
+    type Item = anyhow::Result<crate::envelope::Message>;
+
+    fn poll_next(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        if std::future::Future::poll(this.cancelled.as_mut(), cx).is_ready() {
+            return std::task::Poll::Ready(None);
+        }
+
+        loop {
+            while this.ping_interval.poll_tick(cx).is_ready() {
+                if let Err(err) = this.ping() {
+                    return std::task::Poll::Ready(Some(Err(err)));
+                }
+            }
+
+            let mut buf = tokio::io::ReadBuf::new(&mut this.buf);
+            let address = match this.socket.poll_recv_from(cx, &mut buf) {
+                std::task::Poll::Ready(Ok(address)) => Address::Socket(address),
+                std::task::Poll::Ready(Err(err)) => {
+                    // the Server isn't there (yet)
+                    if crate::transport::is_disconnected(&err) {
+                        continue;
+                    }
+
+                    println!("poll_recv_from() failed because err={:?}", err);
+                    return std::task::Poll::Ready(Some(Err(err.into())));
+                }
+                std::task::Poll::Pending => return std::task::Poll::Pending,
+            };
+            let n = buf.filled().len();
+
+            if let Some(message) = crate::transport::handle_client_datagram(
+                &address,
+                &this.buf[0..n],
+                this.session.as_mut(),
+                &this.latency_tracker,
+                this.clock.as_ref(),
+            ) {
+                return std::task::Poll::Ready(Some(Ok(message)));
+            }
+        }
+    }
+}
+
+// for Message::Input, Message::Control, Message::DriveMode and Message::Lease (Release, HandOver)
+impl futures::Sink<crate::envelope::Message> for Client {
+    type Error = anyhow::Error;
+
+    fn poll_ready(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        if self.cancellation_token.is_cancelled() {
+            return std::task::Poll::Ready(Err(get_cancelled_error()));
+        }
+
+        self.socket.poll_send_ready(cx).map_err(Into::into)
+    }
+
+    fn start_send(
+        self: std::pin::Pin<&mut Self>,
+        message: crate::envelope::Message,
+    ) -> Result<(), Self::Error> {
+        self.get_mut().send_message(&message)
+    }
+
+    fn poll_flush(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{SinkExt, StreamExt};
+
+    const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+    fn get_input_message() -> crate::serialization::InputMessage {
+        crate::serialization::InputMessage {
+            throttle: 0.69,
+            steering: 0.69,
+            throttle_left: 0.69,
+            throttle_right: 0.69,
+            steering_left: 0.69,
+            steering_right: 0.69,
+            mode_up: true,
+            mode_down: true,
+            mode_left: true,
+            mode_right: true,
+            handbrake: true,
+        }
+    }
+
+    fn get_telemetry_message() -> crate::serialization::TelemetryMessage {
+        crate::serialization::TelemetryMessage {
+            throttle: 0.69,
+            steering: 0.69,
+            throttle_left: 0.69,
+            throttle_right: 0.69,
+            throttle_min: -0.69,
+            throttle_max: 0.69,
+            steering_offset: 0.01,
+            failsafe: false,
+            armed: true,
+            battery_voltage: Some(7.4),
+            drive_mode: None,
+            link_stats: None,
+        }
+    }
+
+    async fn get_server_and_client() -> anyhow::Result<(Server, Client)> {
+        let server = Server::new("127.0.0.1:0".parse()?).await?;
+        let client = Client::new(server.get_bind_address().get_socket_address().unwrap()).await?;
+
+        Ok((server, client))
+    }
+
+    #[tokio::test]
+    async fn happy_path() -> anyhow::Result<()> {
+        let (server, client) = get_server_and_client().await?;
+
+        let (mut outgoing_telemetry_messages, mut incoming_input_messages) = server.split();
+        let (mut outgoing_messages, mut incoming_messages) = client.split();
+
+        for _ in 0..3 {
+            outgoing_messages
+                .send(crate::envelope::Message::Input(get_input_message()))
+                .await?;
+
+            let incoming_input_message =
+                tokio::time::timeout(TIMEOUT, incoming_input_messages.next())
+                    .await?
+                    .unwrap()?;
+            assert_eq!(incoming_input_message, get_input_message());
+        }
+
+        // the first accepted InputMessage gets the lease
+        let Some(Ok(crate::envelope::Message::Lease(lease_message))) =
+            tokio::time::timeout(TIMEOUT, incoming_messages.next()).await?
+        else {
+            panic!("expected a LeaseMessage");
+        };
+        assert_eq!(lease_message, crate::serialization::LeaseMessage::Granted);
+
+        outgoing_telemetry_messages
+            .send(get_telemetry_message())
+            .await?;
+
+        let Some(Ok(crate::envelope::Message::Telemetry(telemetry_message))) =
+            tokio::time::timeout(TIMEOUT, incoming_messages.next()).await?
+        else {
+            panic!("expected a TelemetryMessage");
+        };
+        assert_eq!(telemetry_message.link_stats.unwrap().accepted, 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cancellation() -> anyhow::Result<()> {
+        let (mut server, mut client) = get_server_and_client().await?;
+
+        let server_cancellation_token = server.get_cancellation_token();
+        let client_cancellation_token = client.get_cancellation_token();
+
+        // cancelling wakes up a Stream that's waiting for a datagram that's never coming
+        let server_handle = tokio::spawn(async move {
+            assert!(server.next().await.is_none());
+            server
+        });
+        let client_handle = tokio::spawn(async move {
+            assert!(client.next().await.is_none());
+            client
+        });
+
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        server_cancellation_token.cancel();
+        client_cancellation_token.cancel();
+
+        let mut server = tokio::time::timeout(TIMEOUT, server_handle).await??;
+        let mut client = tokio::time::timeout(TIMEOUT, client_handle).await??;
+
+        // and the Sinks refuse to send anything else
+        assert!(server.send(get_telemetry_message()).await.is_err());
+        assert!(client
+            .send(crate::envelope::Message::Input(get_input_message()))
+            .await
+            .is_err());
+
+        Ok(())
+    }
+}
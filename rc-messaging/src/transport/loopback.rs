@@ -0,0 +1,83 @@
+use crate::transport::{Address, Transport};
+
+// an in-memory, point-to-point link; mostly useful for tests
+pub struct LoopbackTransport {
+    local_address: Address,
+    peer_address: Address,
+    sender: std::sync::mpsc::Sender<Vec<u8>>,
+    receiver: std::sync::Mutex<std::sync::mpsc::Receiver<Vec<u8>>>,
+}
+
+pub fn get_loopback_transports() -> (LoopbackTransport, LoopbackTransport) {
+    let (sender_1, receiver_1) = std::sync::mpsc::channel();
+    let (sender_2, receiver_2) = std::sync::mpsc::channel();
+
+    let address_1 = Address::Link("loopback:1".to_string());
+    let address_2 = Address::Link("loopback:2".to_string());
+
+    (
+        LoopbackTransport {
+            local_address: address_1.clone(),
+            peer_address: address_2.clone(),
+            sender: sender_2,
+            receiver: std::sync::Mutex::new(receiver_1),
+        },
+        LoopbackTransport {
+            local_address: address_2,
+            peer_address: address_1,
+            sender: sender_1,
+            receiver: std::sync::Mutex::new(receiver_2),
+        },
+    )
+}
+
+impl Transport for LoopbackTransport {
+    // point-to-point, so there's only ever one place for it to go
+    fn send_to(&self, buf: &[u8], _address: &Address) -> Result<(), std::io::Error> {
+        // like UDP, nobody listening on the other end isn't an error
+        _ = self.sender.send(buf.to_vec());
+
+        Ok(())
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, Address), std::io::Error> {
+        let receiver = self.receiver.lock().unwrap();
+
+        let datagram = match receiver.recv_timeout(crate::transport::READ_TIMEOUT) {
+            Ok(datagram) => datagram,
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
+                return Err(std::io::ErrorKind::WouldBlock.into());
+            }
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
+                // the other end has gone away, which looks the same as it going quiet
+                std::thread::sleep(crate::transport::READ_TIMEOUT);
+                return Err(std::io::ErrorKind::WouldBlock.into());
+            }
+        };
+
+        // truncate like a UDP socket would
+        let n = datagram.len().min(buf.len());
+        buf[0..n].copy_from_slice(&datagram[0..n]);
+
+        Ok((n, self.peer_address.clone()))
+    }
+
+    fn get_local_address(&self) -> Address {
+        self.local_address.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() -> anyhow::Result<()> {
+        let (server_transport, client_transport) = get_loopback_transports();
+
+        crate::transport::tests::assert_round_trip(
+            Box::new(server_transport),
+            Box::new(client_transport),
+        )
+    }
+}
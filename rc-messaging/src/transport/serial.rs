@@ -0,0 +1,19 @@
+use crate::transport::{Address, READ_TIMEOUT};
+
+pub fn get_serial_transport(
+    path: &str,
+    baud_rate: u32,
+) -> Result<crate::transport::stream::StreamTransport, anyhow::Error> {
+    let port = serialport::new(path, baud_rate)
+        .timeout(READ_TIMEOUT)
+        .open()?;
+
+    let writer = port.try_clone()?;
+
+    Ok(crate::transport::stream::StreamTransport::new(
+        Address::Link(path.to_string()),
+        Address::Link(path.to_string()),
+        Box::new(port),
+        Box::new(writer),
+    ))
+}
@@ -0,0 +1,162 @@
+use crate::transport::{Address, Transport};
+
+const READ_SIZE: usize = 256;
+
+// turns any point-to-point byte stream (e.g. a serial port) into a Transport using
+// crate::framing; the reader should have a read timeout, or the closer can't stop a
+// Server / Client that's waiting on it
+pub struct StreamTransport {
+    local_address: Address,
+    peer_address: Address,
+    reader: std::sync::Mutex<(Box<dyn std::io::Read + Send>, crate::framing::Decoder)>,
+    writer: std::sync::Mutex<Box<dyn std::io::Write + Send>>,
+}
+
+impl StreamTransport {
+    pub fn new(
+        local_address: Address,
+        peer_address: Address,
+        reader: Box<dyn std::io::Read + Send>,
+        writer: Box<dyn std::io::Write + Send>,
+    ) -> Self {
+        Self {
+            local_address,
+            peer_address,
+            reader: std::sync::Mutex::new((reader, crate::framing::Decoder::new())),
+            writer: std::sync::Mutex::new(writer),
+        }
+    }
+}
+
+// shared with the TCP transport, which has a Decoder per connection
+pub(crate) fn read_frame(
+    reader: &mut dyn std::io::Read,
+    decoder: &mut crate::framing::Decoder,
+    address: &Address,
+    buf: &mut [u8],
+) -> Result<usize, std::io::Error> {
+    loop {
+        match decoder.next_frame() {
+            Some(Ok(frame)) => {
+                // truncate like a UDP socket would
+                let n = frame.len().min(buf.len());
+                buf[0..n].copy_from_slice(&frame[0..n]);
+                return Ok(n);
+            }
+            Some(Err(err)) => {
+                println!(
+                    "dropping bytes from address={} because err={}",
+                    address, err
+                );
+                continue;
+            }
+            None => {}
+        }
+
+        let mut read_buf = [0; READ_SIZE];
+        let n = match reader.read(&mut read_buf) {
+            Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => n,
+            Err(err) if crate::transport::is_timeout(&err) => {
+                return Err(std::io::ErrorKind::WouldBlock.into());
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+
+        decoder.push(&read_buf[0..n]);
+    }
+}
+
+impl Transport for StreamTransport {
+    // point-to-point, so there's only ever one place for it to go
+    fn send_to(&self, buf: &[u8], _address: &Address) -> Result<(), std::io::Error> {
+        let frame = crate::framing::encode(buf)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+
+        let mut writer = self.writer.lock().unwrap();
+        writer.write_all(&frame)?;
+        writer.flush()
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, Address), std::io::Error> {
+        let mut reader = self.reader.lock().unwrap();
+        let (reader, decoder) = &mut *reader;
+
+        match read_frame(reader.as_mut(), decoder, &self.peer_address, buf) {
+            Ok(n) => Ok((n, self.peer_address.clone())),
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+                // nothing more is coming, but treat it like a quiet link rather than tearing
+                // everything down
+                std::thread::sleep(crate::transport::READ_TIMEOUT);
+                Err(std::io::ErrorKind::WouldBlock.into())
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn get_local_address(&self) -> Address {
+        self.local_address.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_stream_transport(
+        name: &str,
+        stream: std::os::unix::net::UnixStream,
+    ) -> anyhow::Result<StreamTransport> {
+        stream.set_read_timeout(Some(crate::transport::READ_TIMEOUT))?;
+
+        Ok(StreamTransport::new(
+            Address::Link(name.to_string()),
+            Address::Link(name.to_string()),
+            Box::new(stream.try_clone()?),
+            Box::new(stream),
+        ))
+    }
+
+    #[test]
+    fn round_trip() -> anyhow::Result<()> {
+        let (server_stream, client_stream) = std::os::unix::net::UnixStream::pair()?;
+
+        crate::transport::tests::assert_round_trip(
+            Box::new(get_stream_transport("server", server_stream)?),
+            Box::new(get_stream_transport("client", client_stream)?),
+        )
+    }
+
+    #[test]
+    fn line_noise() -> anyhow::Result<()> {
+        let (stream, mut other_stream) = std::os::unix::net::UnixStream::pair()?;
+        let transport = get_stream_transport("noisy", stream)?;
+
+        let mut corrupted = crate::framing::encode(b"corrupted")?;
+        corrupted[6] ^= 0xff;
+
+        std::io::Write::write_all(&mut other_stream, &[0x00, 0xa5, 0xff])?;
+        std::io::Write::write_all(&mut other_stream, &corrupted)?;
+        std::io::Write::write_all(&mut other_stream, &crate::framing::encode(b"hello")?)?;
+
+        let mut buf = [0; 16];
+        let (n, address) = transport.recv_from(&mut buf)?;
+        assert_eq!(&buf[0..n], b"hello");
+        assert_eq!(address, Address::Link("noisy".to_string()));
+
+        let err = transport.recv_from(&mut buf).unwrap_err();
+        assert!(crate::transport::is_timeout(&err));
+
+        // and the other way
+        transport.send_to(b"world", &address)?;
+
+        let mut decoder = crate::framing::Decoder::new();
+        let mut read_buf = [0; 64];
+        let n = std::io::Read::read(&mut other_stream, &mut read_buf)?;
+        decoder.push(&read_buf[0..n]);
+        assert_eq!(decoder.next_frame(), Some(Ok(b"world".to_vec())));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,304 @@
+use crate::transport::{Address, Transport};
+
+// how long to wait between polls of the listener / connections while waiting for a frame
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(1);
+
+fn configure(stream: &std::net::TcpStream) -> Result<(), std::io::Error> {
+    stream.set_nodelay(true)?;
+    stream.set_nonblocking(true)?;
+
+    Ok(())
+}
+
+fn write_frame(mut stream: &std::net::TcpStream, buf: &[u8]) -> Result<(), std::io::Error> {
+    let frame = crate::framing::encode(buf)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+
+    std::io::Write::write_all(&mut stream, &frame)
+}
+
+struct Connection {
+    address: std::net::SocketAddr,
+    stream: std::net::TcpStream,
+    decoder: crate::framing::Decoder,
+}
+
+// accepts any number of connections; datagrams are framed with crate::framing
+pub struct TcpServerTransport {
+    listener: std::net::TcpListener,
+    local_address: Address,
+    connections: std::sync::Mutex<Vec<Connection>>,
+    // round-robin, so that one busy connection can't starve the others
+    next_connection: std::sync::atomic::AtomicUsize,
+}
+
+impl TcpServerTransport {
+    pub fn new(bind_address: std::net::SocketAddr) -> Result<Self, std::io::Error> {
+        let listener = std::net::TcpListener::bind(bind_address)?;
+        listener.set_nonblocking(true)?;
+
+        Ok(Self {
+            local_address: Address::Socket(listener.local_addr()?),
+            listener,
+            connections: std::sync::Mutex::new(vec![]),
+            next_connection: std::sync::atomic::AtomicUsize::new(0),
+        })
+    }
+
+    fn accept(&self) {
+        loop {
+            let (stream, address) = match self.listener.accept() {
+                Ok(accepted) => accepted,
+                Err(err) if crate::transport::is_timeout(&err) => return,
+                Err(err) => {
+                    println!("accept() failed because err={:?}", err);
+                    return;
+                }
+            };
+
+            if let Err(err) = configure(&stream) {
+                println!(
+                    "dropping connection from address={:?} because err={:?}",
+                    address, err
+                );
+                continue;
+            }
+
+            let mut connections = self.connections.lock().unwrap();
+            connections.push(Connection {
+                address,
+                stream,
+                decoder: crate::framing::Decoder::new(),
+            });
+        }
+    }
+
+    fn try_recv_from(&self, buf: &mut [u8]) -> Option<(usize, Address)> {
+        let mut connections = self.connections.lock().unwrap();
+        if connections.is_empty() {
+            return None;
+        }
+
+        let start = self
+            .next_connection
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+
+        let mut received = None;
+        let mut closed = vec![];
+
+        for i in 0..connections.len() {
+            let i = (start + i) % connections.len();
+            let connection = &mut connections[i];
+            let address = Address::Socket(connection.address);
+
+            match crate::transport::stream::read_frame(
+                &mut connection.stream,
+                &mut connection.decoder,
+                &address,
+                buf,
+            ) {
+                Ok(n) => {
+                    received = Some((n, address));
+                    break;
+                }
+                Err(err) if crate::transport::is_timeout(&err) => {}
+                Err(err) => {
+                    if err.kind() != std::io::ErrorKind::UnexpectedEof {
+                        println!(
+                            "dropping connection from address={} because err={:?}",
+                            address, err
+                        );
+                    }
+                    closed.push(i);
+                }
+            }
+        }
+
+        closed.sort_unstable();
+        for i in closed.into_iter().rev() {
+            connections.remove(i);
+        }
+
+        received
+    }
+}
+
+impl Transport for TcpServerTransport {
+    fn send_to(&self, buf: &[u8], address: &Address) -> Result<(), std::io::Error> {
+        let connections = self.connections.lock().unwrap();
+
+        let connection = connections
+            .iter()
+            .find(|connection| Address::Socket(connection.address) == *address)
+            .ok_or(std::io::Error::from(std::io::ErrorKind::NotConnected))?;
+
+        write_frame(&connection.stream, buf)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, Address), std::io::Error> {
+        let deadline = std::time::Instant::now() + crate::transport::READ_TIMEOUT;
+
+        loop {
+            self.accept();
+
+            if let Some(received) = self.try_recv_from(buf) {
+                return Ok(received);
+            }
+
+            if std::time::Instant::now() >= deadline {
+                return Err(std::io::ErrorKind::WouldBlock.into());
+            }
+
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn get_local_address(&self) -> Address {
+        self.local_address.clone()
+    }
+}
+
+// (re)connects on demand, so that it can be started before the Server
+pub struct TcpClientTransport {
+    send_address: std::net::SocketAddr,
+    writer: std::sync::Mutex<Option<std::net::TcpStream>>,
+    reader: std::sync::Mutex<Option<(std::net::TcpStream, crate::framing::Decoder)>>,
+}
+
+impl TcpClientTransport {
+    pub fn new(send_address: std::net::SocketAddr) -> Self {
+        Self {
+            send_address,
+            writer: std::sync::Mutex::new(None),
+            reader: std::sync::Mutex::new(None),
+        }
+    }
+
+    fn connect(&self) -> Result<std::net::TcpStream, std::io::Error> {
+        let stream = std::net::TcpStream::connect_timeout(
+            &self.send_address,
+            crate::transport::WRITE_TIMEOUT,
+        )?;
+        configure(&stream)?;
+
+        *self.reader.lock().unwrap() = Some((stream.try_clone()?, crate::framing::Decoder::new()));
+
+        Ok(stream)
+    }
+}
+
+impl Transport for TcpClientTransport {
+    fn send_to(&self, buf: &[u8], address: &Address) -> Result<(), std::io::Error> {
+        if *address != Address::Socket(self.send_address) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("not connected to address={}", address),
+            ));
+        }
+
+        let mut writer = self.writer.lock().unwrap();
+
+        if writer.is_none() {
+            *writer = Some(self.connect()?);
+        }
+
+        let result = write_frame(writer.as_ref().unwrap(), buf);
+        if result.is_err() {
+            // try again with a new connection next time
+            *writer = None;
+        }
+
+        result
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, Address), std::io::Error> {
+        let address = Address::Socket(self.send_address);
+        let deadline = std::time::Instant::now() + crate::transport::READ_TIMEOUT;
+
+        loop {
+            let mut reader = self.reader.lock().unwrap();
+
+            if let Some((stream, decoder)) = reader.as_mut() {
+                match crate::transport::stream::read_frame(stream, decoder, &address, buf) {
+                    Ok(n) => return Ok((n, address)),
+                    Err(err) if crate::transport::is_timeout(&err) => {}
+                    Err(err) => {
+                        println!(
+                            "lost connection to address={} because err={:?}",
+                            address, err
+                        );
+
+                        *reader = None;
+                        drop(reader);
+
+                        // reconnect on the next send
+                        *self.writer.lock().unwrap() = None;
+
+                        return Err(std::io::ErrorKind::WouldBlock.into());
+                    }
+                }
+            }
+
+            drop(reader);
+
+            if std::time::Instant::now() >= deadline {
+                return Err(std::io::ErrorKind::WouldBlock.into());
+            }
+
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn get_local_address(&self) -> Address {
+        let writer = self.writer.lock().unwrap();
+
+        writer
+            .as_ref()
+            .and_then(|stream| stream.local_addr().ok())
+            .map(Address::Socket)
+            .unwrap_or(Address::Socket(std::net::SocketAddr::from((
+                [0, 0, 0, 0],
+                0,
+            ))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() -> anyhow::Result<()> {
+        let server_transport = TcpServerTransport::new("127.0.0.1:0".parse()?)?;
+        let client_transport = TcpClientTransport::new(
+            server_transport
+                .get_local_address()
+                .get_socket_address()
+                .unwrap(),
+        );
+
+        crate::transport::tests::assert_round_trip(
+            Box::new(server_transport),
+            Box::new(client_transport),
+        )
+    }
+
+    #[test]
+    fn no_server() -> anyhow::Result<()> {
+        // grab a port that nothing is listening on
+        let send_address = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+
+        let client_transport = TcpClientTransport::new(send_address);
+
+        let err = client_transport
+            .send_to(b"hello", &send_address.into())
+            .unwrap_err();
+        assert!(crate::transport::is_disconnected(&err));
+
+        let mut buf = [0; 16];
+        let err = client_transport.recv_from(&mut buf).unwrap_err();
+        assert!(crate::transport::is_timeout(&err));
+
+        Ok(())
+    }
+}
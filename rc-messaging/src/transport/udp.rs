@@ -0,0 +1,44 @@
+use crate::transport::{Address, Transport};
+
+pub struct UdpTransport {
+    socket: std::net::UdpSocket,
+    local_address: Address,
+}
+
+impl UdpTransport {
+    pub fn new(bind_address: std::net::SocketAddr) -> Result<Self, std::io::Error> {
+        let socket = std::net::UdpSocket::bind(bind_address)?;
+        socket.set_read_timeout(Some(crate::transport::READ_TIMEOUT))?;
+        socket.set_write_timeout(Some(crate::transport::WRITE_TIMEOUT))?;
+
+        Ok(Self {
+            local_address: Address::Socket(socket.local_addr()?),
+            socket,
+        })
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send_to(&self, buf: &[u8], address: &Address) -> Result<(), std::io::Error> {
+        let Address::Socket(address) = address else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("can't send a udp datagram to address={}", address),
+            ));
+        };
+
+        self.socket.send_to(buf, address)?;
+
+        Ok(())
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, Address), std::io::Error> {
+        let (n, address) = self.socket.recv_from(buf)?;
+
+        Ok((n, Address::Socket(address)))
+    }
+
+    fn get_local_address(&self) -> Address {
+        self.local_address.clone()
+    }
+}
@@ -11,3 +11,7 @@ trap finish EXIT
 
 echo -e "\nrunning natuve tests...\n"
 cargo test --lib -- --nocapture
+
+echo -e "\nchecking the no_std core builds and round trips...\n"
+cargo build --lib --no-default-features
+cargo test --no-default-features --test no_std
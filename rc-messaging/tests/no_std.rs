@@ -0,0 +1,29 @@
+// built against the library as configured, so with --no-default-features (see test.sh) this
+// checks that the no_std core can encode and decode with its defaults
+
+use rc_messaging::envelope::{decode, encode, Message};
+use rc_messaging::serialization::InputMessage;
+
+#[test]
+fn encode_and_decode_with_default_codec() {
+    let message = Message::Input(InputMessage {
+        throttle: 0.5,
+        steering: -0.25,
+        throttle_left: 0.0,
+        throttle_right: 0.0,
+        steering_left: 0.0,
+        steering_right: 0.0,
+        mode_up: false,
+        mode_down: true,
+        mode_left: false,
+        mode_right: false,
+        handbrake: false,
+    });
+
+    let buf = encode(1337, 69, &message).unwrap();
+
+    let envelope = decode(&buf).unwrap();
+    assert_eq!(envelope.codec, rc_messaging::codec::Codec::default());
+    assert_eq!(envelope.sequence, Some(1337));
+    assert_eq!(envelope.message, message);
+}
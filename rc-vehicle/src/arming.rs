@@ -0,0 +1,269 @@
+// like a flight controller, a Vehicle starts disarmed and ignores InputMessages (passing on only
+// its safe InputMessage) until it's armed; holding the handbrake with every stick centred arms it,
+// but only the failsafe going on for long enough (or disarm) disarms it, as holding the handbrake
+// at a stop is something a driver does anyway
+
+// how far from 0.0 an axis can be and still count as centred (i.e. allowing for stick drift)
+const NEUTRAL_THRESHOLD: f32 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArmingState {
+    Disarmed,
+    Armed,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArmingPolicy {
+    // how long the gesture has to be held for
+    pub arm_hold: std::time::Duration,
+    // how long the failsafe can go on before the Vehicle disarms itself (None for never)
+    pub disarm_after: Option<std::time::Duration>,
+}
+
+impl Default for ArmingPolicy {
+    fn default() -> Self {
+        Self {
+            arm_hold: std::time::Duration::from_secs(1),
+            disarm_after: Some(std::time::Duration::from_secs(10)),
+        }
+    }
+}
+
+pub fn is_neutral(input_message: &rc_messaging::serialization::InputMessage) -> bool {
+    [
+        input_message.throttle,
+        input_message.steering,
+        input_message.throttle_left,
+        input_message.throttle_right,
+        input_message.steering_left,
+        input_message.steering_right,
+    ]
+    .iter()
+    .all(|value| value.abs() <= NEUTRAL_THRESHOLD)
+}
+
+#[derive(Debug, Clone)]
+pub struct Arming {
+    policy: ArmingPolicy,
+    state: ArmingState,
+    gesture_started_at: Option<std::time::Instant>,
+    // the gesture has to be let go of before it'll arm again (e.g. after the failsafe disarms)
+    gesture_released: bool,
+}
+
+impl Default for Arming {
+    fn default() -> Self {
+        Self::new(ArmingPolicy::default())
+    }
+}
+
+impl Arming {
+    pub fn new(policy: ArmingPolicy) -> Self {
+        Self {
+            policy,
+            state: ArmingState::Disarmed,
+            gesture_started_at: None,
+            gesture_released: true,
+        }
+    }
+
+    pub fn get_policy(&self) -> &ArmingPolicy {
+        &self.policy
+    }
+
+    pub fn get_state(&self) -> ArmingState {
+        self.state
+    }
+
+    pub fn is_armed(&self) -> bool {
+        self.state == ArmingState::Armed
+    }
+
+    pub fn disarm(&mut self) {
+        if self.state == ArmingState::Armed {
+            println!("disarming");
+        }
+
+        self.state = ArmingState::Disarmed;
+        self.gesture_started_at = None;
+    }
+
+    // for each InputMessage received; returns the state it leaves the Vehicle in
+    pub fn update(
+        &mut self,
+        now: std::time::Instant,
+        input_message: &rc_messaging::serialization::InputMessage,
+    ) -> ArmingState {
+        if !input_message.handbrake {
+            self.gesture_started_at = None;
+            self.gesture_released = true;
+            return self.state;
+        }
+
+        if self.state == ArmingState::Armed || !self.gesture_released || !is_neutral(input_message)
+        {
+            self.gesture_started_at = None;
+            return self.state;
+        }
+
+        let gesture_started_at = *self.gesture_started_at.get_or_insert(now);
+        if now.saturating_duration_since(gesture_started_at) < self.policy.arm_hold {
+            return self.state;
+        }
+
+        println!("arming");
+        self.state = ArmingState::Armed;
+        self.gesture_started_at = None;
+        self.gesture_released = false;
+
+        self.state
+    }
+
+    // for each time the failsafe acts, with how long it has been going on
+    pub fn update_failsafe(&mut self, failsafe_duration: std::time::Duration) {
+        if self
+            .policy
+            .disarm_after
+            .is_some_and(|disarm_after| failsafe_duration >= disarm_after)
+        {
+            self.disarm();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> std::time::Duration {
+        std::time::Duration::from_millis(ms)
+    }
+
+    fn get_input_message(
+        throttle: f32,
+        handbrake: bool,
+    ) -> rc_messaging::serialization::InputMessage {
+        rc_messaging::serialization::InputMessage {
+            throttle,
+            steering: 0.0,
+            throttle_left: 0.0,
+            throttle_right: 0.0,
+            steering_left: 0.0,
+            steering_right: 0.0,
+            mode_up: false,
+            mode_down: false,
+            mode_left: false,
+            mode_right: false,
+            handbrake,
+        }
+    }
+
+    #[test]
+    fn arm() {
+        let mut arming = Arming::default();
+        assert_eq!(arming.get_state(), ArmingState::Disarmed);
+
+        let now = std::time::Instant::now();
+
+        // not held for long enough
+        assert_eq!(
+            arming.update(now, &get_input_message(0.0, true)),
+            ArmingState::Disarmed
+        );
+        assert_eq!(
+            arming.update(now + ms(999), &get_input_message(0.0, true)),
+            ArmingState::Disarmed
+        );
+        assert_eq!(
+            arming.update(now + ms(1000), &get_input_message(0.0, false)),
+            ArmingState::Disarmed
+        );
+
+        // held for long enough
+        assert_eq!(
+            arming.update(now + ms(2000), &get_input_message(0.05, true)),
+            ArmingState::Disarmed
+        );
+        assert_eq!(
+            arming.update(now + ms(3000), &get_input_message(0.0, true)),
+            ArmingState::Armed
+        );
+
+        // still holding
+        assert_eq!(
+            arming.update(now + ms(5000), &get_input_message(0.0, true)),
+            ArmingState::Armed
+        );
+    }
+
+    #[test]
+    fn handbrake_at_a_stop_stays_armed() {
+        let mut arming = Arming::default();
+
+        let now = std::time::Instant::now();
+
+        arming.update(now, &get_input_message(0.0, true));
+        arming.update(now + ms(1000), &get_input_message(0.0, true));
+        assert!(arming.is_armed());
+
+        // let go, drive, then stop and hold the handbrake for a good while
+        arming.update(now + ms(1100), &get_input_message(1.0, false));
+        arming.update(now + ms(2000), &get_input_message(0.0, true));
+        assert_eq!(
+            arming.update(now + ms(10000), &get_input_message(0.0, true)),
+            ArmingState::Armed
+        );
+
+        // only disarm does it
+        arming.disarm();
+        assert!(!arming.is_armed());
+
+        // and the handbrake has to be let go of before it'll arm again
+        assert_eq!(
+            arming.update(now + ms(11000), &get_input_message(0.0, true)),
+            ArmingState::Disarmed
+        );
+    }
+
+    #[test]
+    fn not_neutral() {
+        let mut arming = Arming::default();
+
+        let now = std::time::Instant::now();
+
+        arming.update(now, &get_input_message(0.5, true));
+        assert_eq!(
+            arming.update(now + ms(2000), &get_input_message(0.5, true)),
+            ArmingState::Disarmed
+        );
+
+        // the stick moving restarts the gesture
+        arming.update(now + ms(3000), &get_input_message(0.0, true));
+        arming.update(now + ms(3500), &get_input_message(-0.5, true));
+        assert_eq!(
+            arming.update(now + ms(4000), &get_input_message(0.0, true)),
+            ArmingState::Disarmed
+        );
+        assert_eq!(
+            arming.update(now + ms(5000), &get_input_message(0.0, true)),
+            ArmingState::Armed
+        );
+    }
+
+    #[test]
+    fn disarm_after_failsafe() {
+        let mut arming = Arming::new(ArmingPolicy {
+            arm_hold: std::time::Duration::ZERO,
+            disarm_after: Some(ms(1000)),
+        });
+
+        arming.update(std::time::Instant::now(), &get_input_message(0.0, true));
+        assert!(arming.is_armed());
+
+        arming.update_failsafe(ms(999));
+        assert!(arming.is_armed());
+
+        arming.update_failsafe(ms(1000));
+        assert!(!arming.is_armed());
+    }
+}
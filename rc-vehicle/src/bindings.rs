@@ -0,0 +1,183 @@
+// what the mode buttons do to a Vehicle, as a table of Bindings so that each vehicle can have its
+// own controls; by default up / down raise / lower the speed limit and left / right trim the steering
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Button {
+    ModeUp,
+    ModeDown,
+    ModeLeft,
+    ModeRight,
+}
+
+impl Button {
+    pub fn is_pressed(self, input_message: &rc_messaging::serialization::InputMessage) -> bool {
+        match self {
+            Button::ModeUp => input_message.mode_up,
+            Button::ModeDown => input_message.mode_down,
+            Button::ModeLeft => input_message.mode_left,
+            Button::ModeRight => input_message.mode_right,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    // once, as the button goes down
+    Press,
+    // on every InputMessage for as long as the button is down
+    Hold,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    // widens both throttle_min and throttle_max by the step (so negative to lower the limit)
+    ChangeSpeedLimit(f32),
+    // added to the steering_offset (so negative to trim left)
+    Trim(f32),
+    // passed on to the InputMessageHandler, for vehicles that have lights
+    ToggleLights,
+    // to the next of the Vehicle's crate::drive_mode::DriveModes (for vehicles that have them)
+    CycleDriveMode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Binding {
+    pub button: Button,
+    pub trigger: Trigger,
+    pub action: Action,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bindings {
+    bindings: Vec<Binding>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Self::new(vec![
+            Binding {
+                button: Button::ModeUp,
+                trigger: Trigger::Press,
+                action: Action::ChangeSpeedLimit(0.10),
+            },
+            Binding {
+                button: Button::ModeDown,
+                trigger: Trigger::Press,
+                action: Action::ChangeSpeedLimit(-0.10),
+            },
+            Binding {
+                button: Button::ModeLeft,
+                trigger: Trigger::Hold,
+                action: Action::Trim(-0.01),
+            },
+            Binding {
+                button: Button::ModeRight,
+                trigger: Trigger::Hold,
+                action: Action::Trim(0.01),
+            },
+        ])
+    }
+}
+
+impl Bindings {
+    // a button can be bound more than once; its Actions happen in the order given
+    pub fn new(bindings: Vec<Binding>) -> Self {
+        Self { bindings }
+    }
+
+    // what the InputMessage sets off, given the one before it (None counting as no buttons down)
+    pub fn get_actions(
+        &self,
+        input_message: &rc_messaging::serialization::InputMessage,
+        last_input_message: Option<&rc_messaging::serialization::InputMessage>,
+    ) -> Vec<Action> {
+        self.bindings
+            .iter()
+            .filter(|binding| {
+                if !binding.button.is_pressed(input_message) {
+                    return false;
+                }
+
+                match binding.trigger {
+                    Trigger::Press => !last_input_message.is_some_and(|last_input_message| {
+                        binding.button.is_pressed(last_input_message)
+                    }),
+                    Trigger::Hold => true,
+                }
+            })
+            .map(|binding| binding.action)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_input_message(
+        mode_up: bool,
+        mode_left: bool,
+    ) -> rc_messaging::serialization::InputMessage {
+        let mut input_message = crate::failsafe::get_safe_input_message();
+        input_message.mode_up = mode_up;
+        input_message.mode_left = mode_left;
+        input_message
+    }
+
+    #[test]
+    fn get_actions() {
+        let bindings = Bindings::default();
+
+        let released = get_input_message(false, false);
+        let pressed = get_input_message(true, true);
+
+        assert_eq!(bindings.get_actions(&released, None), vec![]);
+
+        // a press only counts as the button goes down, but a hold keeps on going
+        assert_eq!(
+            bindings.get_actions(&pressed, None),
+            vec![Action::ChangeSpeedLimit(0.10), Action::Trim(-0.01)]
+        );
+        assert_eq!(
+            bindings.get_actions(&pressed, Some(&released)),
+            vec![Action::ChangeSpeedLimit(0.10), Action::Trim(-0.01)]
+        );
+        assert_eq!(
+            bindings.get_actions(&pressed, Some(&pressed)),
+            vec![Action::Trim(-0.01)]
+        );
+        assert_eq!(bindings.get_actions(&released, Some(&pressed)), vec![]);
+    }
+
+    #[test]
+    fn custom_bindings() {
+        let bindings = Bindings::new(vec![
+            Binding {
+                button: Button::ModeLeft,
+                trigger: Trigger::Press,
+                action: Action::ToggleLights,
+            },
+            Binding {
+                button: Button::ModeLeft,
+                trigger: Trigger::Press,
+                action: Action::Trim(0.05),
+            },
+        ]);
+
+        // unbound buttons do nothing
+        assert_eq!(
+            bindings.get_actions(&get_input_message(true, false), None),
+            vec![]
+        );
+
+        assert_eq!(
+            bindings.get_actions(&get_input_message(false, true), None),
+            vec![Action::ToggleLights, Action::Trim(0.05)]
+        );
+
+        assert_eq!(
+            Bindings::new(vec![]).get_actions(&get_input_message(true, true), None),
+            vec![]
+        );
+    }
+}
@@ -0,0 +1,341 @@
+// named profiles (e.g. beginner / sport / crawl) so that the same vehicle can be handed to anyone;
+// each has its own throttle cap, expo curves, steering rate and acceleration limit, applied after
+// the Vehicle's own InputShaper and before its throttle limits
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DriveMode {
+    pub name: String,
+    // 0.0 to 1.0 of whatever the throttle limits allow
+    pub throttle_cap: f32,
+    // see crate::shaping::Expo
+    pub throttle_expo: f32,
+    pub steering_expo: f32,
+    // how far the steering can move per second (None for as fast as the servo can)
+    pub steering_rate: Option<f32>,
+    // how far the throttle can open up per second (None for no limit); letting off is never held up
+    pub acceleration: Option<f32>,
+}
+
+impl DriveMode {
+    pub fn get_input_shaper(&self) -> crate::shaping::InputShaper {
+        let mut input_shaper = crate::shaping::InputShaper::new();
+
+        for axis in [
+            crate::shaping::Axis::Throttle,
+            crate::shaping::Axis::ThrottleLeft,
+            crate::shaping::Axis::ThrottleRight,
+        ] {
+            let mut stages: Vec<Box<dyn crate::shaping::Stage>> = vec![
+                Box::new(crate::shaping::Expo(self.throttle_expo)),
+                Box::new(crate::shaping::Scale(self.throttle_cap)),
+            ];
+            if let Some(acceleration) = self.acceleration {
+                stages.push(Box::new(crate::shaping::AccelerationLimit::new(
+                    acceleration,
+                )));
+            }
+
+            input_shaper.set_pipeline(axis, crate::shaping::Pipeline::new(stages));
+        }
+
+        let mut stages: Vec<Box<dyn crate::shaping::Stage>> =
+            vec![Box::new(crate::shaping::Expo(self.steering_expo))];
+        if let Some(steering_rate) = self.steering_rate {
+            stages.push(Box::new(crate::shaping::Slew::new(steering_rate)));
+        }
+
+        input_shaper.set_pipeline(
+            crate::shaping::Axis::Steering,
+            crate::shaping::Pipeline::new(stages),
+        );
+
+        input_shaper
+    }
+}
+
+// gentle all round, for the kids
+pub fn get_beginner_drive_mode() -> DriveMode {
+    DriveMode {
+        name: "beginner".to_string(),
+        throttle_cap: 0.4,
+        throttle_expo: 0.5,
+        steering_expo: 0.5,
+        steering_rate: Some(2.0),
+        acceleration: Some(1.0),
+    }
+}
+
+// everything the throttle limits allow, as it comes
+pub fn get_sport_drive_mode() -> DriveMode {
+    DriveMode {
+        name: "sport".to_string(),
+        throttle_cap: 1.0,
+        throttle_expo: 0.0,
+        steering_expo: 0.2,
+        steering_rate: None,
+        acceleration: None,
+    }
+}
+
+// slow and fine around the bottom of the throttle, for picking a way over things
+pub fn get_crawl_drive_mode() -> DriveMode {
+    DriveMode {
+        name: "crawl".to_string(),
+        throttle_cap: 0.25,
+        throttle_expo: 0.7,
+        steering_expo: 0.0,
+        steering_rate: None,
+        acceleration: Some(0.5),
+    }
+}
+
+pub fn get_default_drive_modes() -> Vec<DriveMode> {
+    vec![
+        get_beginner_drive_mode(),
+        get_sport_drive_mode(),
+        get_crawl_drive_mode(),
+    ]
+}
+
+// the DriveModes a Vehicle can be in and which one it's in; it starts in the first one, so that
+// should be the gentlest
+pub struct DriveModes {
+    drive_modes: Vec<DriveMode>,
+    index: usize,
+    input_shaper: crate::shaping::InputShaper,
+    // what last came out of the input_shaper and when, for the next one to carry on from
+    last_shaped: Option<(
+        rc_messaging::serialization::InputMessage,
+        std::time::Instant,
+    )>,
+}
+
+impl DriveModes {
+    pub fn new(drive_modes: Vec<DriveMode>) -> Self {
+        assert!(!drive_modes.is_empty());
+
+        let input_shaper = drive_modes[0].get_input_shaper();
+
+        Self {
+            drive_modes,
+            index: 0,
+            input_shaper,
+            last_shaped: None,
+        }
+    }
+
+    pub fn get_drive_mode(&self) -> &DriveMode {
+        &self.drive_modes[self.index]
+    }
+
+    // the new one's Stages carry on from where the old one's got to (so switching while driving
+    // doesn't snap everything back to neutral)
+    fn select(&mut self, index: usize) {
+        self.index = index;
+        self.input_shaper = self.get_drive_mode().get_input_shaper();
+        if let Some((input_message, at)) = &self.last_shaped {
+            self.input_shaper.resume(input_message, *at);
+        }
+
+        println!("drive_mode={:?}", self.get_drive_mode());
+    }
+
+    // to the next one along (wrapping around)
+    pub fn cycle(&mut self) {
+        self.select((self.index + 1) % self.drive_modes.len());
+    }
+
+    pub fn set(&mut self, name: &str) -> anyhow::Result<()> {
+        let Some(index) = self
+            .drive_modes
+            .iter()
+            .position(|drive_mode| drive_mode.name == name)
+        else {
+            anyhow::bail!("no drive mode with name={:?}", name);
+        };
+
+        self.select(index);
+
+        Ok(())
+    }
+
+    pub fn handle_drive_mode_message(
+        &mut self,
+        drive_mode_message: rc_messaging::serialization::DriveModeMessage,
+    ) -> anyhow::Result<()> {
+        match drive_mode_message {
+            rc_messaging::serialization::DriveModeMessage::Set(name) => self.set(&name),
+            rc_messaging::serialization::DriveModeMessage::Cycle => {
+                self.cycle();
+                Ok(())
+            }
+        }
+    }
+
+    pub fn shape(
+        &mut self,
+        input_message: &mut rc_messaging::serialization::InputMessage,
+        now: std::time::Instant,
+    ) {
+        self.input_shaper.shape(input_message, now);
+        self.last_shaped = Some((input_message.clone(), now));
+    }
+
+    pub fn reset(&mut self) {
+        self.input_shaper.reset();
+        self.last_shaped = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_name(drive_modes: &DriveModes) -> String {
+        drive_modes.get_drive_mode().name.clone()
+    }
+
+    #[test]
+    fn drive_modes() -> anyhow::Result<()> {
+        let mut drive_modes = DriveModes::new(get_default_drive_modes());
+        assert_eq!(get_name(&drive_modes), "beginner");
+
+        drive_modes.cycle();
+        assert_eq!(get_name(&drive_modes), "sport");
+        drive_modes.cycle();
+        drive_modes.cycle();
+        assert_eq!(get_name(&drive_modes), "beginner");
+
+        drive_modes.handle_drive_mode_message(
+            rc_messaging::serialization::DriveModeMessage::Set("crawl".to_string()),
+        )?;
+        assert_eq!(get_name(&drive_modes), "crawl");
+
+        // an unknown one changes nothing
+        assert!(drive_modes
+            .handle_drive_mode_message(rc_messaging::serialization::DriveModeMessage::Set(
+                "ludicrous".to_string()
+            ))
+            .is_err());
+        assert_eq!(get_name(&drive_modes), "crawl");
+
+        drive_modes
+            .handle_drive_mode_message(rc_messaging::serialization::DriveModeMessage::Cycle)?;
+        assert_eq!(get_name(&drive_modes), "beginner");
+
+        Ok(())
+    }
+
+    #[test]
+    fn shape() {
+        let mut drive_modes = DriveModes::new(vec![
+            DriveMode {
+                name: "capped".to_string(),
+                throttle_cap: 0.5,
+                throttle_expo: 1.0,
+                steering_expo: 0.0,
+                steering_rate: Some(1.0),
+                acceleration: None,
+            },
+            get_sport_drive_mode(),
+        ]);
+
+        let now = std::time::Instant::now();
+
+        let mut input_message = crate::failsafe::get_safe_input_message();
+        input_message.throttle = 0.5;
+        input_message.throttle_left = -1.0;
+        input_message.steering = 1.0;
+
+        let mut shaped_input_message = input_message.clone();
+        drive_modes.shape(&mut shaped_input_message, now);
+        assert_eq!(shaped_input_message.throttle, 0.0625);
+        assert_eq!(shaped_input_message.throttle_left, -0.5);
+        assert_eq!(shaped_input_message.steering, 0.0);
+
+        let mut shaped_input_message = input_message.clone();
+        drive_modes.shape(
+            &mut shaped_input_message,
+            now + std::time::Duration::from_millis(500),
+        );
+        assert_eq!(shaped_input_message.steering, 0.5);
+
+        // nothing held up in sport, and only a little expo
+        drive_modes.cycle();
+        input_message.steering = 0.5;
+        let mut shaped_input_message = input_message.clone();
+        drive_modes.shape(
+            &mut shaped_input_message,
+            now + std::time::Duration::from_millis(600),
+        );
+        assert_eq!(shaped_input_message.throttle, 0.5);
+        assert_eq!(shaped_input_message.throttle_left, -1.0);
+        assert!((shaped_input_message.steering - 0.425).abs() < 0.0001);
+    }
+
+    #[test]
+    fn switch_while_driving() {
+        let mut drive_modes = DriveModes::new(vec![
+            DriveMode {
+                name: "unlimited".to_string(),
+                throttle_cap: 1.0,
+                throttle_expo: 0.0,
+                steering_expo: 0.0,
+                steering_rate: None,
+                acceleration: None,
+            },
+            DriveMode {
+                name: "limited".to_string(),
+                throttle_cap: 1.0,
+                throttle_expo: 0.0,
+                steering_expo: 0.0,
+                steering_rate: Some(1.0),
+                acceleration: Some(1.0),
+            },
+        ]);
+
+        let now = std::time::Instant::now();
+
+        let mut input_message = crate::failsafe::get_safe_input_message();
+        input_message.throttle = 0.5;
+        input_message.steering = 0.5;
+
+        let mut shaped_input_message = input_message.clone();
+        drive_modes.shape(&mut shaped_input_message, now);
+        assert_eq!(shaped_input_message.throttle, 0.5);
+        assert_eq!(shaped_input_message.steering, 0.5);
+
+        // carries on from where unlimited got to rather than from neutral
+        drive_modes.cycle();
+        let mut shaped_input_message = input_message.clone();
+        drive_modes.shape(
+            &mut shaped_input_message,
+            now + std::time::Duration::from_millis(100),
+        );
+        assert_eq!(shaped_input_message.throttle, 0.5);
+        assert_eq!(shaped_input_message.steering, 0.5);
+
+        // and is still held to its own rates from there
+        input_message.throttle = 1.0;
+        input_message.steering = -0.5;
+        let mut shaped_input_message = input_message.clone();
+        drive_modes.shape(
+            &mut shaped_input_message,
+            now + std::time::Duration::from_millis(200),
+        );
+        assert!((shaped_input_message.throttle - 0.6).abs() < 0.0001);
+        assert!((shaped_input_message.steering - 0.4).abs() < 0.0001);
+
+        // but after the failsafe the next one starts from neutral again
+        drive_modes.reset();
+        drive_modes.cycle();
+        drive_modes.cycle();
+        let mut shaped_input_message = input_message.clone();
+        drive_modes.shape(
+            &mut shaped_input_message,
+            now + std::time::Duration::from_millis(300),
+        );
+        assert_eq!(shaped_input_message.throttle, 0.0);
+        assert_eq!(shaped_input_message.steering, 0.0);
+    }
+}
@@ -0,0 +1,208 @@
+// what a Vehicle does when InputMessages stop arriving; once none has arrived for the timeout, the
+// failsafe starts and works through its stages (e.g. hold -> brake -> neutral), each starting its
+// given time after the failsafe did and lasting until the next one starts (or InputMessages return)
+
+const DEFAULT_TIMEOUT_HZ: f64 = 5.0;
+pub const DEFAULT_TIMEOUT: std::time::Duration =
+    std::time::Duration::from_millis((1.0 / DEFAULT_TIMEOUT_HZ * 1000.0) as u64);
+
+pub fn get_safe_input_message() -> rc_messaging::serialization::InputMessage {
+    rc_messaging::serialization::InputMessage {
+        throttle: 0.0,
+        steering: 0.0,
+        throttle_left: 0.0,
+        throttle_right: 0.0,
+        steering_left: 0.0,
+        steering_right: 0.0,
+        mode_up: false,
+        mode_down: false,
+        mode_left: false,
+        mode_right: false,
+        handbrake: true,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FailsafeAction {
+    // keep doing whatever the last InputMessage said (e.g. to ride out a short dropout)
+    Hold,
+    Send(rc_messaging::serialization::InputMessage),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailsafeStage {
+    // since the failsafe started
+    pub after: std::time::Duration,
+    pub action: FailsafeAction,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailsafePolicy {
+    timeout: std::time::Duration,
+    stages: Vec<FailsafeStage>,
+}
+
+impl Default for FailsafePolicy {
+    // stop everything as soon as InputMessages stop
+    fn default() -> Self {
+        Self::new_immediate(DEFAULT_TIMEOUT, get_safe_input_message())
+    }
+}
+
+impl FailsafePolicy {
+    // the first stage must start straight away and the last must Send something, as it's what's
+    // left in place when the Vehicle stops
+    pub fn new(timeout: std::time::Duration, stages: Vec<FailsafeStage>) -> Self {
+        assert!(!timeout.is_zero());
+        assert!(stages.first().is_some_and(|stage| stage.after.is_zero()));
+        assert!(stages.windows(2).all(|pair| pair[0].after < pair[1].after));
+        assert!(stages
+            .last()
+            .is_some_and(|stage| matches!(stage.action, FailsafeAction::Send(_))));
+
+        Self { timeout, stages }
+    }
+
+    pub fn new_immediate(
+        timeout: std::time::Duration,
+        safe_input_message: rc_messaging::serialization::InputMessage,
+    ) -> Self {
+        Self::new(
+            timeout,
+            vec![FailsafeStage {
+                after: std::time::Duration::ZERO,
+                action: FailsafeAction::Send(safe_input_message),
+            }],
+        )
+    }
+
+    pub fn get_timeout(&self) -> std::time::Duration {
+        self.timeout
+    }
+
+    pub fn get_stages(&self) -> &[FailsafeStage] {
+        &self.stages
+    }
+
+    // which stage the failsafe is in, given how long it's been since the last InputMessage
+    pub fn get_stage_index(&self, since_last_input_message: std::time::Duration) -> Option<usize> {
+        let since_started = since_last_input_message.checked_sub(self.timeout)?;
+
+        self.stages
+            .iter()
+            .rposition(|stage| stage.after <= since_started)
+    }
+
+    pub fn get_input_message(
+        &self,
+        since_last_input_message: std::time::Duration,
+        last_input_message: &rc_messaging::serialization::InputMessage,
+    ) -> Option<rc_messaging::serialization::InputMessage> {
+        let stage_index = self.get_stage_index(since_last_input_message)?;
+
+        match &self.stages[stage_index].action {
+            FailsafeAction::Hold => Some(last_input_message.clone()),
+            FailsafeAction::Send(input_message) => Some(input_message.clone()),
+        }
+    }
+
+    // for when the Vehicle stops (or before it's been sent anything at all)
+    pub fn get_final_input_message(&self) -> &rc_messaging::serialization::InputMessage {
+        match &self.stages.last().unwrap().action {
+            FailsafeAction::Send(input_message) => input_message,
+            FailsafeAction::Hold => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> std::time::Duration {
+        std::time::Duration::from_millis(ms)
+    }
+
+    fn get_input_message(throttle: f32) -> rc_messaging::serialization::InputMessage {
+        let mut input_message = get_safe_input_message();
+        input_message.throttle = throttle;
+        input_message.handbrake = false;
+        input_message
+    }
+
+    // hold -> brake -> neutral
+    fn get_failsafe_policy() -> FailsafePolicy {
+        FailsafePolicy::new(
+            ms(100),
+            vec![
+                FailsafeStage {
+                    after: ms(0),
+                    action: FailsafeAction::Hold,
+                },
+                FailsafeStage {
+                    after: ms(500),
+                    action: FailsafeAction::Send(get_input_message(-0.5)),
+                },
+                FailsafeStage {
+                    after: ms(1000),
+                    action: FailsafeAction::Send(get_input_message(0.0)),
+                },
+            ],
+        )
+    }
+
+    #[test]
+    fn stages() {
+        let failsafe_policy = get_failsafe_policy();
+        let last_input_message = get_input_message(0.69);
+
+        for (since_last_input_message, expected_stage_index, expected_throttle) in [
+            (ms(50), None, None),
+            (ms(100), Some(0), Some(0.69)),
+            (ms(599), Some(0), Some(0.69)),
+            (ms(600), Some(1), Some(-0.5)),
+            (ms(1099), Some(1), Some(-0.5)),
+            (ms(1100), Some(2), Some(0.0)),
+            (ms(60_000), Some(2), Some(0.0)),
+        ] {
+            assert_eq!(
+                failsafe_policy.get_stage_index(since_last_input_message),
+                expected_stage_index
+            );
+            assert_eq!(
+                failsafe_policy
+                    .get_input_message(since_last_input_message, &last_input_message)
+                    .map(|input_message| input_message.throttle),
+                expected_throttle
+            );
+        }
+
+        assert_eq!(
+            *failsafe_policy.get_final_input_message(),
+            get_input_message(0.0)
+        );
+    }
+
+    #[test]
+    fn default() {
+        let failsafe_policy = FailsafePolicy::default();
+
+        assert_eq!(failsafe_policy.get_timeout(), DEFAULT_TIMEOUT);
+        assert_eq!(
+            failsafe_policy.get_input_message(DEFAULT_TIMEOUT, &get_input_message(1.0)),
+            Some(get_safe_input_message())
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn must_end_safe() {
+        FailsafePolicy::new(
+            ms(100),
+            vec![FailsafeStage {
+                after: ms(0),
+                action: FailsafeAction::Hold,
+            }],
+        );
+    }
+}
@@ -1,4 +1,11 @@
+pub mod arming;
+pub mod bindings;
+pub mod drive_mode;
 pub mod drone;
+pub mod failsafe;
+pub mod mixing;
 pub mod pwm;
+pub mod settings;
+pub mod shaping;
 pub mod string;
 pub mod vehicle;
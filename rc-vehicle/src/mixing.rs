@@ -0,0 +1,183 @@
+// converting between the ways of driving in an InputMessage, so that a vehicle can be driven with
+// whichever the controller gives; throttle + steering (a single stick, arcade style) into
+// throttle_left / throttle_right for tanks, or the other way around for cars
+
+pub trait Mixer {
+    fn mix(&self, input_message: &mut rc_messaging::serialization::InputMessage);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PivotTurn {
+    // the inside track goes backwards, so with no throttle it turns on the spot
+    Spin,
+    // the inside track only ever slows to a stop, so with no throttle it turns around that
+    Skid,
+}
+
+// steering turns the same way whichever way the throttle is (like a tank, not like a car reversing)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ArcadeToTank {
+    // how much of the steering goes into the mix with no throttle (i.e. how fast it pivots; 0.0 for
+    // not at all) and at full throttle, in a straight line between the two
+    pub pivot_rate: f32,
+    pub steering_rate: f32,
+    pub pivot_turn: PivotTurn,
+}
+
+impl Default for ArcadeToTank {
+    fn default() -> Self {
+        Self {
+            pivot_rate: 1.0,
+            steering_rate: 0.5,
+            pivot_turn: PivotTurn::Spin,
+        }
+    }
+}
+
+impl Mixer for ArcadeToTank {
+    fn mix(&self, input_message: &mut rc_messaging::serialization::InputMessage) {
+        let throttle = input_message.throttle;
+
+        let rate = self.pivot_rate + (self.steering_rate - self.pivot_rate) * throttle.abs();
+        let steering = input_message.steering * rate;
+
+        let mut throttle_left = throttle + steering;
+        let mut throttle_right = throttle - steering;
+
+        // scaled back together (rather than clipped) so the turn isn't lost at full throttle
+        let largest = throttle_left.abs().max(throttle_right.abs()).max(1.0);
+        throttle_left /= largest;
+        throttle_right /= largest;
+
+        if self.pivot_turn == PivotTurn::Skid {
+            if throttle >= 0.0 {
+                throttle_left = throttle_left.max(0.0);
+                throttle_right = throttle_right.max(0.0);
+            } else {
+                throttle_left = throttle_left.min(0.0);
+                throttle_right = throttle_right.min(0.0);
+            }
+        }
+
+        input_message.throttle_left = throttle_left;
+        input_message.throttle_right = throttle_right;
+    }
+}
+
+// the throttle is the average of the tracks and the steering is how far apart they are
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TankToAckermann {
+    // how much of the difference goes into the steering (clamped to -1.0 / 1.0)
+    pub steering_rate: f32,
+}
+
+impl Default for TankToAckermann {
+    fn default() -> Self {
+        Self { steering_rate: 1.0 }
+    }
+}
+
+impl Mixer for TankToAckermann {
+    fn mix(&self, input_message: &mut rc_messaging::serialization::InputMessage) {
+        let throttle_left = input_message.throttle_left;
+        let throttle_right = input_message.throttle_right;
+
+        input_message.throttle = (throttle_left + throttle_right) / 2.0;
+        input_message.steering =
+            ((throttle_left - throttle_right) / 2.0 * self.steering_rate).clamp(-1.0, 1.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: (f32, f32), expected: (f32, f32)) {
+        assert!(
+            (actual.0 - expected.0).abs() < 0.0001 && (actual.1 - expected.1).abs() < 0.0001,
+            "actual={:?}, expected={:?}",
+            actual,
+            expected
+        );
+    }
+
+    fn mix_arcade_to_tank(mixer: &ArcadeToTank, throttle: f32, steering: f32) -> (f32, f32) {
+        let mut input_message = crate::failsafe::get_safe_input_message();
+        input_message.throttle = throttle;
+        input_message.steering = steering;
+
+        mixer.mix(&mut input_message);
+
+        (input_message.throttle_left, input_message.throttle_right)
+    }
+
+    fn mix_tank_to_ackermann(
+        mixer: &TankToAckermann,
+        throttle_left: f32,
+        throttle_right: f32,
+    ) -> (f32, f32) {
+        let mut input_message = crate::failsafe::get_safe_input_message();
+        input_message.throttle_left = throttle_left;
+        input_message.throttle_right = throttle_right;
+
+        mixer.mix(&mut input_message);
+
+        (input_message.throttle, input_message.steering)
+    }
+
+    #[test]
+    fn arcade_to_tank_spin() {
+        let mixer = ArcadeToTank::default();
+
+        assert_close(mix_arcade_to_tank(&mixer, 0.0, 0.0), (0.0, 0.0));
+        assert_close(mix_arcade_to_tank(&mixer, 1.0, 0.0), (1.0, 1.0));
+        assert_close(mix_arcade_to_tank(&mixer, -0.5, 0.0), (-0.5, -0.5));
+
+        // on the spot
+        assert_close(mix_arcade_to_tank(&mixer, 0.0, 1.0), (1.0, -1.0));
+        assert_close(mix_arcade_to_tank(&mixer, 0.0, -0.5), (-0.5, 0.5));
+
+        // less and less steering the faster it goes, but the outside track never beyond 1.0
+        assert_close(mix_arcade_to_tank(&mixer, 0.5, 1.0), (1.0, -0.2));
+        assert_close(mix_arcade_to_tank(&mixer, 1.0, 1.0), (1.0, 0.3333));
+        assert_close(mix_arcade_to_tank(&mixer, -1.0, 1.0), (-0.3333, -1.0));
+    }
+
+    #[test]
+    fn arcade_to_tank_skid() {
+        let mixer = ArcadeToTank {
+            pivot_turn: PivotTurn::Skid,
+            ..Default::default()
+        };
+
+        // around the inside track
+        assert_close(mix_arcade_to_tank(&mixer, 0.0, 1.0), (1.0, 0.0));
+        assert_close(mix_arcade_to_tank(&mixer, 0.0, -1.0), (0.0, 1.0));
+        assert_close(mix_arcade_to_tank(&mixer, 0.5, 1.0), (1.0, 0.0));
+        assert_close(mix_arcade_to_tank(&mixer, -0.5, 1.0), (0.0, -1.0));
+        assert_close(mix_arcade_to_tank(&mixer, 1.0, 1.0), (1.0, 0.3333));
+
+        // or not at all
+        let mixer = ArcadeToTank {
+            pivot_rate: 0.0,
+            ..mixer
+        };
+        assert_close(mix_arcade_to_tank(&mixer, 0.0, 1.0), (0.0, 0.0));
+        assert_close(mix_arcade_to_tank(&mixer, 0.5, 1.0), (0.75, 0.25));
+    }
+
+    #[test]
+    fn tank_to_ackermann() {
+        let mixer = TankToAckermann::default();
+
+        assert_close(mix_tank_to_ackermann(&mixer, 0.0, 0.0), (0.0, 0.0));
+        assert_close(mix_tank_to_ackermann(&mixer, 1.0, 1.0), (1.0, 0.0));
+        assert_close(mix_tank_to_ackermann(&mixer, -1.0, -1.0), (-1.0, 0.0));
+        assert_close(mix_tank_to_ackermann(&mixer, 1.0, 0.0), (0.5, 0.5));
+        assert_close(mix_tank_to_ackermann(&mixer, 1.0, -1.0), (0.0, 1.0));
+
+        let mixer = TankToAckermann { steering_rate: 2.0 };
+        assert_close(mix_tank_to_ackermann(&mixer, 0.5, 0.0), (0.25, 0.5));
+        assert_close(mix_tank_to_ackermann(&mixer, -1.0, 1.0), (0.0, -1.0));
+    }
+}
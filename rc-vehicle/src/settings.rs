@@ -0,0 +1,512 @@
+// a Vehicle's tuning (as adjusted with the mode buttons) kept somewhere that survives a restart;
+// stores are simple key / value ones so that they map onto things like NVS on an ESP32
+
+pub trait SettingsStore {
+    fn get(&self, key: &str) -> anyhow::Result<Option<f32>>;
+
+    // as one write, so that saving a Vehicle's settings wears flash once (rather than once per
+    // setting) and never leaves a mix of old and new
+    fn set_all(&mut self, values: &[(&str, f32)]) -> anyhow::Result<()>;
+
+    fn set(&mut self, key: &str, value: f32) -> anyhow::Result<()> {
+        self.set_all(&[(key, value)])
+    }
+
+    // everything in the store replaced with the given values, for when what's there can't be read
+    // (and so would stop set_all from ever working again)
+    fn replace_all(&mut self, values: &[(&str, f32)]) -> anyhow::Result<()>;
+}
+
+// short enough for NVS (which allows at most 15 characters)
+const THROTTLE_MIN_KEY: &str = "throttle_min";
+const THROTTLE_MAX_KEY: &str = "throttle_max";
+const STEERING_OFFSET_KEY: &str = "steering_offset";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    pub throttle_min: f32,
+    pub throttle_max: f32,
+    pub steering_offset: f32,
+}
+
+impl Settings {
+    // anything that isn't in the store yet (or isn't a number) is left as it is, and anything
+    // out of range is clamped to the range the mode buttons keep it in; if the store can't be
+    // read then nothing changes
+    pub fn load(&mut self, settings_store: &dyn SettingsStore) -> anyhow::Result<()> {
+        let mut settings = *self;
+
+        for (key, value, min, max) in [
+            (THROTTLE_MIN_KEY, &mut settings.throttle_min, -1.0, 0.0),
+            (THROTTLE_MAX_KEY, &mut settings.throttle_max, 0.0, 1.0),
+            (
+                STEERING_OFFSET_KEY,
+                &mut settings.steering_offset,
+                -1.0,
+                1.0,
+            ),
+        ] {
+            let Some(stored_value) = settings_store.get(key)? else {
+                continue;
+            };
+
+            if !stored_value.is_finite() {
+                println!(
+                    "ignoring key={:?} because stored_value={:?}; carrying on with value={:?}",
+                    key, stored_value, value
+                );
+                continue;
+            }
+
+            *value = stored_value.clamp(min, max);
+        }
+
+        *self = settings;
+
+        Ok(())
+    }
+
+    fn get_values(&self) -> [(&'static str, f32); 3] {
+        [
+            (THROTTLE_MIN_KEY, self.throttle_min),
+            (THROTTLE_MAX_KEY, self.throttle_max),
+            (STEERING_OFFSET_KEY, self.steering_offset),
+        ]
+    }
+
+    pub fn save(&self, settings_store: &mut dyn SettingsStore) -> anyhow::Result<()> {
+        settings_store.set_all(&self.get_values())
+    }
+
+    // for a store that couldn't be loaded; whatever was in it is lost (or put aside, for a
+    // FileSettingsStore) and these are saved in its place
+    pub fn replace(&self, settings_store: &mut dyn SettingsStore) -> anyhow::Result<()> {
+        settings_store.replace_all(&self.get_values())
+    }
+}
+
+// key=value lines (blank ones and # comments are ignored), which is what's in a
+// FileSettingsStore's file and a BlobSettingsStore's blob
+fn parse(contents: &str) -> anyhow::Result<std::collections::BTreeMap<String, f32>> {
+    let mut values = std::collections::BTreeMap::new();
+
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            anyhow::bail!("failed to parse line={:?}", line);
+        };
+
+        let value = value.trim().parse::<f32>().map_err(|err| {
+            anyhow::anyhow!("failed to parse line={:?} because err={:?}", line, err)
+        })?;
+
+        values.insert(key.trim().to_string(), value);
+    }
+
+    Ok(values)
+}
+
+fn format(values: &std::collections::BTreeMap<String, f32>) -> String {
+    values
+        .iter()
+        .map(|(key, value)| format!("{}={}\n", key, value))
+        .collect()
+}
+
+// a text file of key=value lines, rewritten as a whole (via a temporary file, so that it's never
+// left half written) on each set_all; replace_all moves a file that can't be read aside (with a
+// .corrupt extension) for somebody to look at, rather than throwing it away
+pub struct FileSettingsStore {
+    path: std::path::PathBuf,
+}
+
+impl FileSettingsStore {
+    pub fn new(path: impl AsRef<std::path::Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    fn read(&self) -> anyhow::Result<std::collections::BTreeMap<String, f32>> {
+        let contents = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            // nothing saved yet
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        parse(&contents).map_err(|err| {
+            anyhow::anyhow!("failed to read path={:?} because err={:?}", self.path, err)
+        })
+    }
+
+    fn write(&self, values: &std::collections::BTreeMap<String, f32>) -> anyhow::Result<()> {
+        let temporary_path = self.path.with_extension("tmp");
+        std::fs::write(&temporary_path, format(values))?;
+        std::fs::rename(&temporary_path, &self.path)?;
+
+        Ok(())
+    }
+}
+
+impl SettingsStore for FileSettingsStore {
+    fn get(&self, key: &str) -> anyhow::Result<Option<f32>> {
+        Ok(self.read()?.get(key).copied())
+    }
+
+    fn set_all(&mut self, values: &[(&str, f32)]) -> anyhow::Result<()> {
+        let mut stored_values = self.read()?;
+        for (key, value) in values {
+            stored_values.insert(key.to_string(), *value);
+        }
+
+        self.write(&stored_values)
+    }
+
+    fn replace_all(&mut self, values: &[(&str, f32)]) -> anyhow::Result<()> {
+        match std::fs::rename(&self.path, self.path.with_extension("corrupt")) {
+            Ok(()) => {}
+            // nothing to move aside
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        self.write(
+            &values
+                .iter()
+                .map(|(key, value)| (key.to_string(), *value))
+                .collect(),
+        )
+    }
+}
+
+// the size of the buffer a BlobSettingsStore reads into, which is plenty for a few key=value lines
+const BLOB_SIZE: usize = 256;
+
+// for stores of bytes under a key, like NVS on an ESP32 (which has no floats, and where each write
+// wears the flash); everything is kept as key=value lines in the one blob, so that set_all is a
+// single write; get_blob reads the blob into the buffer (returning its length, if there is one)
+// and set_blob replaces it, which keeps this apart from any particular version of esp-idf-svc
+pub struct BlobSettingsStore<T> {
+    store: T,
+    key: &'static str,
+    get_blob: fn(&T, &str, &mut [u8]) -> anyhow::Result<Option<usize>>,
+    set_blob: fn(&mut T, &str, &[u8]) -> anyhow::Result<()>,
+}
+
+impl<T> BlobSettingsStore<T> {
+    pub fn new(
+        store: T,
+        key: &'static str,
+        get_blob: fn(&T, &str, &mut [u8]) -> anyhow::Result<Option<usize>>,
+        set_blob: fn(&mut T, &str, &[u8]) -> anyhow::Result<()>,
+    ) -> Self {
+        Self {
+            store,
+            key,
+            get_blob,
+            set_blob,
+        }
+    }
+
+    fn read(&self) -> anyhow::Result<std::collections::BTreeMap<String, f32>> {
+        let mut buf = [0; BLOB_SIZE];
+
+        // nothing saved yet
+        let Some(n) = (self.get_blob)(&self.store, self.key, &mut buf)? else {
+            return Ok(std::collections::BTreeMap::new());
+        };
+
+        let contents = std::str::from_utf8(&buf[0..n])?;
+
+        parse(contents).map_err(|err| {
+            anyhow::anyhow!("failed to read key={:?} because err={:?}", self.key, err)
+        })
+    }
+}
+
+impl<T> SettingsStore for BlobSettingsStore<T> {
+    fn get(&self, key: &str) -> anyhow::Result<Option<f32>> {
+        Ok(self.read()?.get(key).copied())
+    }
+
+    fn set_all(&mut self, values: &[(&str, f32)]) -> anyhow::Result<()> {
+        let mut stored_values = self.read()?;
+        for (key, value) in values {
+            stored_values.insert(key.to_string(), *value);
+        }
+
+        let contents = format(&stored_values);
+        if contents.len() > BLOB_SIZE {
+            anyhow::bail!(
+                "failed to write key={:?} because it'd be {} bytes (more than {})",
+                self.key,
+                contents.len(),
+                BLOB_SIZE
+            );
+        }
+
+        (self.set_blob)(&mut self.store, self.key, contents.as_bytes())
+    }
+
+    fn replace_all(&mut self, values: &[(&str, f32)]) -> anyhow::Result<()> {
+        let contents = format(
+            &values
+                .iter()
+                .map(|(key, value)| (key.to_string(), *value))
+                .collect(),
+        );
+
+        (self.set_blob)(&mut self.store, self.key, contents.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!(
+            "rc-vehicle-settings-{}-{}.txt",
+            name,
+            std::process::id()
+        ))
+    }
+
+    fn get_settings() -> Settings {
+        Settings {
+            throttle_min: -0.2,
+            throttle_max: 0.2,
+            steering_offset: 0.0,
+        }
+    }
+
+    #[test]
+    fn file_settings_store() -> anyhow::Result<()> {
+        let path = get_path("file");
+        _ = std::fs::remove_file(&path);
+
+        let mut settings_store = FileSettingsStore::new(&path);
+
+        // nothing there yet
+        assert_eq!(settings_store.get("throttle_max")?, None);
+
+        settings_store.set("throttle_max", 0.3)?;
+        settings_store.set("steering_offset", -0.01)?;
+        settings_store.set("throttle_max", 0.4)?;
+
+        assert_eq!(settings_store.get("throttle_max")?, Some(0.4));
+        assert_eq!(settings_store.get("steering_offset")?, Some(-0.01));
+        assert_eq!(settings_store.get("throttle_min")?, None);
+
+        // as plain as it gets, so it can be edited by hand
+        assert_eq!(
+            std::fs::read_to_string(&path)?,
+            "steering_offset=-0.01\nthrottle_max=0.4\n"
+        );
+        assert!(!path.with_extension("tmp").exists());
+
+        // and read back by the next one along (e.g. after a restart)
+        std::fs::write(
+            &path,
+            "# tuned by hand\n\nthrottle_max = 0.5\nsteering_offset=-0.01\n",
+        )?;
+        let settings_store = FileSettingsStore::new(&path);
+        assert_eq!(settings_store.get("throttle_max")?, Some(0.5));
+
+        std::fs::remove_file(&path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn file_settings_store_corrupted() -> anyhow::Result<()> {
+        let path = get_path("corrupted");
+
+        for contents in ["throttle_max", "throttle_max=fast"] {
+            std::fs::write(&path, contents)?;
+
+            let mut settings_store = FileSettingsStore::new(&path);
+            assert!(settings_store.get("throttle_max").is_err());
+
+            // rather than throwing away whatever else was there
+            assert!(settings_store.set("throttle_max", 0.5).is_err());
+            assert_eq!(std::fs::read_to_string(&path)?, contents);
+
+            // until it's replaced, which keeps it to one side
+            settings_store.replace_all(&[("throttle_max", 0.5)])?;
+            assert_eq!(
+                std::fs::read_to_string(path.with_extension("corrupt"))?,
+                contents
+            );
+            assert_eq!(std::fs::read_to_string(&path)?, "throttle_max=0.5\n");
+
+            // and everything carries on from there
+            settings_store.set("steering_offset", -0.01)?;
+            assert_eq!(
+                std::fs::read_to_string(&path)?,
+                "steering_offset=-0.01\nthrottle_max=0.5\n"
+            );
+        }
+
+        std::fs::remove_file(&path)?;
+        std::fs::remove_file(path.with_extension("corrupt"))?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn load_out_of_range() -> anyhow::Result<()> {
+        let path = get_path("out-of-range");
+        std::fs::write(
+            &path,
+            "throttle_min=-5.0\nthrottle_max=NaN\nsteering_offset=inf\n",
+        )?;
+
+        // clamped, or left as it was if it isn't a number at all
+        let mut settings = get_settings();
+        settings.load(&FileSettingsStore::new(&path))?;
+        assert_eq!(
+            settings,
+            Settings {
+                throttle_min: -1.0,
+                ..get_settings()
+            }
+        );
+
+        std::fs::write(&path, "throttle_min=0.5\nthrottle_max=-inf\n")?;
+        settings.load(&FileSettingsStore::new(&path))?;
+        assert_eq!(
+            settings,
+            Settings {
+                throttle_min: 0.0,
+                ..get_settings()
+            }
+        );
+
+        std::fs::remove_file(&path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn load_corrupted() -> anyhow::Result<()> {
+        let path = get_path("load-corrupted");
+
+        // none of it is taken, even the lines that are fine
+        std::fs::write(&path, "throttle_max=0.5\nsteering_offset=left\n")?;
+
+        let mut settings = get_settings();
+        assert!(settings.load(&FileSettingsStore::new(&path)).is_err());
+        assert_eq!(settings, get_settings());
+
+        std::fs::remove_file(&path)?;
+
+        Ok(())
+    }
+
+    #[derive(Default)]
+    struct TestBlobs {
+        blobs: std::collections::HashMap<String, Vec<u8>>,
+        writes: usize,
+    }
+
+    fn get_blob_settings_store() -> BlobSettingsStore<TestBlobs> {
+        BlobSettingsStore::new(
+            TestBlobs::default(),
+            "settings",
+            |test_blobs, key, buf| {
+                let Some(blob) = test_blobs.blobs.get(key) else {
+                    return Ok(None);
+                };
+
+                buf[0..blob.len()].copy_from_slice(blob);
+                Ok(Some(blob.len()))
+            },
+            |test_blobs, key, blob| {
+                test_blobs.blobs.insert(key.to_string(), blob.to_vec());
+                test_blobs.writes += 1;
+                Ok(())
+            },
+        )
+    }
+
+    #[test]
+    fn blob_settings_store() -> anyhow::Result<()> {
+        let mut settings_store = get_blob_settings_store();
+
+        // nothing there yet
+        assert_eq!(settings_store.get("throttle_max")?, None);
+
+        let mut settings = get_settings();
+        settings.load(&settings_store)?;
+        assert_eq!(settings, get_settings());
+
+        // all of it in the one write
+        settings.steering_offset = -0.01;
+        settings.save(&mut settings_store)?;
+        assert_eq!(settings_store.store.writes, 1);
+        assert_eq!(
+            settings_store.store.blobs.get("settings"),
+            Some(&b"steering_offset=-0.01\nthrottle_max=0.2\nthrottle_min=-0.2\n".to_vec())
+        );
+
+        let mut loaded_settings = get_settings();
+        loaded_settings.steering_offset = 0.5;
+        loaded_settings.load(&settings_store)?;
+        assert_eq!(loaded_settings, settings);
+
+        // and it's just as careful about what it's given
+        settings_store
+            .store
+            .blobs
+            .insert("settings".to_string(), vec![0xff, 0xfe]);
+        assert!(settings_store.get("throttle_max").is_err());
+        assert!(settings.save(&mut settings_store).is_err());
+
+        // until it's replaced
+        settings.replace(&mut settings_store)?;
+        loaded_settings.load(&settings_store)?;
+        assert_eq!(loaded_settings, settings);
+        settings.save(&mut settings_store)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn load_and_save() -> anyhow::Result<()> {
+        let path = get_path("load-and-save");
+        _ = std::fs::remove_file(&path);
+
+        let mut settings_store = FileSettingsStore::new(&path);
+
+        // the defaults stand until something's been saved
+        let mut settings = get_settings();
+        settings.load(&settings_store)?;
+        assert_eq!(settings, get_settings());
+
+        settings_store.set("steering_offset", 0.02)?;
+        settings.load(&settings_store)?;
+        assert_eq!(
+            settings,
+            Settings {
+                steering_offset: 0.02,
+                ..get_settings()
+            }
+        );
+
+        settings.throttle_max = 0.1;
+        settings.save(&mut settings_store)?;
+
+        let mut loaded_settings = get_settings();
+        loaded_settings.load(&FileSettingsStore::new(&path))?;
+        assert_eq!(loaded_settings, settings);
+
+        std::fs::remove_file(&path)?;
+
+        Ok(())
+    }
+}
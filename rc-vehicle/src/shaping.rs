@@ -0,0 +1,634 @@
+// each axis of an InputMessage can be run through its own Pipeline of Stages (deadzone, expo, slew,
+// trim, clamp, invert and so on) between it being received and it being handled
+
+pub trait Stage {
+    fn apply(&mut self, value: f32, now: std::time::Instant) -> f32;
+
+    // for stages that remember earlier values; called when the failsafe (or being disarmed) has
+    // put everything back to neutral behind the Pipeline's back
+    fn reset(&mut self) {}
+
+    // for stages that remember earlier values; carry on as if the value had last come out at the
+    // time given (e.g. when a new Pipeline takes over from one that was part way through)
+    fn resume(&mut self, _value: f32, _at: std::time::Instant) {}
+}
+
+// anything within the width of the middle is 0.0 and the rest is stretched to still reach -1.0 / 1.0
+pub struct Deadzone(pub f32);
+
+impl Stage for Deadzone {
+    fn apply(&mut self, value: f32, _now: std::time::Instant) -> f32 {
+        if value.abs() <= self.0 {
+            return 0.0;
+        }
+
+        value.signum() * (value.abs() - self.0) / (1.0 - self.0)
+    }
+}
+
+// from 0.0 (linear) to 1.0 (cubic); finer control around the middle at the cost of the ends
+pub struct Expo(pub f32);
+
+impl Stage for Expo {
+    fn apply(&mut self, value: f32, _now: std::time::Instant) -> f32 {
+        (1.0 - self.0) * value + self.0 * value.powi(3)
+    }
+}
+
+// how far the value can move per second (e.g. 4.0 takes at least 250ms to go from 0.0 to 1.0)
+pub struct Slew {
+    rate: f32,
+    last_value: f32,
+    last_at: Option<std::time::Instant>,
+}
+
+impl Slew {
+    pub fn new(rate: f32) -> Self {
+        assert!(rate > 0.0);
+
+        Self {
+            rate,
+            last_value: 0.0,
+            last_at: None,
+        }
+    }
+}
+
+impl Stage for Slew {
+    fn apply(&mut self, value: f32, now: std::time::Instant) -> f32 {
+        // starts from neutral
+        let elapsed = self
+            .last_at
+            .map(|last_at| now.saturating_duration_since(last_at))
+            .unwrap_or_default();
+
+        let step = self.rate * elapsed.as_secs_f32();
+
+        self.last_value = value.clamp(self.last_value - step, self.last_value + step);
+        self.last_at = Some(now);
+
+        self.last_value
+    }
+
+    fn reset(&mut self) {
+        self.last_value = 0.0;
+        self.last_at = None;
+    }
+
+    fn resume(&mut self, value: f32, at: std::time::Instant) {
+        self.last_value = value;
+        self.last_at = Some(at);
+    }
+}
+
+// like Slew, but only away from neutral; speeding up is held to the rate while slowing down (and
+// so braking) happens straight away
+pub struct AccelerationLimit {
+    rate: f32,
+    last_value: f32,
+    last_at: Option<std::time::Instant>,
+}
+
+impl AccelerationLimit {
+    pub fn new(rate: f32) -> Self {
+        assert!(rate > 0.0);
+
+        Self {
+            rate,
+            last_value: 0.0,
+            last_at: None,
+        }
+    }
+}
+
+impl Stage for AccelerationLimit {
+    fn apply(&mut self, value: f32, now: std::time::Instant) -> f32 {
+        // starts from neutral
+        let elapsed = self
+            .last_at
+            .map(|last_at| now.saturating_duration_since(last_at))
+            .unwrap_or_default();
+
+        let step = self.rate * elapsed.as_secs_f32();
+
+        // the other way counts as from neutral
+        let last_value = if value * self.last_value < 0.0 {
+            0.0
+        } else {
+            self.last_value
+        };
+
+        self.last_value = if value.abs() <= last_value.abs() {
+            value
+        } else {
+            value.signum() * value.abs().min(last_value.abs() + step)
+        };
+        self.last_at = Some(now);
+
+        self.last_value
+    }
+
+    fn reset(&mut self) {
+        self.last_value = 0.0;
+        self.last_at = None;
+    }
+
+    fn resume(&mut self, value: f32, at: std::time::Instant) {
+        self.last_value = value;
+        self.last_at = Some(at);
+    }
+}
+
+pub struct Trim(pub f32);
+
+impl Stage for Trim {
+    fn apply(&mut self, value: f32, _now: std::time::Instant) -> f32 {
+        value + self.0
+    }
+}
+
+// the steering offset from the mode buttons; anything between centre and the offset is brought out
+// to the offset (so centre steers a little that way) and everything else is left as it is
+pub struct Offset(pub f32);
+
+impl Stage for Offset {
+    fn apply(&mut self, value: f32, _now: std::time::Instant) -> f32 {
+        let offset = self.0;
+
+        if (offset > 0.0 && (0.0..offset).contains(&value))
+            || (offset < 0.0 && value <= 0.0 && value > offset)
+        {
+            return offset;
+        }
+
+        value
+    }
+}
+
+pub struct Scale(pub f32);
+
+impl Stage for Scale {
+    fn apply(&mut self, value: f32, _now: std::time::Instant) -> f32 {
+        value * self.0
+    }
+}
+
+// forward (positive) by the first and reverse (negative) by the second
+pub struct AsymmetricScale(pub f32, pub f32);
+
+impl Stage for AsymmetricScale {
+    fn apply(&mut self, value: f32, _now: std::time::Instant) -> f32 {
+        if value >= 0.0 {
+            value * self.0
+        } else {
+            value * self.1
+        }
+    }
+}
+
+pub struct Clamp(pub f32, pub f32);
+
+impl Stage for Clamp {
+    fn apply(&mut self, value: f32, _now: std::time::Instant) -> f32 {
+        value.clamp(self.0, self.1)
+    }
+}
+
+pub struct Invert;
+
+impl Stage for Invert {
+    fn apply(&mut self, value: f32, _now: std::time::Instant) -> f32 {
+        -value
+    }
+}
+
+// how long a hobby ESC needs to see neutral between braking and reversing
+pub const REVERSE_NEUTRAL_TIME: std::time::Duration = std::time::Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ReversePhase {
+    // the next reverse will be taken as a brake
+    Forward,
+    Braking(std::time::Instant),
+    Neutral(std::time::Instant),
+    // free to reverse until the next forward
+    Ready,
+}
+
+// hobby ESCs take the first reverse after going forward as a brake and only reverse once they've
+// seen neutral again; this does brake (for the delay) -> neutral -> reverse for the driver, so that
+// holding reverse is all it takes
+pub struct BrakeBeforeReverse {
+    delay: std::time::Duration,
+    phase: ReversePhase,
+}
+
+impl BrakeBeforeReverse {
+    pub fn new(delay: std::time::Duration) -> Self {
+        Self {
+            delay,
+            phase: ReversePhase::Ready,
+        }
+    }
+}
+
+impl Stage for BrakeBeforeReverse {
+    fn apply(&mut self, value: f32, now: std::time::Instant) -> f32 {
+        if value > 0.0 {
+            self.phase = ReversePhase::Forward;
+            return value;
+        }
+
+        if value == 0.0 {
+            // letting go after braking does the ESC's neutral for it
+            if let ReversePhase::Braking(_) = self.phase {
+                self.phase = ReversePhase::Neutral(now);
+            }
+            return value;
+        }
+
+        match self.phase {
+            ReversePhase::Forward => {
+                self.phase = ReversePhase::Braking(now);
+                value
+            }
+            ReversePhase::Braking(started_at) => {
+                if now.saturating_duration_since(started_at) < self.delay {
+                    return value;
+                }
+
+                self.phase = ReversePhase::Neutral(now);
+                0.0
+            }
+            ReversePhase::Neutral(started_at) => {
+                if now.saturating_duration_since(started_at) < REVERSE_NEUTRAL_TIME {
+                    return 0.0;
+                }
+
+                self.phase = ReversePhase::Ready;
+                value
+            }
+            ReversePhase::Ready => value,
+        }
+    }
+
+    // the failsafe has stopped it, so there's nothing left to brake from
+    fn reset(&mut self) {
+        self.phase = ReversePhase::Ready;
+    }
+}
+
+// Stages are applied in the order given
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn Stage>>,
+}
+
+impl Pipeline {
+    pub fn new(stages: Vec<Box<dyn Stage>>) -> Self {
+        Self { stages }
+    }
+
+    pub fn apply(&mut self, value: f32, now: std::time::Instant) -> f32 {
+        self.stages
+            .iter_mut()
+            .fold(value, |value, stage| stage.apply(value, now))
+    }
+
+    pub fn reset(&mut self) {
+        for stage in self.stages.iter_mut() {
+            stage.reset();
+        }
+    }
+
+    // every Stage is given the Pipeline's output, so only the last stage to remember earlier values
+    // is resumed exactly
+    pub fn resume(&mut self, value: f32, at: std::time::Instant) {
+        for stage in self.stages.iter_mut() {
+            stage.resume(value, at);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Axis {
+    Throttle,
+    Steering,
+    ThrottleLeft,
+    ThrottleRight,
+    SteeringLeft,
+    SteeringRight,
+}
+
+impl Axis {
+    pub fn get_value_mut(
+        self,
+        input_message: &mut rc_messaging::serialization::InputMessage,
+    ) -> &mut f32 {
+        match self {
+            Axis::Throttle => &mut input_message.throttle,
+            Axis::Steering => &mut input_message.steering,
+            Axis::ThrottleLeft => &mut input_message.throttle_left,
+            Axis::ThrottleRight => &mut input_message.throttle_right,
+            Axis::SteeringLeft => &mut input_message.steering_left,
+            Axis::SteeringRight => &mut input_message.steering_right,
+        }
+    }
+}
+
+// axes without a Pipeline are passed through as they are
+#[derive(Default)]
+pub struct InputShaper {
+    pipelines: std::collections::HashMap<Axis, Pipeline>,
+}
+
+impl InputShaper {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_pipeline(&mut self, axis: Axis, pipeline: Pipeline) {
+        self.pipelines.insert(axis, pipeline);
+    }
+
+    pub fn shape(
+        &mut self,
+        input_message: &mut rc_messaging::serialization::InputMessage,
+        now: std::time::Instant,
+    ) {
+        for (axis, pipeline) in self.pipelines.iter_mut() {
+            let value = axis.get_value_mut(input_message);
+            *value = pipeline.apply(*value, now);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for pipeline in self.pipelines.values_mut() {
+            pipeline.reset();
+        }
+    }
+
+    // carry on from an InputMessage that was last shaped at the time given
+    pub fn resume(
+        &mut self,
+        input_message: &rc_messaging::serialization::InputMessage,
+        at: std::time::Instant,
+    ) {
+        let mut input_message = input_message.clone();
+        for (axis, pipeline) in self.pipelines.iter_mut() {
+            pipeline.resume(*axis.get_value_mut(&mut input_message), at);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(stage: &mut dyn Stage, values: &[f32]) -> Vec<f32> {
+        let now = std::time::Instant::now();
+
+        values
+            .iter()
+            .map(|value| stage.apply(*value, now))
+            .collect()
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (actual_value, expected_value) in actual.iter().zip(expected) {
+            assert!(
+                (actual_value - expected_value).abs() < 0.0001,
+                "actual={:?}, expected={:?}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn deadzone() {
+        assert_close(
+            &apply(
+                &mut Deadzone(0.2),
+                &[-1.0, -0.6, -0.2, 0.0, 0.1, 0.2, 0.6, 1.0],
+            ),
+            &[-1.0, -0.5, 0.0, 0.0, 0.0, 0.0, 0.5, 1.0],
+        );
+    }
+
+    #[test]
+    fn expo() {
+        assert_close(
+            &apply(&mut Expo(0.0), &[-1.0, -0.5, 0.0, 0.5, 1.0]),
+            &[-1.0, -0.5, 0.0, 0.5, 1.0],
+        );
+        assert_close(
+            &apply(&mut Expo(1.0), &[-1.0, -0.5, 0.0, 0.5, 1.0]),
+            &[-1.0, -0.125, 0.0, 0.125, 1.0],
+        );
+        assert_close(
+            &apply(&mut Expo(0.5), &[-1.0, -0.5, 0.0, 0.5, 1.0]),
+            &[-1.0, -0.3125, 0.0, 0.3125, 1.0],
+        );
+    }
+
+    #[test]
+    fn slew() {
+        let mut slew = Slew::new(2.0);
+
+        let now = std::time::Instant::now();
+        let ms = |ms: u64| now + std::time::Duration::from_millis(ms);
+
+        // from neutral, and then no faster than 2.0 per second
+        assert_eq!(slew.apply(1.0, ms(0)), 0.0);
+        assert_close(&[slew.apply(1.0, ms(100))], &[0.2]);
+        assert_close(&[slew.apply(1.0, ms(300))], &[0.6]);
+        assert_close(&[slew.apply(1.0, ms(1000))], &[1.0]);
+
+        // either way
+        assert_close(&[slew.apply(-1.0, ms(1500))], &[0.0]);
+
+        // small changes aren't held up
+        assert_close(&[slew.apply(0.05, ms(1510))], &[0.02]);
+        assert_close(&[slew.apply(0.05, ms(1600))], &[0.05]);
+
+        // back to neutral
+        slew.apply(1.0, ms(3000));
+        slew.reset();
+        assert_eq!(slew.apply(1.0, ms(3100)), 0.0);
+    }
+
+    #[test]
+    fn acceleration_limit() {
+        let mut acceleration_limit = AccelerationLimit::new(2.0);
+
+        let now = std::time::Instant::now();
+        let ms = |ms: u64| now + std::time::Duration::from_millis(ms);
+
+        // from neutral, and then speeding up no faster than 2.0 per second
+        assert_eq!(acceleration_limit.apply(1.0, ms(0)), 0.0);
+        assert_close(&[acceleration_limit.apply(1.0, ms(100))], &[0.2]);
+        assert_close(&[acceleration_limit.apply(1.0, ms(300))], &[0.6]);
+
+        // but slowing down straight away
+        assert_close(&[acceleration_limit.apply(0.1, ms(310))], &[0.1]);
+
+        // and going the other way is from neutral
+        assert_close(&[acceleration_limit.apply(-1.0, ms(410))], &[-0.2]);
+        assert_close(&[acceleration_limit.apply(-1.0, ms(1000))], &[-1.0]);
+        assert_close(&[acceleration_limit.apply(0.0, ms(1010))], &[0.0]);
+
+        acceleration_limit.apply(1.0, ms(3000));
+        acceleration_limit.reset();
+        assert_eq!(acceleration_limit.apply(1.0, ms(3100)), 0.0);
+    }
+
+    #[test]
+    fn trim_scale_clamp_and_invert() {
+        assert_close(
+            &apply(&mut Trim(0.1), &[-1.0, 0.0, 0.95]),
+            &[-0.9, 0.1, 1.05],
+        );
+        assert_close(
+            &apply(&mut Scale(0.5), &[-1.0, 0.0, 0.5]),
+            &[-0.5, 0.0, 0.25],
+        );
+        assert_close(
+            &apply(
+                &mut AsymmetricScale(0.5, 0.25),
+                &[-1.0, -0.5, 0.0, 0.5, 1.0],
+            ),
+            &[-0.25, -0.125, 0.0, 0.25, 0.5],
+        );
+        assert_close(
+            &apply(&mut Clamp(-0.5, 0.8), &[-1.0, 0.0, 1.0]),
+            &[-0.5, 0.0, 0.8],
+        );
+        assert_close(&apply(&mut Invert, &[-1.0, 0.0, 0.5]), &[1.0, 0.0, -0.5]);
+    }
+
+    #[test]
+    fn offset() {
+        assert_close(
+            &apply(&mut Offset(0.1), &[-1.0, -0.05, 0.0, 0.05, 0.1, 0.5, 1.0]),
+            &[-1.0, -0.05, 0.1, 0.1, 0.1, 0.5, 1.0],
+        );
+        assert_close(
+            &apply(&mut Offset(-0.1), &[-1.0, -0.05, 0.0, 0.05, 1.0]),
+            &[-1.0, -0.1, -0.1, 0.05, 1.0],
+        );
+        assert_close(
+            &apply(&mut Offset(0.0), &[-1.0, 0.0, 1.0]),
+            &[-1.0, 0.0, 1.0],
+        );
+    }
+
+    #[test]
+    fn brake_before_reverse() {
+        let mut brake_before_reverse =
+            BrakeBeforeReverse::new(std::time::Duration::from_millis(200));
+
+        let now = std::time::Instant::now();
+        let ms = |ms: u64| now + std::time::Duration::from_millis(ms);
+
+        // nothing to brake from yet
+        assert_eq!(brake_before_reverse.apply(-0.5, ms(0)), -0.5);
+
+        // but after going forward, reverse brakes for the delay, then goes through neutral
+        assert_eq!(brake_before_reverse.apply(1.0, ms(100)), 1.0);
+        assert_eq!(brake_before_reverse.apply(-0.5, ms(200)), -0.5);
+        assert_eq!(brake_before_reverse.apply(-0.5, ms(399)), -0.5);
+        assert_eq!(brake_before_reverse.apply(-0.5, ms(400)), 0.0);
+        assert_eq!(brake_before_reverse.apply(-0.5, ms(449)), 0.0);
+        assert_eq!(brake_before_reverse.apply(-0.5, ms(450)), -0.5);
+        assert_eq!(brake_before_reverse.apply(-1.0, ms(500)), -1.0);
+
+        // and carries on reversing after stopping
+        assert_eq!(brake_before_reverse.apply(0.0, ms(600)), 0.0);
+        assert_eq!(brake_before_reverse.apply(-1.0, ms(700)), -1.0);
+
+        // letting go while braking is as good as the neutral
+        assert_eq!(brake_before_reverse.apply(0.5, ms(800)), 0.5);
+        assert_eq!(brake_before_reverse.apply(-0.5, ms(900)), -0.5);
+        assert_eq!(brake_before_reverse.apply(0.0, ms(950)), 0.0);
+        assert_eq!(brake_before_reverse.apply(-0.5, ms(1000)), -0.5);
+    }
+
+    #[test]
+    fn brake_before_reverse_reset() {
+        let mut brake_before_reverse =
+            BrakeBeforeReverse::new(std::time::Duration::from_millis(200));
+
+        let now = std::time::Instant::now();
+        let ms = |ms: u64| now + std::time::Duration::from_millis(ms);
+
+        assert_eq!(brake_before_reverse.apply(1.0, ms(0)), 1.0);
+        brake_before_reverse.reset();
+        assert_eq!(brake_before_reverse.apply(-0.5, ms(100)), -0.5);
+
+        // and part way through braking
+        assert_eq!(brake_before_reverse.apply(1.0, ms(200)), 1.0);
+        assert_eq!(brake_before_reverse.apply(-0.5, ms(300)), -0.5);
+        assert_eq!(brake_before_reverse.apply(-0.5, ms(500)), 0.0);
+        brake_before_reverse.reset();
+        assert_eq!(brake_before_reverse.apply(-0.5, ms(510)), -0.5);
+    }
+
+    #[test]
+    fn pipeline() {
+        // in order, so the trim is clamped away at the top end
+        let mut pipeline = Pipeline::new(vec![
+            Box::new(Invert),
+            Box::new(Deadzone(0.1)),
+            Box::new(Trim(0.1)),
+            Box::new(Clamp(-1.0, 1.0)),
+        ]);
+
+        let now = std::time::Instant::now();
+        assert_close(&[pipeline.apply(0.05, now)], &[0.1]);
+        assert_close(&[pipeline.apply(-1.0, now)], &[1.0]);
+        assert_close(&[pipeline.apply(1.0, now)], &[-0.9]);
+    }
+
+    #[test]
+    fn input_shaper() {
+        let mut input_shaper = InputShaper::new();
+        input_shaper.set_pipeline(Axis::Steering, Pipeline::new(vec![Box::new(Invert)]));
+        input_shaper.set_pipeline(
+            Axis::ThrottleRight,
+            Pipeline::new(vec![Box::new(Slew::new(1.0))]),
+        );
+
+        let mut input_message = crate::failsafe::get_safe_input_message();
+        input_message.throttle = 0.5;
+        input_message.steering = 0.5;
+        input_message.throttle_right = 0.5;
+
+        let now = std::time::Instant::now();
+
+        let mut shaped_input_message = input_message.clone();
+        input_shaper.shape(&mut shaped_input_message, now);
+
+        // the others are left alone
+        assert_eq!(shaped_input_message.throttle, 0.5);
+        assert_eq!(shaped_input_message.steering, -0.5);
+        assert_eq!(shaped_input_message.throttle_right, 0.0);
+
+        let mut shaped_input_message = input_message.clone();
+        input_shaper.shape(
+            &mut shaped_input_message,
+            now + std::time::Duration::from_millis(250),
+        );
+        assert_eq!(shaped_input_message.throttle_right, 0.25);
+
+        input_shaper.reset();
+
+        let mut shaped_input_message = input_message.clone();
+        input_shaper.shape(
+            &mut shaped_input_message,
+            now + std::time::Duration::from_millis(500),
+        );
+        assert_eq!(shaped_input_message.throttle_right, 0.0);
+    }
+}
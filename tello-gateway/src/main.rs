@@ -21,19 +21,94 @@ fn main() -> anyhow::Result<()> {
     let (incoming_input_message_sender, incoming_input_message_receiver) =
         std::sync::mpsc::channel();
 
+    // UDP unless something else has been asked for (e.g. TRANSPORT=tcp or
+    // TRANSPORT=serial:/dev/ttyUSB0:115200)
+    let transport = rc_messaging::transport::get_server_transport(
+        &std::env::var("TRANSPORT").unwrap_or("udp".to_string()),
+        format!("{}:{}", "0.0.0.0", 13337).parse()?,
+    )?;
+
+    // drive from a recorded session rather than (as well as) the network, optionally sped up
+    // (e.g. REPLAY=session.bin REPLAY_SPEED=2.0)
+    if let Ok(path) = std::env::var("REPLAY") {
+        let mut player = rc_messaging::recording::Player::open(path)?;
+        if let Ok(speed) = std::env::var("REPLAY_SPEED") {
+            player.set_speed(speed.parse()?);
+        }
+
+        let incoming_input_message_sender = incoming_input_message_sender.clone();
+        std::thread::spawn(move || -> anyhow::Result<()> {
+            player.run(&incoming_input_message_sender)?;
+
+            Ok(())
+        });
+    }
+
+    // Server converts datagrams to InputMessages (and TelemetryMessages to datagrams)
+    let mut server = rc_messaging::transport::Server::new_with_transport(
+        transport,
+        incoming_input_message_sender,
+    );
+
+    // keep a copy of the session for replaying later (e.g. RECORD=session.bin)
+    if let Ok(path) = std::env::var("RECORD") {
+        server.set_recorder(rc_messaging::recording::Recorder::create(path)?);
+    }
+
+    // only accept signed datagrams if a key has been configured
+    if let Ok(pre_shared_key) = std::env::var("PRE_SHARED_KEY") {
+        server.set_pre_shared_key(rc_messaging::auth::PreSharedKey::new(
+            pre_shared_key.as_bytes(),
+        ));
+    }
+
+    // what each ControlMessage channel means to us (laid out like an InputMessage)
+    server.set_vehicle_descriptor(rc_messaging::serialization::VehicleDescriptor {
+        kind: rc_messaging::serialization::VehicleKind::Drone,
+        axes: vec![
+            "".to_string(),
+            "".to_string(),
+            "pitch".to_string(),
+            "nick".to_string(),
+            "yaw".to_string(),
+            "roll".to_string(),
+        ],
+        buttons: vec!["take_off".to_string(), "land".to_string()],
+    });
+
+    let outgoing_telemetry_message_sender = server.get_outgoing_telemetry_message_sender();
+
     // run a thread to handle Server
     std::thread::spawn(move || -> anyhow::Result<()> {
-        // Server converts UDP datagrams to InputMessages
-        let server = rc_messaging::transport::Server::new(
-            format!("{}:{}", "0.0.0.0", 13337).parse()?,
-            incoming_input_message_sender,
-        )?;
-
         server.run()?;
 
         Ok(())
     });
 
+    // let controllers on the LAN find us
+    let mut announcer = rc_messaging::discovery::Announcer::new(
+        rc_messaging::serialization::BeaconMessage {
+            name: std::env::var("VEHICLE_NAME").unwrap_or("tello-gateway".to_string()),
+            kind: rc_messaging::serialization::VehicleKind::Drone,
+            protocol_version: rc_messaging::envelope::PROTOCOL_VERSION,
+            control_port: 13337,
+        },
+        rc_messaging::discovery::get_broadcast_address(),
+    )?;
+
+    if let Ok(pre_shared_key) = std::env::var("PRE_SHARED_KEY") {
+        announcer.set_pre_shared_key(rc_messaging::auth::PreSharedKey::new(
+            pre_shared_key.as_bytes(),
+        ));
+    }
+
+    // run a thread to handle Announcer
+    std::thread::spawn(move || -> anyhow::Result<()> {
+        announcer.run()?;
+
+        Ok(())
+    });
+
     // run a thread to handle Tello -> StringCar -> Vehicle
     std::thread::spawn(move || -> anyhow::Result<()> {
         // TelloDrone converts set_throttle_* / set_steering_* calls to Tello API calls
@@ -50,6 +125,35 @@ fn main() -> anyhow::Result<()> {
             1.0,
             0.0,
         );
+        vehicle.set_outgoing_telemetry_message_sender(outgoing_telemetry_message_sender);
+
+        // the starting throttle limits and steering offset only stand until some are saved (e.g.
+        // SETTINGS=/var/lib/tello-gateway.settings)
+        vehicle.set_settings_store(Box::new(rc_vehicle::settings::FileSettingsStore::new(
+            std::env::var("SETTINGS").unwrap_or("tello-gateway.settings".to_string()),
+        )));
+
+        // mode_up / mode_down take off / land (in the Drone), so they mustn't change the speed limit
+        // on the way through too
+        vehicle.set_bindings(rc_vehicle::bindings::Bindings::new(vec![]));
+
+        // if the link goes, hover for a while in case it comes back and then land
+        let hover_input_message = rc_vehicle::failsafe::get_safe_input_message();
+        let mut land_input_message = hover_input_message.clone();
+        land_input_message.mode_down = true;
+        vehicle.set_failsafe_policy(rc_vehicle::failsafe::FailsafePolicy::new(
+            rc_vehicle::failsafe::DEFAULT_TIMEOUT,
+            vec![
+                rc_vehicle::failsafe::FailsafeStage {
+                    after: std::time::Duration::ZERO,
+                    action: rc_vehicle::failsafe::FailsafeAction::Send(hover_input_message),
+                },
+                rc_vehicle::failsafe::FailsafeStage {
+                    after: std::time::Duration::from_secs(5),
+                    action: rc_vehicle::failsafe::FailsafeAction::Send(land_input_message),
+                },
+            ],
+        ));
 
         vehicle.run()?;
 